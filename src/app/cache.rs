@@ -0,0 +1,291 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use mongodb::{
+    Collection, IndexModel,
+    bson::{DateTime, doc},
+    options::IndexOptions,
+};
+use serde::{Deserialize, Serialize};
+
+/// A TMDB response kept in the Mongo tier of the cache.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedResponse {
+    #[serde(rename = "_id")]
+    key: String,
+    /// The title the response belongs to, e.g. `/movie/550`, used for invalidation.
+    resource: String,
+    body: String,
+    expires_at: DateTime,
+}
+
+#[derive(Debug)]
+struct Entry {
+    resource: String,
+    body: String,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+/// Entries by key plus their keys ordered from the least recently used.
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    order: BTreeMap<u64, String>,
+    clock: u64,
+}
+impl Lru {
+    fn get(&mut self, key: &str) -> Option<String> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires_at <= Instant::now() {
+            let last_used = entry.last_used;
+            self.entries.remove(key);
+            self.order.remove(&last_used);
+            return None;
+        }
+        self.clock += 1;
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.clock, key.to_string());
+        entry.last_used = self.clock;
+        Some(entry.body.clone())
+    }
+    fn put(&mut self, key: String, resource: String, body: String, ttl: Duration, capacity: usize) {
+        self.clock += 1;
+        let entry = Entry {
+            resource,
+            body,
+            expires_at: Instant::now() + ttl,
+            last_used: self.clock,
+        };
+        if let Some(old) = self.entries.insert(key.clone(), entry) {
+            self.order.remove(&old.last_used);
+        }
+        self.order.insert(self.clock, key);
+        while self.entries.len() > capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+    fn invalidate(&mut self, resource: &str) {
+        let order = &mut self.order;
+        self.entries.retain(|_, entry| {
+            let keep = entry.resource != resource;
+            if !keep {
+                order.remove(&entry.last_used);
+            }
+            keep
+        });
+    }
+}
+
+/// Cache of TMDB responses: an in-process LRU in front of an optional Mongo
+/// collection, so cached responses survive restarts.
+///
+/// Mongo failures are logged and treated as misses, the cache never fails a request.
+#[derive(Debug)]
+pub struct ResponseCache {
+    capacity: usize,
+    memory: Mutex<Lru>,
+    mongo: Option<Collection<CachedResponse>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn in_memory(capacity: usize) -> Self {
+        Self {
+            capacity,
+            memory: Mutex::default(),
+            mongo: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates the cache and the TTL index that lets Mongo purge expired responses.
+    pub async fn with_mongo(capacity: usize, responses: Collection<CachedResponse>) -> Self {
+        let index = IndexModel::builder()
+            .keys(doc! {"expires_at": 1})
+            .options(
+                IndexOptions::builder()
+                    .name("tmdb_cache_ttl".to_string())
+                    .expire_after(Duration::ZERO)
+                    .build(),
+            )
+            .build();
+        if let Err(e) = responses.create_index(index).await {
+            tracing::warn!("Failed to create TMDB cache TTL index: {e}");
+        }
+        Self {
+            mongo: Some(responses),
+            ..Self::in_memory(capacity)
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn lru(&self) -> std::sync::MutexGuard<'_, Lru> {
+        // The LRU stays consistent even if a holder panicked, so poisoning is ignored.
+        self.memory.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub async fn get(&self, key: &str) -> Option<String> {
+        if let Some(body) = self.lru().get(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(body);
+        }
+        if let Some(responses) = &self.mongo {
+            // The TTL monitor only runs once a minute, so expiry is checked here too.
+            let filter = doc! {"_id": key, "expires_at": {"$gt": DateTime::now()}};
+            match responses.find_one(filter).await {
+                Ok(Some(cached)) => {
+                    let ttl = Duration::from_millis(
+                        (cached.expires_at.timestamp_millis() - DateTime::now().timestamp_millis())
+                            .max(0) as u64,
+                    );
+                    self.lru().put(
+                        cached.key,
+                        cached.resource,
+                        cached.body.clone(),
+                        ttl,
+                        self.capacity,
+                    );
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(cached.body);
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to read TMDB cache: {e}"),
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    pub async fn put(&self, key: String, resource: String, body: String, ttl: Duration) {
+        if let Some(responses) = &self.mongo {
+            let cached = CachedResponse {
+                key: key.clone(),
+                resource: resource.clone(),
+                body: body.clone(),
+                expires_at: DateTime::from_millis(
+                    DateTime::now().timestamp_millis() + ttl.as_millis() as i64,
+                ),
+            };
+            if let Err(e) = responses
+                .replace_one(doc! {"_id": &key}, cached)
+                .upsert(true)
+                .await
+            {
+                tracing::warn!("Failed to write TMDB cache: {e}");
+            }
+        }
+        self.lru().put(key, resource, body, ttl, self.capacity);
+    }
+
+    /// Drops every cached response of the resource in both tiers.
+    pub async fn invalidate(&self, resource: &str) {
+        self.lru().invalidate(resource);
+        if let Some(responses) = &self.mongo
+            && let Err(e) = responses.delete_many(doc! {"resource": resource}).await
+        {
+            tracing::warn!("Failed to invalidate TMDB cache: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{
+        mock_server::{MockResponse, MockServer},
+        tmdb::Tmdb,
+    };
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    async fn put(cache: &ResponseCache, key: &str, resource: &str, ttl: Duration) {
+        cache
+            .put(key.to_string(), resource.to_string(), key.to_string(), ttl)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entry_is_evicted() {
+        let cache = ResponseCache::in_memory(2);
+        put(&cache, "a", "/movie/1", HOUR).await;
+        put(&cache, "b", "/movie/2", HOUR).await;
+        assert!(cache.get("a").await.is_some());
+        put(&cache, "c", "/movie/3", HOUR).await;
+
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("c").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn entries_expire_after_their_own_ttl() {
+        let cache = ResponseCache::in_memory(10);
+        put(&cache, "search", "/search/movie", Duration::from_millis(50)).await;
+        put(&cache, "details", "/movie/1", HOUR).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(cache.get("search").await.is_none());
+        assert_eq!(cache.get("details").await.as_deref(), Some("details"));
+    }
+
+    #[tokio::test]
+    async fn invalidate_drops_every_response_of_the_resource() {
+        let cache = ResponseCache::in_memory(10);
+        put(&cache, "details", "/movie/1", HOUR).await;
+        put(&cache, "credits", "/movie/1", HOUR).await;
+        put(&cache, "other", "/movie/2", HOUR).await;
+        cache.invalidate("/movie/1").await;
+
+        assert!(cache.get("details").await.is_none());
+        assert!(cache.get("credits").await.is_none());
+        assert!(cache.get("other").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn hits_and_misses_are_counted() {
+        let cache = ResponseCache::in_memory(10);
+        assert!(cache.get("a").await.is_none());
+        put(&cache, "a", "/movie/1", HOUR).await;
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("a").await.is_some());
+
+        assert_eq!((cache.hits(), cache.misses()), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn responses_are_cached_per_language() {
+        let server = MockServer::start(|_| MockResponse::json("{}")).await;
+        let russian = Tmdb::new("token".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let english = russian.clone().with_language("en");
+
+        russian.find("tt0137523", "imdb_id").await.unwrap();
+        russian.find("tt0137523", "imdb_id").await.unwrap();
+        assert_eq!(server.requests().len(), 1);
+        english.find("tt0137523", "imdb_id").await.unwrap();
+        assert_eq!(server.requests().len(), 2);
+
+        let metrics = russian.metrics();
+        assert_eq!((metrics.cache_hits, metrics.cache_misses), (1, 2));
+    }
+}
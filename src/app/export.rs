@@ -0,0 +1,81 @@
+use anyhow::Result;
+use mongodb::bson::DateTime;
+use serde::Serialize;
+
+use crate::app::models::{Movie, Serial, TitleSnapshot};
+
+/// One exported title, flat so it maps onto a spreadsheet row.
+#[derive(Debug, Serialize)]
+pub struct ExportRow {
+    pub kind: &'static str,
+    pub tmdb_id: i64,
+    pub imdb_id: Option<String>,
+    pub title: String,
+    pub original_title: String,
+    pub year: Option<i32>,
+    pub watched: bool,
+    pub rating: Option<f64>,
+    pub added_at: Option<String>,
+    pub watched_at: Option<String>,
+    pub rated_at: Option<String>,
+}
+
+fn rfc3339(date: Option<DateTime>) -> Option<String> {
+    date.and_then(|d| d.try_to_rfc3339_string().ok())
+}
+
+impl ExportRow {
+    /// Without a snapshot only what the record itself keeps is exported.
+    pub fn film(movie: &Movie, snapshot: Option<&TitleSnapshot>) -> Self {
+        Self {
+            kind: "film",
+            tmdb_id: movie.film_id,
+            imdb_id: snapshot.and_then(|s| s.imdb_id.clone()),
+            title: snapshot.map(|s| s.title.clone()).unwrap_or_default(),
+            original_title: snapshot
+                .map(|s| s.original_title.clone())
+                .unwrap_or_default(),
+            year: snapshot.and_then(|s| s.year),
+            watched: movie.viewing.watched,
+            rating: movie.viewing.my_rating,
+            added_at: rfc3339(movie.added_at),
+            watched_at: rfc3339(movie.viewing.watched_at),
+            rated_at: rfc3339(movie.viewing.rated_at),
+        }
+    }
+    pub fn serial(serial: &Serial, snapshot: Option<&TitleSnapshot>) -> Self {
+        Self {
+            kind: "serial",
+            tmdb_id: serial.serial_id,
+            imdb_id: snapshot.and_then(|s| s.imdb_id.clone()),
+            title: snapshot.map(|s| s.title.clone()).unwrap_or_default(),
+            original_title: snapshot
+                .map(|s| s.original_title.clone())
+                .unwrap_or_default(),
+            year: snapshot.and_then(|s| s.year),
+            watched: serial.viewing.watched,
+            // Same rating the bot shows, derived from the seasons when the serial has none.
+            rating: serial.overall_rating(),
+            added_at: rfc3339(serial.added_at),
+            watched_at: rfc3339(serial.viewing.watched_at),
+            rated_at: rfc3339(
+                serial
+                    .viewing
+                    .rated_at
+                    .or_else(|| serial.season_ratings.iter().map(|r| r.rated_at).max()),
+            ),
+        }
+    }
+}
+
+pub fn to_csv(rows: &[ExportRow]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+pub fn to_json(rows: &[ExportRow]) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(rows)?)
+}
@@ -0,0 +1,105 @@
+use anyhow::Result;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::app::{
+    import::{ImportSummary, ImportedTitle, TitleKind, header, parse_date, save},
+    storage::{AddOutcome, Storage},
+    tmdb::Tmdb,
+};
+
+const IMDB_SOURCE: &str = "imdb_id";
+
+/// A row of IMDb's "Your Ratings" export.
+#[derive(Debug, Deserialize)]
+struct Row {
+    #[serde(rename = "Const")]
+    imdb_id: String,
+    /// Already on the 1–10 scale.
+    #[serde(rename = "Your Rating")]
+    rating: f64,
+    #[serde(rename = "Date Rated")]
+    date_rated: String,
+    #[serde(rename = "Title")]
+    title: String,
+    #[serde(rename = "Year", default)]
+    year: Option<i32>,
+}
+
+/// A rated title with its IMDb id (`tconst`).
+#[derive(Debug)]
+pub struct ImdbRating {
+    pub imdb_id: String,
+    pub title: ImportedTitle,
+}
+
+pub fn is_imdb_export(data: &[u8]) -> bool {
+    let header = header(data);
+    header.contains("Const") && header.contains("Your Rating")
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<ImdbRating>> {
+    let mut reader = csv::Reader::from_reader(data);
+    let mut ratings = Vec::new();
+    for row in reader.deserialize() {
+        let row: Row = row?;
+        let rated_at = parse_date(&row.date_rated);
+        ratings.push(ImdbRating {
+            imdb_id: row.imdb_id,
+            title: ImportedTitle {
+                title: row.title,
+                kind: TitleKind::Film,
+                original_title: None,
+                year: row.year,
+                watched: true,
+                rating: Some(row.rating),
+                added_at: rated_at,
+                watch_history: rated_at.into_iter().collect(),
+                rated_at,
+            },
+        });
+    }
+    Ok(ratings)
+}
+
+/// Resolves every IMDb id through TMDB and saves it as a film or a serial.
+#[instrument(name = "import imdb ratings", skip_all, fields(count = ratings.len()))]
+pub async fn import(
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    user_id: u64,
+    region: &str,
+    ratings: Vec<ImdbRating>,
+) -> Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+    for rating in ratings {
+        let label = rating.title.label();
+        let found = match tmdb_client.find(&rating.imdb_id, IMDB_SOURCE).await {
+            Ok(found) => found,
+            Err(e) => {
+                tracing::warn!("Failed to find {id}: {e}", id = rating.imdb_id);
+                summary.unmatched.push(label);
+                continue;
+            }
+        };
+        let mut title = rating.title;
+        let tmdb_id = if let Some(film) = found.movie_results.first() {
+            film.id
+        } else if let Some(tv_show) = found.tv_results.first() {
+            title.kind = TitleKind::Serial;
+            tv_show.id
+        } else {
+            summary.unmatched.push(label);
+            continue;
+        };
+        match save(storage, tmdb_client, user_id, region, title, tmdb_id).await {
+            Ok(AddOutcome::Added) => summary.matched += 1,
+            Ok(_) => summary.already_listed += 1,
+            Err(e) => {
+                tracing::warn!("Failed to save {label}: {e}");
+                summary.unmatched.push(label);
+            }
+        }
+    }
+    Ok(summary)
+}
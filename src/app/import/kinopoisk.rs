@@ -0,0 +1,193 @@
+use std::borrow::Cow;
+
+use anyhow::{Result, anyhow};
+
+use crate::app::import::{ImportedTitle, TitleKind, parse_date};
+
+/// Column positions found in the header row, titles are required.
+#[derive(Debug, Default)]
+struct Columns {
+    title: Option<usize>,
+    original_title: Option<usize>,
+    year: Option<usize>,
+    rating: Option<usize>,
+    date: Option<usize>,
+    kind: Option<usize>,
+}
+impl Columns {
+    fn from_header(header: &[String]) -> Self {
+        let mut columns = Self::default();
+        for (i, name) in header.iter().enumerate() {
+            let name = name.to_lowercase();
+            if name.contains("оригинальн") {
+                columns.original_title.get_or_insert(i);
+            } else if name.contains("название") {
+                columns.title.get_or_insert(i);
+            } else if name.starts_with("год") {
+                columns.year.get_or_insert(i);
+            } else if name.contains("оценка") && !name.contains("кинопоиск") {
+                columns.rating.get_or_insert(i);
+            } else if name.starts_with("дата") {
+                columns.date.get_or_insert(i);
+            } else if name.starts_with("тип") {
+                columns.kind.get_or_insert(i);
+            }
+        }
+        columns
+    }
+}
+
+/// Exports from Kinopoisk are Windows-1251 "xls" pages or CSV files converted from them.
+fn decode(data: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(data) {
+        Ok(text) => Cow::Borrowed(text.trim_start_matches('\u{feff}')),
+        Err(_) => encoding_rs::WINDOWS_1251.decode(data).0,
+    }
+}
+
+pub fn is_kinopoisk_export(data: &[u8]) -> bool {
+    let text = decode(data).to_lowercase();
+    text.contains("оригинальное название") || text.contains("русскоязычное название")
+}
+
+/// Parses a ratings or "буду смотреть" export, a list without a rating column is a watch list.
+pub fn parse(data: &[u8]) -> Result<Vec<ImportedTitle>> {
+    let text = decode(data);
+    let mut rows = if text.to_lowercase().contains("<table") {
+        html_rows(&text)
+    } else {
+        csv_rows(&text)?
+    };
+    let header_index = rows
+        .iter()
+        .position(|row| Columns::from_header(row).title.is_some())
+        .ok_or_else(|| anyhow!("Kinopoisk export has no title column"))?;
+    let columns = Columns::from_header(&rows[header_index]);
+    let watched = columns.rating.is_some();
+    let cell = |row: &[String], index: Option<usize>| {
+        index
+            .and_then(|i| row.get(i))
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty())
+    };
+    let mut titles = Vec::new();
+    for row in rows.drain(header_index + 1..) {
+        let Some(raw_title) = cell(&row, columns.title) else {
+            continue;
+        };
+        let year_cell = cell(&row, columns.year).unwrap_or_default();
+        let kind_cell = cell(&row, columns.kind).unwrap_or_default().to_lowercase();
+        let (title, marked_serial) = strip_serial_marker(&raw_title);
+        // Serials are exported with a year range such as "2011 – 2019" or "2020 – ...".
+        let kind = if marked_serial || kind_cell.contains("сериал") || year_cell.len() > 4 {
+            TitleKind::Serial
+        } else {
+            TitleKind::Film
+        };
+        let logged_at = cell(&row, columns.date).and_then(|d| parse_russian_date(&d));
+        let rating = cell(&row, columns.rating).and_then(|r| r.replace(',', ".").parse().ok());
+        titles.push(ImportedTitle {
+            kind,
+            title,
+            original_title: cell(&row, columns.original_title),
+            year: year_cell.get(..4).and_then(|y| y.parse().ok()),
+            watched,
+            rating,
+            added_at: logged_at,
+            watch_history: if watched {
+                logged_at.into_iter().collect()
+            } else {
+                Vec::new()
+            },
+            rated_at: rating.and(logged_at),
+        });
+    }
+    Ok(titles)
+}
+
+fn strip_serial_marker(title: &str) -> (String, bool) {
+    for marker in ["(мини-сериал)", "(сериал)"] {
+        if let Some(stripped) = title.strip_suffix(marker) {
+            return (stripped.trim().to_string(), true);
+        }
+    }
+    (title.to_string(), false)
+}
+
+/// Accepts `dd.mm.yyyy` with an optional time, or `yyyy-mm-dd`.
+fn parse_russian_date(date: &str) -> Option<mongodb::bson::DateTime> {
+    let date = date.split([',', ' ']).next()?;
+    let parts: Vec<&str> = date.split('.').collect();
+    match parts.as_slice() {
+        [day, month, year] => parse_date(&format!("{year}-{month}-{day}")),
+        _ => parse_date(date),
+    }
+}
+
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>> {
+    let header = text.lines().next().unwrap_or_default();
+    let delimiter = [b';', b'\t', b',']
+        .into_iter()
+        .max_by_key(|d| header.bytes().filter(|b| b == d).count())
+        .unwrap_or(b',');
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        rows.push(record?.iter().map(str::to_string).collect());
+    }
+    Ok(rows)
+}
+
+/// Cells of every `<tr>`, with nested tags stripped and basic entities decoded.
+fn html_rows(html: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut cell: Option<String> = None;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        if let Some(cell) = cell.as_mut() {
+            cell.push_str(&rest[..start]);
+        }
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+        let name = tag
+            .split(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or_default();
+        match name {
+            "tr" => row.clear(),
+            "/tr" => rows.push(std::mem::take(&mut row)),
+            "td" | "th" => cell = Some(String::new()),
+            "/td" | "/th" => {
+                if let Some(text) = cell.take() {
+                    row.push(html_text(&text));
+                }
+            }
+            "br" | "br/" => {
+                if let Some(cell) = cell.as_mut() {
+                    cell.push(' ');
+                }
+            }
+            _ => {}
+        }
+        rest = &rest[start + end + 1..];
+    }
+    rows
+}
+
+fn html_text(text: &str) -> String {
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::app::import::{ImportedTitle, header, parse_date};
+
+/// A row of `diary.csv`, `ratings.csv`, `watched.csv` or `watchlist.csv`,
+/// columns missing from a particular file are left empty.
+#[derive(Debug, Deserialize)]
+struct Row {
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Year", default)]
+    year: Option<i32>,
+    /// From half a star to five stars.
+    #[serde(rename = "Rating", default)]
+    rating: Option<f64>,
+    #[serde(rename = "Watched Date", default)]
+    watched_date: Option<String>,
+}
+
+pub fn is_letterboxd_export(data: &[u8]) -> bool {
+    header(data).contains("Letterboxd URI")
+}
+
+/// Parses a Letterboxd export, rows of the same film are merged into one.
+///
+/// The file name tells `watchlist.csv` apart from the files of watched films.
+pub fn parse(file_name: &str, data: &[u8]) -> Result<Vec<ImportedTitle>> {
+    let watched = !file_name.to_lowercase().starts_with("watchlist");
+    let mut reader = csv::Reader::from_reader(data);
+    let mut films: Vec<ImportedTitle> = Vec::new();
+    for row in reader.deserialize() {
+        let row: Row = row?;
+        let logged_at = parse_date(&row.date);
+        let index = match films
+            .iter()
+            .position(|f| f.title == row.name && f.year == row.year)
+        {
+            Some(index) => index,
+            None => {
+                films.push(ImportedTitle {
+                    title: row.name.clone(),
+                    year: row.year,
+                    watched,
+                    added_at: logged_at,
+                    ..Default::default()
+                });
+                films.len() - 1
+            }
+        };
+        let film = &mut films[index];
+        if watched {
+            let watched_at = row
+                .watched_date
+                .as_deref()
+                .and_then(parse_date)
+                .or(logged_at);
+            if let Some(watched_at) = watched_at {
+                film.watch_history.push(watched_at);
+            }
+        }
+        if let Some(stars) = row.rating {
+            film.rating = Some(stars * 2.0);
+            film.rated_at = logged_at;
+        }
+    }
+    for film in &mut films {
+        film.watch_history.sort();
+        film.watch_history.dedup();
+    }
+    Ok(films)
+}
@@ -0,0 +1,499 @@
+pub mod imdb;
+pub mod kinopoisk;
+pub mod letterboxd;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use mongodb::bson::DateTime;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::app::{
+    models::{Movie, Serial, TitleSnapshot},
+    storage::{AddOutcome, Storage},
+    tmdb::Tmdb,
+};
+
+/// How many TMDB candidates are offered for an ambiguous row.
+const MAX_CANDIDATES: usize = 5;
+/// How long an ambiguous row waits for the user to pick a candidate.
+const PENDING_IMPORT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Ambiguous rows kept per user, the oldest ones are dropped beyond it.
+const MAX_PENDING_PER_USER: usize = 500;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TitleKind {
+    #[default]
+    Film,
+    Serial,
+}
+
+/// A title read from another tracker's export, not yet matched to TMDB.
+#[derive(Clone, Debug, Default)]
+pub struct ImportedTitle {
+    pub kind: TitleKind,
+    pub title: String,
+    /// Title in the original language, when the export has it.
+    pub original_title: Option<String>,
+    pub year: Option<i32>,
+    pub watched: bool,
+    /// Rating on the bot's 10-point scale.
+    pub rating: Option<f64>,
+    pub added_at: Option<DateTime>,
+    pub watch_history: Vec<DateTime>,
+    pub rated_at: Option<DateTime>,
+}
+impl ImportedTitle {
+    pub fn label(&self) -> String {
+        match self.year {
+            Some(year) => format!("{title} ({year})", title = self.title),
+            None => self.title.clone(),
+        }
+    }
+    fn into_movie(self, user_id: u64, film_id: i64, snapshot: TitleSnapshot) -> Movie {
+        let mut movie = Movie::new(user_id, film_id);
+        movie.viewing.watched = self.watched;
+        movie.viewing.my_rating = self.rating;
+        movie.viewing.rated_at = self.rated_at;
+        movie.added_at = self.added_at.or(movie.added_at);
+        movie.viewing.watched_at = self.watched_at();
+        movie.viewing.watch_history = self.watch_history;
+        movie.snapshot = Some(snapshot);
+        movie
+    }
+    fn into_serial(self, user_id: u64, serial_id: i64, snapshot: TitleSnapshot) -> Serial {
+        let mut serial = Serial::new(user_id, serial_id);
+        serial.viewing.watched = self.watched;
+        serial.viewing.my_rating = self.rating;
+        serial.viewing.rated_at = self.rated_at;
+        serial.added_at = self.added_at.or(serial.added_at);
+        serial.viewing.watched_at = self.watched_at();
+        serial.viewing.watch_history = self.watch_history;
+        serial.snapshot = Some(snapshot);
+        serial
+    }
+    /// Latest viewing, falling back to when the title was logged.
+    fn watched_at(&self) -> Option<DateTime> {
+        if !self.watched {
+            return None;
+        }
+        self.watch_history.last().copied().or(self.added_at)
+    }
+}
+
+/// First line of a CSV file, used to tell export formats apart.
+pub fn header(data: &[u8]) -> &str {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let line = data.split(|b| *b == b'\n').next().unwrap_or_default();
+    std::str::from_utf8(line).unwrap_or_default()
+}
+
+/// Parses a `YYYY-MM-DD` date as midnight UTC.
+pub fn parse_date(date: &str) -> Option<DateTime> {
+    DateTime::parse_rfc3339_str(format!("{d}T00:00:00Z", d = date.trim())).ok()
+}
+
+/// A TMDB search result an imported title may refer to.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub id: i64,
+    pub title: String,
+    pub original_title: String,
+    pub date: String,
+}
+impl Candidate {
+    fn year(&self) -> Option<i32> {
+        self.date.split('-').next()?.parse().ok()
+    }
+    pub fn label(&self) -> String {
+        format!("{t} ({d})", t = self.title, d = self.date)
+    }
+}
+
+/// Result of looking an imported title up in TMDB.
+#[derive(Debug)]
+pub enum Resolution {
+    Matched(i64),
+    Ambiguous(Vec<Candidate>),
+    Unmatched,
+}
+
+async fn search(tmdb_client: &Tmdb, kind: TitleKind, query: String) -> Result<Vec<Candidate>> {
+    let candidates = match kind {
+        TitleKind::Film => tmdb_client
+            .search_film(query, 1)
+            .await?
+            .results
+            .into_iter()
+            .map(|r| Candidate {
+                id: r.id,
+                title: r.title,
+                original_title: r.original_title,
+                date: r.release_date,
+            })
+            .collect(),
+        TitleKind::Serial => tmdb_client
+            .search_tvshow(query, 1)
+            .await?
+            .results
+            .into_iter()
+            .map(|r| Candidate {
+                id: r.id,
+                title: r.name,
+                original_title: r.original_name,
+                date: r.first_air_date,
+            })
+            .collect(),
+    };
+    Ok(candidates)
+}
+
+/// Matches a title by name and year among TMDB search results,
+/// searching by the original title when the export has one.
+#[instrument(name = "resolve imported title", skip(tmdb_client))]
+pub async fn resolve(tmdb_client: &Tmdb, title: &ImportedTitle) -> Result<Resolution> {
+    let query = title.original_title.clone().unwrap_or(title.title.clone());
+    let candidates: Vec<Candidate> = search(tmdb_client, title.kind, query)
+        .await?
+        .into_iter()
+        .filter(|c| title.year.is_none() || c.year() == title.year)
+        .collect();
+    if candidates.len() == 1 {
+        return Ok(Resolution::Matched(candidates[0].id));
+    }
+    let names: Vec<&str> = std::iter::once(title.title.as_str())
+        .chain(title.original_title.as_deref())
+        .collect();
+    let exact: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|c| {
+            names.iter().any(|n| {
+                c.title.to_lowercase() == n.to_lowercase()
+                    || c.original_title.to_lowercase() == n.to_lowercase()
+            })
+        })
+        .collect();
+    if exact.len() == 1 {
+        return Ok(Resolution::Matched(exact[0].id));
+    }
+    if candidates.is_empty() {
+        return Ok(Resolution::Unmatched);
+    }
+    Ok(Resolution::Ambiguous(
+        candidates.into_iter().take(MAX_CANDIDATES).collect(),
+    ))
+}
+
+/// Saves an imported title as the given TMDB film or serial, depending on its kind.
+pub async fn save(
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    user_id: u64,
+    region: &str,
+    title: ImportedTitle,
+    tmdb_id: i64,
+) -> Result<AddOutcome> {
+    match title.kind {
+        TitleKind::Film => save_film(storage, tmdb_client, user_id, region, title, tmdb_id).await,
+        TitleKind::Serial => save_serial(storage, tmdb_client, user_id, title, tmdb_id).await,
+    }
+}
+
+async fn save_film(
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    user_id: u64,
+    region: &str,
+    film: ImportedTitle,
+    film_id: i64,
+) -> Result<AddOutcome> {
+    let details = tmdb_client.get_films_details(film_id).await?;
+    let movie = film.into_movie(user_id, film_id, TitleSnapshot::from(&details));
+    storage.import_film(movie, region.to_string()).await
+}
+
+async fn save_serial(
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    user_id: u64,
+    title: ImportedTitle,
+    serial_id: i64,
+) -> Result<AddOutcome> {
+    let details = tmdb_client.get_tv_show_details(serial_id).await?;
+    let serial = title.into_serial(user_id, serial_id, TitleSnapshot::from(&details));
+    storage.import_serial(serial).await
+}
+
+/// An ambiguous row waiting for the user to pick one of the candidates.
+#[derive(Debug)]
+struct PendingImport {
+    key: u64,
+    title: ImportedTitle,
+    candidates: Vec<(i64, String)>,
+    expires_at: Instant,
+}
+
+/// Ambiguous rows per user, kept until the user picks a candidate or skips them.
+///
+/// The rows form a queue that is asked one at a time. Rows expire after a day and every user keeps a bounded number of them,
+/// so rows nobody answers don't pile up; a restart drops them all.
+#[derive(Clone, Debug)]
+pub struct PendingImports {
+    next_key: Arc<AtomicU64>,
+    rows: Arc<Mutex<HashMap<u64, Vec<PendingImport>>>>,
+    ttl: Duration,
+    max_per_user: usize,
+}
+impl Default for PendingImports {
+    fn default() -> Self {
+        Self {
+            next_key: Arc::default(),
+            rows: Arc::default(),
+            ttl: PENDING_IMPORT_TTL,
+            max_per_user: MAX_PENDING_PER_USER,
+        }
+    }
+}
+impl PendingImports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    async fn push(
+        &self,
+        user_id: u64,
+        title: ImportedTitle,
+        candidates: Vec<(i64, String)>,
+    ) -> u64 {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut rows = self.rows.lock().await;
+        rows.retain(|_, pending| {
+            pending.retain(|p| p.expires_at > now);
+            !pending.is_empty()
+        });
+        let pending = rows.entry(user_id).or_default();
+        pending.push(PendingImport {
+            key,
+            title,
+            candidates,
+            expires_at: now + self.ttl,
+        });
+        if pending.len() > self.max_per_user {
+            let dropped = pending.len() - self.max_per_user;
+            pending.drain(..dropped);
+            tracing::warn!("Dropped {dropped} pending import rows of user {user_id}");
+        }
+        key
+    }
+    /// Removes the pending row, only its owner can resolve it.
+    pub async fn take(&self, user_id: u64, key: u64) -> Option<ImportedTitle> {
+        let mut rows = self.rows.lock().await;
+        let pending = rows.get_mut(&user_id)?;
+        let index = pending.iter().position(|p| p.key == key)?;
+        let row = pending.remove(index);
+        if pending.is_empty() {
+            rows.remove(&user_id);
+        }
+        (row.expires_at > Instant::now()).then_some(row.title)
+    }
+    /// The oldest row still waiting for the user and how many rows are left.
+    pub async fn next(&self, user_id: u64) -> Option<(AmbiguousRow, usize)> {
+        let now = Instant::now();
+        let mut rows = self.rows.lock().await;
+        let pending = rows.get_mut(&user_id)?;
+        pending.retain(|p| p.expires_at > now);
+        let Some(first) = pending.first() else {
+            rows.remove(&user_id);
+            return None;
+        };
+        let row = AmbiguousRow {
+            key: first.key,
+            label: first.title.label(),
+            candidates: first.candidates.clone(),
+        };
+        Some((row, pending.len()))
+    }
+}
+
+/// An ambiguous row as shown to the user, candidates are `(tmdb id, label)`.
+#[derive(Debug)]
+pub struct AmbiguousRow {
+    pub key: u64,
+    pub label: String,
+    pub candidates: Vec<(i64, String)>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub matched: usize,
+    pub already_listed: usize,
+    pub ambiguous: usize,
+    pub unmatched: Vec<String>,
+}
+
+/// Matches every title and saves the unambiguous ones, ambiguous rows become pending.
+#[instrument(name = "import titles", skip_all, fields(count = titles.len()))]
+pub async fn import_titles(
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    pending: &PendingImports,
+    user_id: u64,
+    region: &str,
+    titles: Vec<ImportedTitle>,
+) -> Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+    for title in titles {
+        match resolve(tmdb_client, &title).await {
+            Ok(Resolution::Matched(tmdb_id)) => {
+                let label = title.label();
+                match save(storage, tmdb_client, user_id, region, title, tmdb_id).await {
+                    Ok(AddOutcome::Added) => summary.matched += 1,
+                    Ok(_) => summary.already_listed += 1,
+                    Err(e) => {
+                        tracing::warn!("Failed to save {label}: {e}");
+                        summary.unmatched.push(label);
+                    }
+                }
+            }
+            Ok(Resolution::Ambiguous(candidates)) => {
+                let candidates = candidates.iter().map(|c| (c.id, c.label())).collect();
+                pending.push(user_id, title, candidates).await;
+                summary.ambiguous += 1;
+            }
+            Ok(Resolution::Unmatched) => summary.unmatched.push(title.label()),
+            Err(e) => {
+                tracing::warn!("Failed to resolve {title}: {e}", title = title.label());
+                summary.unmatched.push(title.label());
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::app::{
+        mock_server::{MockResponse, MockServer},
+        releases,
+        storage::InMemoryStorage,
+    };
+
+    #[tokio::test]
+    async fn failed_details_fetch_leaves_the_row_unmatched() {
+        let server = MockServer::start(|request| {
+            if !request.path.starts_with("/search/movie?") {
+                return MockResponse::status(404);
+            }
+            let film = json!({
+                "adult": false,
+                "backdrop_path": null,
+                "genre_ids": [],
+                "id": 404,
+                "original_language": "en",
+                "original_title": "Missing",
+                "overview": "",
+                "popularity": 1.0,
+                "poster_path": null,
+                "release_date": "2001-01-01",
+                "title": "Пропавший",
+                "video": false,
+                "vote_average": 0.0,
+                "vote_count": 0
+            });
+            MockResponse::json(
+                json!({"page": 1, "results": [film], "total_pages": 1, "total_results": 1})
+                    .to_string(),
+            )
+        })
+        .await;
+        let tmdb_client = Tmdb::new("token".to_string())
+            .unwrap()
+            .with_base_url(server.url());
+        let storage: Storage = Arc::new(InMemoryStorage::new());
+        let title = ImportedTitle {
+            title: "Пропавший".to_string(),
+            year: Some(2001),
+            ..Default::default()
+        };
+
+        let summary = import_titles(
+            &storage,
+            &tmdb_client,
+            &PendingImports::new(),
+            1,
+            releases::DEFAULT_REGION,
+            vec![title],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.matched, 0);
+        assert_eq!(summary.unmatched, vec!["Пропавший (2001)".to_string()]);
+        assert!(storage.get_users_movies(1).await.unwrap().is_empty());
+    }
+
+    fn title(name: &str) -> ImportedTitle {
+        ImportedTitle {
+            title: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn pending_rows_are_bounded_per_user() {
+        let pending = PendingImports {
+            max_per_user: 2,
+            ..PendingImports::new()
+        };
+        let first = pending.push(1, title("a"), Vec::new()).await;
+        let second = pending.push(1, title("b"), Vec::new()).await;
+        let third = pending.push(1, title("c"), Vec::new()).await;
+        let other = pending.push(2, title("d"), Vec::new()).await;
+
+        assert!(pending.take(1, first).await.is_none());
+        assert_eq!(pending.take(1, second).await.unwrap().title, "b");
+        assert_eq!(pending.take(1, third).await.unwrap().title, "c");
+        assert!(pending.take(1, other).await.is_none());
+        assert_eq!(pending.take(2, other).await.unwrap().title, "d");
+    }
+
+    #[tokio::test]
+    async fn pending_rows_expire() {
+        let pending = PendingImports {
+            ttl: Duration::ZERO,
+            ..PendingImports::new()
+        };
+        let key = pending.push(1, title("a"), Vec::new()).await;
+        assert!(pending.take(1, key).await.is_none());
+        pending.push(2, title("b"), Vec::new()).await;
+        assert!(pending.rows.lock().await.get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn pending_rows_are_asked_in_order() {
+        let pending = PendingImports::new();
+        let first = pending.push(1, title("a"), vec![(10, "A".into())]).await;
+        pending.push(1, title("b"), Vec::new()).await;
+
+        let (row, left) = pending.next(1).await.unwrap();
+        assert_eq!((row.key, row.label.as_str(), left), (first, "a", 2));
+        assert_eq!(row.candidates, vec![(10, "A".to_string())]);
+        pending.take(1, first).await;
+        let (row, left) = pending.next(1).await.unwrap();
+        assert_eq!((row.label.as_str(), left), ("b", 1));
+        assert!(pending.next(2).await.is_none());
+    }
+}
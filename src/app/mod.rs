@@ -1,28 +1,177 @@
+pub mod cache;
+pub mod export;
+pub mod import;
+#[cfg(test)]
+pub mod mock_server;
 pub mod models;
+pub mod releases;
+pub mod reminders;
+pub mod review;
+pub mod snapshots;
+pub mod stats;
 pub mod storage;
 pub mod telegram;
 pub mod tmdb;
 
-use anyhow::Result;
+use std::{sync::Arc, time::Duration};
 
-use models::{Movie, Serial};
+use anyhow::{Result, anyhow};
+
+use models::{GroupFilm, GroupSettings, Movie, Serial};
 use mongodb::{Client, Collection};
+use telegram::{DialogueRecord, DialogueStorage};
 
 const CONTENT_DATABASE: &str = "content";
 const MOVIES: &str = "movies";
 const SERIALS: &str = "serials";
+const GROUPS: &str = "groups";
+const GROUP_FILMS: &str = "group_films";
+const POSTERS: &str = "posters";
+const DIALOGUES: &str = "dialogues";
+const TMDB_CACHE: &str = "tmdb_cache";
+const DEFAULT_DIALOGUE_TTL_HOURS: u64 = 24;
+const IN_MEMORY_DATABASE_URL: &str = "memory://";
+const SQLITE_URL_SCHEME: &str = "sqlite://";
 
 #[tracing::instrument(name = "app")]
 pub async fn run() -> Result<()> {
     tracing_subscriber::fmt().init();
-    let mongo_url = std::env::var("MONGODB_URI")?;
     let tmdb_token = std::env::var("TMDB_TOKEN")?;
+    let dry_run = std::env::var("MIGRATIONS_DRY_RUN").is_ok_and(|v| v == "1" || v == "true");
+    let storage = connect_storage(dry_run).await?;
+    if dry_run {
+        tracing::info!("Migrations dry run finished, exiting");
+        return Ok(());
+    }
+    let dialogue_storage = connect_dialogue_storage().await?;
+    let tmdb_client = tmdb_client(tmdb_token).await?;
+    tokio::spawn(snapshots::run(storage.clone(), tmdb_client.clone()));
+    tokio::spawn(releases::run(
+        storage.clone(),
+        tmdb_client.clone(),
+        teloxide::Bot::from_env(),
+    ));
+    tokio::spawn(reminders::run(
+        storage.clone(),
+        tmdb_client.clone(),
+        teloxide::Bot::from_env(),
+    ));
+    telegram::run(storage, dialogue_storage, tmdb_client).await?;
+    Ok(())
+}
+
+async fn connect_storage(migrations_dry_run: bool) -> Result<storage::Storage> {
+    if let Ok(database_url) = std::env::var("DATABASE_URL") {
+        if database_url == IN_MEMORY_DATABASE_URL {
+            tracing::warn!("Using in-memory storage, all data will be lost on restart");
+            return Ok(Arc::new(storage::InMemoryStorage::new()));
+        }
+        if let Some(path) = database_url.strip_prefix(SQLITE_URL_SCHEME) {
+            tracing::info!("Using SQLite storage at {path}");
+            return Ok(Arc::new(storage::SqliteStorage::open(path)?));
+        }
+        return Err(anyhow!("Unsupported DATABASE_URL: {database_url}"));
+    }
+    let mongo_url = std::env::var("MONGODB_URI")?;
     let client = Client::with_uri_str(mongo_url).await?;
     let database = client.database(CONTENT_DATABASE);
+    storage::migrations::run(&database, migrations_dry_run).await?;
     let movies_collection: Collection<Movie> = database.collection(MOVIES);
     let serials_collection: Collection<Serial> = database.collection(SERIALS);
-    let storage = storage::Storage::new(movies_collection, serials_collection);
-    let tmdb_client = tmdb::Tmdb::new(tmdb_token)?;
-    telegram::run(storage, tmdb_client).await?;
-    Ok(())
+    let groups_collection: Collection<GroupSettings> = database.collection(GROUPS);
+    let group_films_collection: Collection<GroupFilm> = database.collection(GROUP_FILMS);
+    let posters_collection: Collection<storage::PosterRecord> = database.collection(POSTERS);
+    let storage = storage::MongoStorage::new(
+        movies_collection,
+        serials_collection,
+        groups_collection,
+        group_films_collection,
+        posters_collection,
+    );
+    if migrations_dry_run {
+        return Ok(Arc::new(storage));
+    }
+    if let Err(e) = storage.ensure_indexes().await {
+        tracing::warn!(
+            "Failed to create unique watch list indexes, duplicate entries may exist: {e}"
+        );
+    }
+    Ok(Arc::new(storage))
+}
+
+/// TMDB client with the servers, language, retry and cache settings taken from the environment.
+///
+/// Responses are cached in memory, `TMDB_CACHE_STORAGE=mongo` adds a Mongo tier.
+/// `TMDB_BOOTSTRAP_CONFIGURATION=1` takes the image server and sizes from TMDB itself.
+async fn tmdb_client(token: String) -> Result<tmdb::Tmdb> {
+    let mut client = tmdb::Tmdb::new(token)?;
+    let capacity = match std::env::var("TMDB_CACHE_CAPACITY") {
+        Ok(capacity) => capacity.parse()?,
+        Err(_) => tmdb::DEFAULT_CACHE_CAPACITY,
+    };
+    let backend = std::env::var("TMDB_CACHE_STORAGE").unwrap_or_default();
+    let cache = match backend.as_str() {
+        "" | "memory" => cache::ResponseCache::in_memory(capacity),
+        "mongo" => {
+            let mongo_url = std::env::var("MONGODB_URI")?;
+            let mongo = Client::with_uri_str(mongo_url).await?;
+            let responses: Collection<cache::CachedResponse> =
+                mongo.database(CONTENT_DATABASE).collection(TMDB_CACHE);
+            tracing::info!("Using Mongo TMDB cache tier");
+            cache::ResponseCache::with_mongo(capacity, responses).await
+        }
+        _ => return Err(anyhow!("Unsupported TMDB_CACHE_STORAGE: {backend}")),
+    };
+    client = client.with_cache(cache);
+    if let Ok(base_url) = std::env::var("TMDB_BASE_URL") {
+        client = client.with_base_url(base_url);
+    }
+    if let Ok(language) = std::env::var("TMDB_LANGUAGE") {
+        client = client.with_language(language);
+    }
+    if let Ok(max_retries) = std::env::var("TMDB_MAX_RETRIES") {
+        client = client.with_retry_policy(tmdb::RetryPolicy {
+            max_retries: max_retries.parse()?,
+            ..Default::default()
+        });
+    }
+    if let Ok(max_in_flight) = std::env::var("TMDB_MAX_IN_FLIGHT") {
+        client = client.with_max_in_flight(max_in_flight.parse()?);
+    }
+    if std::env::var("TMDB_BOOTSTRAP_CONFIGURATION").is_ok_and(|v| v == "1" || v == "true") {
+        client = match client.clone().bootstrap_configuration().await {
+            Ok(configured) => configured,
+            Err(e) => {
+                tracing::warn!("Failed to load TMDB configuration, using default images: {e}");
+                client
+            }
+        };
+    }
+    // An explicit image server wins over the bootstrapped one, e.g. for a local mirror.
+    if let Ok(image_base_url) = std::env::var("TMDB_IMAGE_BASE_URL") {
+        client = client.with_image_base_url(image_base_url);
+    }
+    Ok(client)
+}
+
+/// Dialogue states live in memory unless `DIALOGUE_STORAGE=mongo` is set.
+async fn connect_dialogue_storage() -> Result<Arc<DialogueStorage>> {
+    let backend = std::env::var("DIALOGUE_STORAGE").unwrap_or_default();
+    match backend.as_str() {
+        "" | "memory" => Ok(DialogueStorage::in_memory()),
+        "mongo" => {
+            let ttl_hours = match std::env::var("DIALOGUE_TTL_HOURS") {
+                Ok(hours) => hours.parse()?,
+                Err(_) => DEFAULT_DIALOGUE_TTL_HOURS,
+            };
+            let ttl = Duration::from_secs(ttl_hours * 60 * 60);
+            let mongo_url = std::env::var("MONGODB_URI")?;
+            let client = Client::with_uri_str(mongo_url).await?;
+            let dialogues: Collection<DialogueRecord> =
+                client.database(CONTENT_DATABASE).collection(DIALOGUES);
+            tracing::info!("Using Mongo dialogue storage, states expire after {ttl_hours}h");
+            Ok(DialogueStorage::mongo(dialogues, ttl).await)
+        }
+        _ => Err(anyhow!("Unsupported DIALOGUE_STORAGE: {backend}")),
+    }
 }
@@ -0,0 +1,139 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use super::TitleSnapshot;
+use crate::app::tmdb::escape_html;
+
+/// A member of a group chat, the name is kept for the shared list.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupMember {
+    pub user_id: u64,
+    pub name: String,
+}
+
+/// A member's rating of a film the group watched.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MemberRating {
+    pub user_id: u64,
+    pub name: String,
+    pub rating: f64,
+    pub rated_at: DateTime,
+}
+
+/// Settings of a group chat's shared watch list.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupSettings {
+    #[serde(rename = "_id")]
+    pub chat_id: i64,
+    /// The member who opened the list first.
+    pub owner: GroupMember,
+    /// Only the owner and the member who added a film may delete it.
+    #[serde(default)]
+    pub restrict_delete: bool,
+}
+impl GroupSettings {
+    pub fn new(chat_id: i64, owner: GroupMember) -> Self {
+        Self {
+            chat_id,
+            owner,
+            restrict_delete: false,
+        }
+    }
+    pub fn is_owner(&self, user_id: u64) -> bool {
+        self.owner.user_id == user_id
+    }
+    pub fn can_delete(&self, user_id: u64, film: &GroupFilm) -> bool {
+        !self.restrict_delete || self.is_owner(user_id) || film.added_by.user_id == user_id
+    }
+}
+
+/// A film on the shared watch list of a group chat.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupFilm {
+    #[serde(rename = "_id")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub chat_id: i64,
+    pub film_id: i64,
+    pub added_by: GroupMember,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<DateTime>,
+    pub watched: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watched_at: Option<DateTime>,
+    /// Members who were at the screening.
+    #[serde(default)]
+    pub attendees: Vec<GroupMember>,
+    #[serde(default)]
+    pub ratings: Vec<MemberRating>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<TitleSnapshot>,
+}
+impl GroupFilm {
+    pub fn new(chat_id: i64, film_id: i64, added_by: GroupMember, snapshot: TitleSnapshot) -> Self {
+        Self {
+            id: None,
+            chat_id,
+            film_id,
+            added_by,
+            added_at: Some(DateTime::now()),
+            watched: false,
+            watched_at: None,
+            attendees: Vec::new(),
+            ratings: Vec::new(),
+            snapshot: Some(snapshot),
+        }
+    }
+    pub fn watch(&mut self, member: GroupMember) {
+        self.watched = true;
+        self.watched_at = Some(DateTime::now());
+        self.attend(member);
+    }
+    pub fn attend(&mut self, member: GroupMember) {
+        if !self.attendees.iter().any(|a| a.user_id == member.user_id) {
+            self.attendees.push(member);
+        }
+    }
+    /// Records the member's rating, rating a film means having seen it.
+    pub fn rate(&mut self, member: GroupMember, rating: f64) {
+        self.ratings.retain(|r| r.user_id != member.user_id);
+        self.ratings.push(MemberRating {
+            user_id: member.user_id,
+            name: member.name.clone(),
+            rating,
+            rated_at: DateTime::now(),
+        });
+        self.attend(member);
+    }
+    pub fn average_rating(&self) -> Option<f64> {
+        if self.ratings.is_empty() {
+            return None;
+        }
+        let sum: f64 = self.ratings.iter().map(|r| r.rating).sum();
+        Some(sum / self.ratings.len() as f64)
+    }
+    /// Who added the film, who watched it and how they rated it, as HTML.
+    pub fn summary(&self) -> String {
+        let mut text = format!("\n👤 Добавил(а): {}", escape_html(&self.added_by.name));
+        if !self.attendees.is_empty() {
+            let names: Vec<String> = self
+                .attendees
+                .iter()
+                .map(|a| escape_html(&a.name))
+                .collect();
+            text.push_str(&format!("\n🍿 Смотрели: {}", names.join(", ")));
+        }
+        if !self.ratings.is_empty() {
+            let ratings: Vec<String> = self
+                .ratings
+                .iter()
+                .map(|r| format!("{} — {}", escape_html(&r.name), r.rating))
+                .collect();
+            text.push_str(&format!("\n⭐ Оценки: {}", ratings.join(", ")));
+        }
+        if let Some(average) = self.average_rating() {
+            text.push_str(&format!("\n📊 Средняя оценка группы: {average:.2}"));
+        }
+        text
+    }
+}
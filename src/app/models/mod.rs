@@ -1,4 +1,16 @@
+mod group;
+pub use group::{GroupFilm, GroupMember, GroupSettings, MemberRating};
 mod movie;
-pub use movie::Movie;
+pub use movie::{Movie, ReleaseWatch};
 mod serial;
-pub use serial::Serial;
+pub use serial::{EpisodeNumber, SeasonRating, Serial};
+mod snapshot;
+pub use snapshot::TitleSnapshot;
+mod viewing;
+pub use viewing::Viewing;
+
+use mongodb::bson::DateTime;
+
+pub fn format_date(date: DateTime) -> String {
+    date.to_chrono().format("%d.%m.%Y").to_string()
+}
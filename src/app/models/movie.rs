@@ -1,6 +1,29 @@
-use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{DateTime, oid::ObjectId};
 use serde::{Deserialize, Serialize};
 
+use super::{TitleSnapshot, Viewing};
+use crate::app::releases;
+
+/// An upcoming film the user waits for, and which of its releases were announced.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReleaseWatch {
+    /// ISO 3166-1 code of the country whose release dates count.
+    pub region: String,
+    #[serde(default)]
+    pub theatrical_notified: bool,
+    #[serde(default)]
+    pub digital_notified: bool,
+}
+impl ReleaseWatch {
+    pub fn new(region: &str) -> Self {
+        Self {
+            region: region.to_string(),
+            theatrical_notified: false,
+            digital_notified: false,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Movie {
     #[serde(rename = "_id")]
@@ -8,9 +31,21 @@ pub struct Movie {
     pub id: Option<ObjectId>,
     pub user_id: u64,
     pub film_id: i64,
-    pub watched: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub my_rating: Option<f64>,
+    #[serde(flatten)]
+    pub viewing: Viewing,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_watch: Option<ReleaseWatch>,
+    /// Release region of the user who listed the film, release alerts that
+    /// start on a later snapshot refresh use it too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<TitleSnapshot>,
+    /// When refreshing the snapshot last failed, the title then waits for a later round.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_failed_at: Option<DateTime>,
 }
 impl Movie {
     pub fn new(user_id: u64, film_id: i64) -> Self {
@@ -18,8 +53,36 @@ impl Movie {
             id: None,
             user_id,
             film_id,
-            watched: false,
-            my_rating: None,
+            viewing: Viewing::default(),
+            added_at: Some(DateTime::now()),
+            release_watch: None,
+            region: None,
+            snapshot: None,
+            snapshot_failed_at: None,
+        }
+    }
+    /// Whether the snapshot is missing or stale and no refresh failed since `older_than`.
+    ///
+    /// Snapshots without the release status are due too, refreshing them
+    /// starts release alerts for films that were listed before they existed.
+    pub fn snapshot_due(&self, older_than: DateTime) -> bool {
+        self.snapshot
+            .as_ref()
+            .is_none_or(|s| s.is_stale(older_than) || s.status.is_none())
+            && self.snapshot_failed_at.is_none_or(|t| t < older_than)
+    }
+    /// Starts waiting for the release of an unwatched film that isn't out by `today`.
+    ///
+    /// Every path that stores a film snapshot goes through here,
+    /// so films whose release date moves later get a watch as well.
+    /// Films listed before the region was kept fall back to the default one.
+    pub fn track_release(&mut self, today: &str) {
+        if self.viewing.watched || self.release_watch.is_some() {
+            return;
+        }
+        if self.snapshot.as_ref().is_some_and(|s| s.is_upcoming(today)) {
+            let region = self.region.as_deref().unwrap_or(releases::DEFAULT_REGION);
+            self.release_watch = Some(ReleaseWatch::new(region));
         }
     }
 }
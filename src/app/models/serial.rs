@@ -1,15 +1,58 @@
-use mongodb::bson::oid::ObjectId;
+use std::fmt::Display;
+
+use mongodb::bson::{DateTime, oid::ObjectId};
 use serde::{Deserialize, Serialize};
 
+use super::{TitleSnapshot, Viewing};
+
+/// Season and episode pair, ordered by season first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EpisodeNumber {
+    pub season: i64,
+    pub episode: i64,
+}
+impl Display for EpisodeNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S{:02}E{:02}", self.season, self.episode)
+    }
+}
+
+/// Rating of a single season on the 10-point scale.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonRating {
+    pub season: i64,
+    pub rating: f64,
+    pub rated_at: DateTime,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Serial {
     #[serde(rename = "_id")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub user_id: u64,
     pub serial_id: i64,
-    pub watched: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub my_rating: Option<f64>,
+    #[serde(flatten)]
+    pub viewing: Viewing,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<DateTime>,
+    /// Ratings of separate seasons, ordered by season.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub season_ratings: Vec<SeasonRating>,
+    /// Whether to send a reminder when a new episode airs.
+    #[serde(default)]
+    pub reminders: bool,
+    /// Last episode a reminder was sent for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminded: Option<EpisodeNumber>,
+    /// Last episode the user has seen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<EpisodeNumber>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<TitleSnapshot>,
+    /// When refreshing the snapshot last failed, the title then waits for a later round.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_failed_at: Option<DateTime>,
 }
 
 impl Serial {
@@ -18,8 +61,90 @@ impl Serial {
             id: None,
             user_id,
             serial_id,
-            watched: false,
-            my_rating: None,
+            viewing: Viewing::default(),
+            added_at: Some(DateTime::now()),
+            season_ratings: Vec::new(),
+            reminders: false,
+            reminded: None,
+            progress: None,
+            snapshot: None,
+            snapshot_failed_at: None,
+        }
+    }
+    /// Whether the snapshot is missing or stale and no refresh failed since `older_than`.
+    pub fn snapshot_due(&self, older_than: DateTime) -> bool {
+        self.snapshot
+            .as_ref()
+            .is_none_or(|s| s.is_stale(older_than))
+            && self.snapshot_failed_at.is_none_or(|t| t < older_than)
+    }
+    /// Clears the watch state together with the progress, a finished progress
+    /// would otherwise mark the serial watched again on the next update.
+    pub fn unwatch(&mut self) {
+        self.viewing.unwatch();
+        self.progress = None;
+    }
+    pub fn rate_season(&mut self, season: i64, rate: f64) {
+        self.season_ratings.retain(|r| r.season != season);
+        self.season_ratings.push(SeasonRating {
+            season,
+            rating: rate,
+            rated_at: DateTime::now(),
+        });
+        self.season_ratings.sort_by_key(|r| r.season);
+    }
+    /// Average of the season ratings.
+    pub fn season_average(&self) -> Option<f64> {
+        if self.season_ratings.is_empty() {
+            return None;
         }
+        let sum: f64 = self.season_ratings.iter().map(|r| r.rating).sum();
+        Some(sum / self.season_ratings.len() as f64)
+    }
+    /// The rating set for the whole serial, otherwise derived from the season ratings.
+    pub fn overall_rating(&self) -> Option<f64> {
+        self.viewing.my_rating.or_else(|| self.season_average())
     }
+    /// "📀 Сезон 1: 8 · Сезон 2: 6.5" line for watched-list cards.
+    pub fn season_ratings_line(&self) -> Option<String> {
+        if self.season_ratings.is_empty() {
+            return None;
+        }
+        let ratings: Vec<String> = self
+            .season_ratings
+            .iter()
+            .map(|r| format!("Сезон {}: {}", r.season, r.rating))
+            .collect();
+        Some(format!("📀 {}", ratings.join(" · ")))
+    }
+    /// Episodes the user has seen: up to the progress mark, or every aired
+    /// episode of a watched serial.
+    pub fn episodes_seen(&self, snapshot: &TitleSnapshot) -> i64 {
+        match self.progress {
+            Some(progress) => snapshot.episodes_up_to(progress),
+            None if self.viewing.watched => snapshot
+                .aired_episodes()
+                .unwrap_or_else(|| snapshot.seasons.iter().map(|s| s.episode_count).sum()),
+            None => 0,
+        }
+    }
+    /// "📺 S02E05 ▓▓▓░░░░░░░ 12/40" line for serial cards.
+    pub fn progress_line(&self, snapshot: &TitleSnapshot) -> Option<String> {
+        let progress = self.progress?;
+        let mut line = format!("📺 {progress}");
+        if let Some(aired) = snapshot.aired_episodes()
+            && aired > 0
+        {
+            let seen = snapshot.episodes_up_to(progress).min(aired);
+            line.push_str(&format!(" {} {seen}/{aired}", progress_bar(seen, aired)));
+        }
+        Some(line)
+    }
+}
+
+const PROGRESS_BAR_WIDTH: i64 = 10;
+
+fn progress_bar(seen: i64, total: i64) -> String {
+    let filled = (seen * PROGRESS_BAR_WIDTH / total).clamp(0, PROGRESS_BAR_WIDTH);
+    "▓".repeat(filled as usize) + &"░".repeat((PROGRESS_BAR_WIDTH - filled) as usize)
 }
@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::EpisodeNumber;
+use crate::app::tmdb::{FilmDetails, TVShowDetails, escape_html};
+
+/// Episode count of a serial season.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonSummary {
+    pub season_number: i64,
+    pub episode_count: i64,
+}
+
+/// Denormalized TMDB metadata kept inside watch list records,
+/// so list views can be rendered without calling the API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TitleSnapshot {
+    pub title: String,
+    pub original_title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imdb_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub year: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster_path: Option<String>,
+    /// Film runtime or a typical episode runtime, in minutes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<i64>,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    /// ISO 3166-1 codes of the production countries.
+    #[serde(default)]
+    pub countries: Vec<String>,
+    pub vote_average: f64,
+    /// Serial seasons, specials excluded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seasons: Vec<SeasonSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_aired: Option<EpisodeNumber>,
+    /// Film release date as `YYYY-MM-DD`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_date: Option<String>,
+    /// Film production status, missing in snapshots taken before it was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    pub refreshed_at: DateTime,
+}
+impl TitleSnapshot {
+    pub fn is_stale(&self, older_than: DateTime) -> bool {
+        self.refreshed_at < older_than
+    }
+    /// How many episodes come before and including `episode`.
+    pub fn episodes_up_to(&self, episode: EpisodeNumber) -> i64 {
+        let previous: i64 = self
+            .seasons
+            .iter()
+            .filter(|s| s.season_number < episode.season)
+            .map(|s| s.episode_count)
+            .sum();
+        previous + episode.episode
+    }
+    pub fn aired_episodes(&self) -> Option<i64> {
+        self.last_aired.map(|last| self.episodes_up_to(last))
+    }
+    /// Whether the film is still in production or releases after `today` (`YYYY-MM-DD`).
+    pub fn is_upcoming(&self, today: &str) -> bool {
+        matches!(
+            self.status.as_deref(),
+            Some("Rumored" | "Planned" | "In Production" | "Post Production")
+        ) || self
+            .release_date
+            .as_deref()
+            .is_some_and(|date| date > today)
+    }
+}
+fn year(date: &str) -> Option<i32> {
+    date.split('-').next()?.parse().ok()
+}
+fn poster_path(path: &str) -> Option<String> {
+    (!path.is_empty()).then(|| path.to_string())
+}
+impl From<&FilmDetails> for TitleSnapshot {
+    fn from(film: &FilmDetails) -> Self {
+        Self {
+            title: film.title.clone(),
+            original_title: film.original_title.clone(),
+            imdb_id: (!film.imdb_id.is_empty()).then(|| film.imdb_id.clone()),
+            year: year(&film.release_date),
+            poster_path: poster_path(&film.poster_path),
+            runtime: (film.runtime > 0).then_some(film.runtime),
+            genres: film.genres.iter().map(|g| g.name.clone()).collect(),
+            countries: film
+                .production_countries
+                .iter()
+                .map(|c| c.iso_3166_1.clone())
+                .collect(),
+            vote_average: film.vote_average,
+            seasons: Vec::new(),
+            last_aired: None,
+            release_date: (!film.release_date.is_empty()).then(|| film.release_date.clone()),
+            status: Some(film.status.clone()),
+            refreshed_at: DateTime::now(),
+        }
+    }
+}
+/// Last aired episode of a serial, specials excluded.
+fn last_aired(tv_show: &TVShowDetails) -> Option<EpisodeNumber> {
+    let last = &tv_show.last_episode_to_air;
+    (last.season_number > 0 && last.episode_number > 0).then_some(EpisodeNumber {
+        season: last.season_number,
+        episode: last.episode_number,
+    })
+}
+impl From<&TVShowDetails> for TitleSnapshot {
+    fn from(tv_show: &TVShowDetails) -> Self {
+        Self {
+            title: tv_show.name.clone(),
+            original_title: tv_show.original_name.clone(),
+            imdb_id: tv_show
+                .external_ids
+                .as_ref()
+                .and_then(|ids| ids.imdb_id.clone())
+                .filter(|id| !id.is_empty()),
+            year: year(&tv_show.first_air_date),
+            poster_path: poster_path(&tv_show.poster_path),
+            runtime: tv_show.episode_run_time.first().copied(),
+            genres: tv_show.genres.iter().map(|g| g.name.clone()).collect(),
+            countries: if tv_show.production_countries.is_empty() {
+                tv_show.origin_country.clone()
+            } else {
+                tv_show
+                    .production_countries
+                    .iter()
+                    .map(|c| c.iso_3166_1.clone())
+                    .collect()
+            },
+            vote_average: tv_show.vote_average,
+            seasons: tv_show
+                .seasons
+                .iter()
+                .filter(|s| s.season_number > 0)
+                .map(|s| SeasonSummary {
+                    season_number: s.season_number,
+                    episode_count: s.episode_count,
+                })
+                .collect(),
+            last_aired: last_aired(tv_show),
+            release_date: None,
+            status: None,
+            refreshed_at: DateTime::now(),
+        }
+    }
+}
+impl Display for TitleSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<b>{}</b>", escape_html(&self.title))?;
+        if let Some(year) = self.year {
+            write!(f, " ({year})")?;
+        }
+        if self.original_title != self.title {
+            write!(f, "\n<i>{}</i>", escape_html(&self.original_title))?;
+        }
+        if !self.genres.is_empty() {
+            let genres: Vec<String> = self.genres.iter().map(|g| escape_html(g)).collect();
+            write!(f, "\n🎭 {}", genres.join(" | "))?;
+        }
+        if let Some(runtime) = self.runtime {
+            write!(f, "\n⏱️ {runtime} мин")?;
+        }
+        if self.vote_average > 0.0 {
+            write!(f, "\n⭐ {:.1}", self.vote_average)?;
+        }
+        Ok(())
+    }
+}
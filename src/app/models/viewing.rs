@@ -0,0 +1,101 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::format_date;
+
+/// Whether and when the user watched a title and how they rated it.
+///
+/// Films and serials keep it flattened into their records,
+/// so the stored fields are the same as before it was shared.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Viewing {
+    pub watched: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub my_rating: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watched_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rated_at: Option<DateTime>,
+    /// Every time the title was marked watched, oldest first.
+    #[serde(default)]
+    pub watch_history: Vec<DateTime>,
+}
+impl Viewing {
+    pub fn watch(&mut self) {
+        let now = DateTime::now();
+        self.watched = true;
+        self.watched_at = Some(now);
+        self.watch_history.push(now);
+    }
+    pub fn unwatch(&mut self) {
+        self.watched = false;
+        self.my_rating = None;
+        self.watched_at = None;
+        self.rated_at = None;
+    }
+    pub fn rate(&mut self, rate: f64) {
+        self.my_rating = Some(rate);
+        self.rated_at = Some(DateTime::now());
+    }
+    /// Takes the viewings and the rating of an imported copy of the same title,
+    /// leaving what the import doesn't have as it was.
+    pub fn merge_imported(&mut self, imported: &Self) {
+        if imported.watched {
+            self.watched = true;
+            self.watched_at = imported.watched_at.or(self.watched_at);
+            for date in &imported.watch_history {
+                if !self.watch_history.contains(date) {
+                    self.watch_history.push(*date);
+                }
+            }
+            self.watch_history.sort();
+        }
+        if imported.my_rating.is_some() {
+            self.my_rating = imported.my_rating;
+            self.rated_at = imported.rated_at.or(self.rated_at);
+        }
+    }
+    /// Whether the import carries anything to take over, see `merge_imported`.
+    pub fn has_progress(&self) -> bool {
+        self.watched || self.my_rating.is_some()
+    }
+    /// Human readable "watched on …" line for watched-list cards.
+    pub fn watched_on(&self) -> Option<String> {
+        let date = self
+            .watched_at
+            .or_else(|| self.watch_history.last().copied())?;
+        let mut text = format!("Просмотрено {}", format_date(date));
+        let times = self.watch_history.len();
+        if times > 1 {
+            let word = if (2..=4).contains(&times) {
+                "раза"
+            } else {
+                "раз"
+            };
+            text.push_str(&format!(" (смотрели {times} {word})"));
+        }
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson;
+
+    use crate::app::models::Movie;
+
+    #[test]
+    fn records_keep_the_viewing_fields_at_the_top_level() {
+        let mut movie = Movie::new(1, 550);
+        movie.viewing.watch();
+        movie.viewing.rate(8.0);
+        let document = bson::to_document(&movie).unwrap();
+        assert_eq!(document.get_bool("watched"), Ok(true));
+        assert_eq!(document.get_f64("my_rating"), Ok(8.0));
+        assert_eq!(document.get_array("watch_history").unwrap().len(), 1);
+
+        let movie: Movie = bson::from_slice(&bson::to_vec(&document).unwrap()).unwrap();
+        assert_eq!(movie.viewing.my_rating, Some(8.0));
+        assert!(movie.viewing.watched_at.is_some());
+    }
+}
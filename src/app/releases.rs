@@ -0,0 +1,148 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Result;
+use mongodb::bson::DateTime;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardMarkup, ParseMode},
+};
+use tracing::instrument;
+
+use crate::app::{
+    models::{Movie, ReleaseWatch},
+    storage::Storage,
+    telegram::MyCallback,
+    tmdb::{
+        DIGITAL_RELEASE, ReleaseDatesResponse, THEATRICAL_LIMITED_RELEASE, THEATRICAL_RELEASE,
+        Tmdb, escape_html,
+    },
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Region of release alerts when the user's language is unknown.
+pub const DEFAULT_REGION: &str = "RU";
+
+/// Today's date as `YYYY-MM-DD`, the format of TMDB release dates.
+pub fn today() -> String {
+    DateTime::now().to_chrono().format("%Y-%m-%d").to_string()
+}
+
+/// Release region for a Telegram language code such as `ru` or `en-US`.
+pub fn region(language_code: Option<&str>) -> String {
+    let Some(code) = language_code else {
+        return DEFAULT_REGION.to_string();
+    };
+    if let Some((_, country)) = code.split_once(['-', '_']) {
+        return country.to_uppercase();
+    }
+    match code {
+        "en" => "US",
+        "uk" => "UA",
+        "be" => "BY",
+        "kk" => "KZ",
+        "ru" | "" => DEFAULT_REGION,
+        other => return other.to_uppercase(),
+    }
+    .to_string()
+}
+
+/// Periodically notifies users when films they wait for reach cinemas or digital stores.
+///
+/// Which releases were already announced is stored with every film,
+/// so restarts neither lose nor repeat alerts.
+#[instrument(name = "release alerts", skip_all)]
+pub async fn run(storage: Storage, tmdb_client: Tmdb, bot: Bot) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = check(&storage, &tmdb_client, &bot).await {
+            tracing::warn!("Failed to send release alerts: {e}");
+        }
+    }
+}
+
+async fn check(storage: &Storage, tmdb_client: &Tmdb, bot: &Bot) -> Result<()> {
+    let mut by_film: BTreeMap<i64, Vec<Movie>> = BTreeMap::new();
+    for movie in storage.films_awaiting_release().await? {
+        by_film.entry(movie.film_id).or_default().push(movie);
+    }
+    let today = today();
+    for (id, movies) in by_film {
+        let release_dates = match tmdb_client.get_release_dates(id).await {
+            Ok(release_dates) => release_dates,
+            Err(e) => {
+                tracing::warn!("Failed to check releases of film {id}: {e}");
+                continue;
+            }
+        };
+        for movie in movies {
+            let Some(watch) = movie.release_watch.clone() else {
+                continue;
+            };
+            let title = movie
+                .snapshot
+                .as_ref()
+                .map_or_else(|| format!("Фильм {id}"), |s| s.title.clone());
+            let updated = notify(bot, &movie, &title, watch, &release_dates, &today).await?;
+            if let Some(updated) = updated {
+                let done = updated.theatrical_notified && updated.digital_notified;
+                storage
+                    .set_film_release_watch(movie.user_id, id, (!done).then_some(updated))
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends the alerts that are due and returns the watch to store, if anything was sent.
+async fn notify(
+    bot: &Bot,
+    movie: &Movie,
+    title: &str,
+    mut watch: ReleaseWatch,
+    release_dates: &ReleaseDatesResponse,
+    today: &str,
+) -> Result<Option<ReleaseWatch>> {
+    let released = |types: &[i64]| {
+        release_dates
+            .earliest(&watch.region, types)
+            .filter(|date| date.as_str() <= today)
+    };
+    let mut lines = Vec::new();
+    if !watch.theatrical_notified
+        && let Some(date) = released(&[THEATRICAL_LIMITED_RELEASE, THEATRICAL_RELEASE])
+    {
+        lines.push(format!("🍿 В кино с {date}"));
+        watch.theatrical_notified = true;
+    }
+    if !watch.digital_notified
+        && let Some(date) = released(&[DIGITAL_RELEASE])
+    {
+        lines.push(format!("💻 В цифре с {date}"));
+        watch.digital_notified = true;
+    }
+    if lines.is_empty() {
+        return Ok(None);
+    }
+    let id = movie.film_id;
+    let text = format!(
+        "🎬 <b>{title}</b> вышел!\n{lines}",
+        title = escape_html(title),
+        lines = lines.join("\n")
+    );
+    let mu = InlineKeyboardMarkup::default().append_row(vec![
+        MyCallback::GetFilmsDetails { id }.into(),
+        MyCallback::MarkFilmWatched { id }.into(),
+    ]);
+    let sent = bot
+        .send_message(ChatId(movie.user_id as i64), text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(mu)
+        .await;
+    if let Err(e) = sent {
+        tracing::warn!("Failed to alert user {u}: {e}", u = movie.user_id);
+        return Ok(None);
+    }
+    Ok(Some(watch))
+}
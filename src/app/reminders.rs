@@ -0,0 +1,104 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Result;
+use mongodb::bson::DateTime;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardMarkup, ParseMode},
+};
+use tracing::instrument;
+
+use crate::app::{
+    models::{EpisodeNumber, Serial},
+    storage::Storage,
+    telegram::MyCallback,
+    tmdb::{TVShowDetails, Tmdb, escape_html},
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically notifies users about episodes of their serials airing today.
+///
+/// The last notified episode is stored with every serial, so restarts
+/// neither lose nor repeat reminders.
+#[instrument(name = "episode reminders", skip_all)]
+pub async fn run(storage: Storage, tmdb_client: Tmdb, bot: Bot) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = check(&storage, &tmdb_client, &bot).await {
+            tracing::warn!("Failed to send episode reminders: {e}");
+        }
+    }
+}
+
+async fn check(storage: &Storage, tmdb_client: &Tmdb, bot: &Bot) -> Result<()> {
+    let mut by_serial: BTreeMap<i64, Vec<Serial>> = BTreeMap::new();
+    for serial in storage.serials_with_reminders().await? {
+        by_serial.entry(serial.serial_id).or_default().push(serial);
+    }
+    let today = DateTime::now().to_chrono().format("%Y-%m-%d").to_string();
+    for (id, serials) in by_serial {
+        let tv_show = match tmdb_client.get_tv_show_details(id).await {
+            Ok(tv_show) => tv_show,
+            Err(e) => {
+                tracing::warn!("Failed to check serial {id}: {e}");
+                continue;
+            }
+        };
+        let Some((episode, name)) = airing_today(&tv_show, &today) else {
+            continue;
+        };
+        for serial in serials {
+            if serial.reminded.is_some_and(|reminded| reminded >= episode) {
+                continue;
+            }
+            let text = format!(
+                "🔔 <b>{title}</b>\n{episode} «{name}» выходит сегодня",
+                title = escape_html(&tv_show.name),
+                name = escape_html(name),
+            );
+            let mu = InlineKeyboardMarkup::default()
+                .append_row(vec![
+                    MyCallback::GetSerialDetails { id }.into(),
+                    MyCallback::ShowSeasons { id }.into(),
+                ])
+                .append_row(vec![
+                    MyCallback::SerialReminders { id, enabled: false }.into(),
+                ]);
+            let sent = bot
+                .send_message(ChatId(serial.user_id as i64), text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(mu)
+                .await;
+            if let Err(e) = sent {
+                tracing::warn!("Failed to remind user {u}: {e}", u = serial.user_id);
+                continue;
+            }
+            storage
+                .set_serial_reminded(serial.user_id, id, episode)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The episode airing on `today`, TMDB may already list it as the last aired one.
+fn airing_today<'a>(tv_show: &'a TVShowDetails, today: &str) -> Option<(EpisodeNumber, &'a str)> {
+    let next = tv_show
+        .next_episode_to_air
+        .as_ref()
+        .map(|e| (e.season_number, e.episode_number, &e.air_date, &e.name));
+    let last = &tv_show.last_episode_to_air;
+    let last = Some((
+        last.season_number,
+        last.episode_number,
+        &last.air_date,
+        &last.name,
+    ));
+    [next, last]
+        .into_iter()
+        .flatten()
+        .find(|(_, _, air_date, _)| air_date.as_str() == today)
+        .map(|(season, episode, _, name)| (EpisodeNumber { season, episode }, name.as_str()))
+}
@@ -0,0 +1,258 @@
+use std::{collections::HashMap, fmt::Display, io::Cursor};
+
+use anyhow::Result;
+use image::{ImageFormat, RgbImage, imageops};
+use mongodb::bson::DateTime;
+use tracing::instrument;
+
+use crate::app::{
+    snapshots::{film_snapshot, serial_snapshot},
+    stats::{hours, top},
+    storage::Storage,
+    tmdb::{ImageKind, Tmdb, escape_html},
+};
+
+/// How many titles are listed among the best rated.
+const TOP_SIZE: usize = 5;
+const MAX_POSTERS: usize = 12;
+const COLLAGE_COLUMNS: usize = 4;
+const TILE_WIDTH: u32 = 200;
+const TILE_HEIGHT: u32 = 300;
+
+/// A title watched during the reviewed year.
+#[derive(Clone, Debug)]
+pub struct ReviewedTitle {
+    pub title: String,
+    pub year: Option<i32>,
+    pub rating: Option<f64>,
+    pub poster_path: Option<String>,
+}
+impl Display for ReviewedTitle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", escape_html(&self.title))?;
+        if let Some(year) = self.year {
+            write!(f, " ({year})")?;
+        }
+        if let Some(rating) = self.rating {
+            write!(f, " — ⭐ {rating:.1}")?;
+        }
+        Ok(())
+    }
+}
+
+/// "Итоги года": what the user watched during one calendar year.
+#[derive(Clone, Debug, Default)]
+pub struct YearReview {
+    pub year: i32,
+    pub films: Vec<ReviewedTitle>,
+    pub serials: Vec<ReviewedTitle>,
+    /// Only films count: episodes carry no watch dates, so a serial's time can't be split by year.
+    pub film_minutes: i64,
+    pub genres: HashMap<String, u64>,
+    pub directors: HashMap<String, u64>,
+}
+
+/// The year to review: the previous one during January, the current one otherwise.
+pub fn review_year(now: DateTime) -> i32 {
+    let now = now.to_chrono();
+    let year: i32 = now.format("%Y").to_string().parse().unwrap_or_default();
+    if now.format("%m").to_string() == "01" {
+        year - 1
+    } else {
+        year
+    }
+}
+
+fn year_of(date: DateTime) -> i32 {
+    date.to_chrono()
+        .format("%Y")
+        .to_string()
+        .parse()
+        .unwrap_or_default()
+}
+
+/// Viewings during `year`, a title without history counts once by its watched date.
+fn views_in(year: i32, watched_at: Option<DateTime>, history: &[DateTime]) -> i64 {
+    if history.is_empty() {
+        return watched_at.is_some_and(|d| year_of(d) == year).into();
+    }
+    history.iter().filter(|d| year_of(**d) == year).count() as i64
+}
+
+impl YearReview {
+    #[instrument(name = "build year review", skip(storage, tmdb_client))]
+    pub async fn build(
+        storage: &Storage,
+        tmdb_client: &Tmdb,
+        user_id: u64,
+        year: i32,
+    ) -> Result<Self> {
+        let mut review = Self {
+            year,
+            ..Default::default()
+        };
+        for movie in storage.get_users_watched_movies_list(user_id).await? {
+            let views = views_in(year, movie.viewing.watched_at, &movie.viewing.watch_history);
+            if views == 0 {
+                continue;
+            }
+            let snapshot = film_snapshot(storage, tmdb_client, &movie).await?;
+            review.film_minutes += snapshot.runtime.unwrap_or_default() * views;
+            review.add_genres(&snapshot.genres);
+            match tmdb_client.get_films_credits(movie.film_id).await {
+                Ok(credits) => {
+                    for director in credits.crew.iter().filter(|c| c.job == "Director") {
+                        *review.directors.entry(director.name.clone()).or_default() += 1;
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to get credits of film {}: {e}", movie.film_id),
+            }
+            review.films.push(ReviewedTitle {
+                title: snapshot.title,
+                year: snapshot.year,
+                rating: movie.viewing.my_rating,
+                poster_path: snapshot.poster_path,
+            });
+        }
+        for serial in storage.get_users_watched_serials_list(user_id).await? {
+            if views_in(
+                year,
+                serial.viewing.watched_at,
+                &serial.viewing.watch_history,
+            ) == 0
+            {
+                continue;
+            }
+            let snapshot = serial_snapshot(storage, tmdb_client, &serial).await?;
+            review.add_genres(&snapshot.genres);
+            review.serials.push(ReviewedTitle {
+                title: snapshot.title,
+                year: snapshot.year,
+                rating: serial.overall_rating(),
+                poster_path: snapshot.poster_path,
+            });
+        }
+        Ok(review)
+    }
+    fn add_genres(&mut self, genres: &[String]) {
+        for genre in genres {
+            *self.genres.entry(genre.clone()).or_default() += 1;
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.films.is_empty() && self.serials.is_empty()
+    }
+    /// Highest rated films and serials together.
+    pub fn best_rated(&self) -> Vec<&ReviewedTitle> {
+        let mut rated: Vec<&ReviewedTitle> = self
+            .films
+            .iter()
+            .chain(&self.serials)
+            .filter(|t| t.rating.is_some())
+            .collect();
+        rated.sort_by(|a, b| {
+            b.rating
+                .partial_cmp(&a.rating)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        rated.truncate(TOP_SIZE);
+        rated
+    }
+    /// Posters for the collage, best rated titles first.
+    fn poster_paths(&self) -> Vec<&str> {
+        let best = self.best_rated();
+        best.iter()
+            .copied()
+            .chain(self.films.iter().chain(&self.serials))
+            .filter_map(|t| t.poster_path.as_deref())
+            .fold(Vec::new(), |mut paths, path| {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+                paths
+            })
+            .into_iter()
+            .take(MAX_POSTERS)
+            .collect()
+    }
+    /// Renders the posters of the year into one PNG image.
+    #[instrument(name = "render year review collage", skip_all, fields(year = self.year))]
+    pub async fn collage(&self, tmdb_client: &Tmdb) -> Result<Option<Vec<u8>>> {
+        let mut posters = Vec::new();
+        for path in self.poster_paths() {
+            match tmdb_client
+                .get_image_bytes(path, ImageKind::CollageTile)
+                .await
+            {
+                Ok(bytes) => posters.push(bytes),
+                Err(e) => tracing::warn!("Failed to get poster {path}: {e}"),
+            }
+        }
+        if posters.is_empty() {
+            return Ok(None);
+        }
+        let png = tokio::task::spawn_blocking(move || render_collage(&posters)).await??;
+        Ok(Some(png))
+    }
+}
+
+fn render_collage(posters: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let tiles: Vec<RgbImage> = posters
+        .iter()
+        .filter_map(|bytes| image::load_from_memory(bytes).ok())
+        .map(|poster| {
+            imageops::resize(
+                &poster.to_rgb8(),
+                TILE_WIDTH,
+                TILE_HEIGHT,
+                imageops::FilterType::Triangle,
+            )
+        })
+        .collect();
+    let columns = tiles.len().clamp(1, COLLAGE_COLUMNS);
+    let rows = tiles.len().div_ceil(columns).max(1);
+    let mut canvas = RgbImage::new(TILE_WIDTH * columns as u32, TILE_HEIGHT * rows as u32);
+    for (i, tile) in tiles.iter().enumerate() {
+        let x = (i % columns) as i64 * i64::from(TILE_WIDTH);
+        let y = (i / columns) as i64 * i64::from(TILE_HEIGHT);
+        imageops::replace(&mut canvas, tile, x, y);
+    }
+    let mut png = Cursor::new(Vec::new());
+    canvas.write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+impl Display for YearReview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "🎉 <b>Итоги {} года</b>", self.year)?;
+        write!(f, "\n🎬 Фильмов просмотрено: {}", self.films.len())?;
+        write!(f, "\n📺 Сериалов досмотрено: {}", self.serials.len())?;
+        write!(
+            f,
+            "\n⏱️ Часов за фильмами: {} (сериалы не учитываются)",
+            hours(self.film_minutes)
+        )?;
+        let best = self.best_rated();
+        if !best.is_empty() {
+            write!(f, "\n\n<b>Лучшее за год</b>")?;
+            for (i, title) in best.iter().enumerate() {
+                write!(f, "\n{}. {title}", i + 1)?;
+            }
+        }
+        let genres = top(&self.genres);
+        if !genres.is_empty() {
+            write!(f, "\n\n<b>Любимые жанры</b>")?;
+            for (genre, count) in genres {
+                write!(f, "\n🎭 {} — {count}", escape_html(genre))?;
+            }
+        }
+        let directors = top(&self.directors);
+        if !directors.is_empty() {
+            write!(f, "\n\n<b>Любимые режиссёры</b>")?;
+            for (director, count) in directors {
+                write!(f, "\n🎬 {} — {count}", escape_html(director))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use mongodb::bson::DateTime;
+use tracing::instrument;
+
+use crate::app::{
+    models::{Movie, Serial, TitleSnapshot},
+    storage::Storage,
+    tmdb::Tmdb,
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+const MAX_SNAPSHOT_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const REFRESH_BATCH: usize = 50;
+
+/// Periodically refreshes snapshots that are missing or older than a week.
+#[instrument(name = "snapshot refresher", skip_all)]
+pub async fn run(storage: Storage, tmdb_client: Tmdb) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = refresh(&storage, &tmdb_client).await {
+            tracing::warn!("Failed to refresh snapshots: {e}");
+        }
+        tracing::info!("TMDB client: {}", tmdb_client.metrics());
+    }
+}
+
+async fn refresh(storage: &Storage, tmdb_client: &Tmdb) -> Result<()> {
+    let older_than = DateTime::from_millis(
+        DateTime::now().timestamp_millis() - MAX_SNAPSHOT_AGE.as_millis() as i64,
+    );
+    let film_ids = storage
+        .stale_film_snapshots(older_than, REFRESH_BATCH)
+        .await?;
+    for id in film_ids {
+        tmdb_client.invalidate_film(id).await;
+        match tmdb_client.get_films_details(id).await {
+            Ok(film) => {
+                storage
+                    .set_film_snapshot(id, TitleSnapshot::from(&film))
+                    .await?
+            }
+            Err(e) => {
+                tracing::warn!("Failed to refresh film {id}: {e}");
+                storage.defer_film_snapshot(id).await?;
+            }
+        }
+    }
+    let serial_ids = storage
+        .stale_serial_snapshots(older_than, REFRESH_BATCH)
+        .await?;
+    for id in serial_ids {
+        tmdb_client.invalidate_tv_show(id).await;
+        match tmdb_client.get_tv_show_details(id).await {
+            Ok(tv_show) => {
+                storage
+                    .set_serial_snapshot(id, TitleSnapshot::from(&tv_show))
+                    .await?
+            }
+            Err(e) => {
+                tracing::warn!("Failed to refresh serial {id}: {e}");
+                storage.defer_serial_snapshot(id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stored snapshot of the film, fetched and saved if the record predates snapshots.
+pub async fn film_snapshot(
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    movie: &Movie,
+) -> Result<TitleSnapshot> {
+    if let Some(snapshot) = &movie.snapshot {
+        return Ok(snapshot.clone());
+    }
+    let film = tmdb_client.get_films_details(movie.film_id).await?;
+    let snapshot = TitleSnapshot::from(&film);
+    storage
+        .set_film_snapshot(movie.film_id, snapshot.clone())
+        .await?;
+    Ok(snapshot)
+}
+
+/// Stored snapshot of the serial, fetched and saved if the record predates snapshots.
+pub async fn serial_snapshot(
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    serial: &Serial,
+) -> Result<TitleSnapshot> {
+    if let Some(snapshot) = &serial.snapshot {
+        return Ok(snapshot.clone());
+    }
+    let tv_show = tmdb_client.get_tv_show_details(serial.serial_id).await?;
+    let snapshot = TitleSnapshot::from(&tv_show);
+    storage
+        .set_serial_snapshot(serial.serial_id, snapshot.clone())
+        .await?;
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::app::{
+        mock_server::{MockResponse, MockServer},
+        releases,
+        storage::InMemoryStorage,
+    };
+
+    #[tokio::test]
+    async fn failed_refresh_waits_for_a_later_round() {
+        let tmdb_server = MockServer::start(|_| MockResponse::status(404)).await;
+        let tmdb_client = Tmdb::new("token".to_string())
+            .unwrap()
+            .with_base_url(tmdb_server.url());
+        let storage: Storage = Arc::new(InMemoryStorage::new());
+        storage
+            .import_film(Movie::new(1, 550), releases::DEFAULT_REGION.to_string())
+            .await
+            .unwrap();
+        let now = DateTime::now();
+        assert_eq!(storage.stale_film_snapshots(now, 10).await.unwrap(), [550]);
+
+        refresh(&storage, &tmdb_client).await.unwrap();
+        assert_eq!(tmdb_server.requests().len(), 1);
+        let older_than = DateTime::from_millis(
+            DateTime::now().timestamp_millis() - MAX_SNAPSHOT_AGE.as_millis() as i64,
+        );
+        assert!(
+            storage
+                .stale_film_snapshots(older_than, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_starts_release_alerts_for_listed_films() {
+        let tmdb_server = MockServer::start(|_| {
+            MockResponse::json(
+                json!({
+                    "adult": false,
+                    "backdrop_path": "",
+                    "belongs_to_collection": null,
+                    "budget": 0,
+                    "genres": [],
+                    "homepage": "",
+                    "id": 1001,
+                    "imdb_id": "",
+                    "origin_country": [],
+                    "original_language": "en",
+                    "original_title": "Sequel",
+                    "overview": "",
+                    "popularity": 1.0,
+                    "poster_path": "",
+                    "production_companies": [],
+                    "production_countries": [],
+                    "release_date": "",
+                    "revenue": 0,
+                    "runtime": 0,
+                    "spoken_languages": [],
+                    "status": "Post Production",
+                    "tagline": "",
+                    "title": "Сиквел",
+                    "video": false,
+                    "vote_average": 0.0,
+                    "vote_count": 0
+                })
+                .to_string(),
+            )
+        })
+        .await;
+        let tmdb_client = Tmdb::new("token".to_string())
+            .unwrap()
+            .with_base_url(tmdb_server.url());
+        let storage: Storage = Arc::new(InMemoryStorage::new());
+        storage
+            .import_film(Movie::new(1, 1001), "US".to_string())
+            .await
+            .unwrap();
+        assert!(storage.films_awaiting_release().await.unwrap().is_empty());
+
+        refresh(&storage, &tmdb_client).await.unwrap();
+        let awaiting = storage.films_awaiting_release().await.unwrap();
+        assert_eq!(awaiting.len(), 1);
+        assert_eq!(awaiting[0].release_watch.as_ref().unwrap().region, "US");
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+use crate::app::{
+    models::{Movie, Serial},
+    tmdb::escape_html,
+};
+
+/// How many genres and countries are listed.
+const TOP_SIZE: usize = 5;
+const BAR_WIDTH: u64 = 10;
+
+/// Viewing statistics of a user, computed from the snapshots kept in their lists.
+#[derive(Clone, Debug, Default)]
+pub struct UserStats {
+    pub films_watched: u64,
+    pub serials_watched: u64,
+    pub film_minutes: i64,
+    /// Seen episodes times the typical episode runtime.
+    pub serial_minutes: i64,
+    pub rating_sum: f64,
+    pub rating_count: u64,
+    /// Number of ratings per whole point, a rating of 7.5 counts as 7.
+    pub rating_distribution: BTreeMap<i64, u64>,
+    pub genres: HashMap<String, u64>,
+    pub countries: HashMap<String, u64>,
+    pub decades: BTreeMap<i32, u64>,
+}
+impl UserStats {
+    pub fn from_titles(movies: &[Movie], serials: &[Serial]) -> Self {
+        let mut stats = Self::default();
+        for movie in movies {
+            if !movie.viewing.watched {
+                continue;
+            }
+            stats.films_watched += 1;
+            if let Some(rating) = movie.viewing.my_rating {
+                stats.add_rating(rating);
+            }
+            if let Some(snapshot) = &movie.snapshot {
+                stats.film_minutes += snapshot.runtime.unwrap_or_default();
+                stats.add_metadata(&snapshot.genres, &snapshot.countries, snapshot.year);
+            }
+        }
+        for serial in serials {
+            if let Some(snapshot) = &serial.snapshot {
+                stats.serial_minutes +=
+                    serial.episodes_seen(snapshot) * snapshot.runtime.unwrap_or_default();
+            }
+            if !serial.viewing.watched {
+                continue;
+            }
+            stats.serials_watched += 1;
+            if let Some(rating) = serial.overall_rating() {
+                stats.add_rating(rating);
+            }
+            if let Some(snapshot) = &serial.snapshot {
+                stats.add_metadata(&snapshot.genres, &snapshot.countries, snapshot.year);
+            }
+        }
+        stats
+    }
+    fn add_rating(&mut self, rating: f64) {
+        self.rating_sum += rating;
+        self.rating_count += 1;
+        *self
+            .rating_distribution
+            .entry(rating.floor() as i64)
+            .or_default() += 1;
+    }
+    fn add_metadata(&mut self, genres: &[String], countries: &[String], year: Option<i32>) {
+        for genre in genres {
+            *self.genres.entry(genre.clone()).or_default() += 1;
+        }
+        for country in countries {
+            *self.countries.entry(country.clone()).or_default() += 1;
+        }
+        if let Some(year) = year {
+            *self.decades.entry(year.div_euclid(10) * 10).or_default() += 1;
+        }
+    }
+    /// Adds up statistics of films and serials computed separately.
+    pub fn merge(&mut self, other: UserStats) {
+        self.films_watched += other.films_watched;
+        self.serials_watched += other.serials_watched;
+        self.film_minutes += other.film_minutes;
+        self.serial_minutes += other.serial_minutes;
+        self.rating_sum += other.rating_sum;
+        self.rating_count += other.rating_count;
+        for (rating, count) in other.rating_distribution {
+            *self.rating_distribution.entry(rating).or_default() += count;
+        }
+        for (genre, count) in other.genres {
+            *self.genres.entry(genre).or_default() += count;
+        }
+        for (country, count) in other.countries {
+            *self.countries.entry(country).or_default() += count;
+        }
+        for (decade, count) in other.decades {
+            *self.decades.entry(decade).or_default() += count;
+        }
+    }
+    pub fn average_rating(&self) -> Option<f64> {
+        (self.rating_count > 0).then(|| self.rating_sum / self.rating_count as f64)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.films_watched == 0
+            && self.serials_watched == 0
+            && self.rating_count == 0
+            && self.serial_minutes == 0
+    }
+}
+
+/// Most frequent entries, ties broken alphabetically.
+pub fn top(counts: &HashMap<String, u64>) -> Vec<(&String, u64)> {
+    let mut top: Vec<(&String, u64)> = counts.iter().map(|(k, v)| (k, *v)).collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    top.truncate(TOP_SIZE);
+    top
+}
+
+fn bar(count: u64, max: u64) -> String {
+    let filled = (count * BAR_WIDTH).div_ceil(max.max(1)) as usize;
+    "▓".repeat(filled)
+}
+
+/// Regional indicator symbols of a two-letter country code.
+fn flag(code: &str) -> String {
+    if code.len() != 2 || !code.is_ascii() {
+        return String::new();
+    }
+    code.to_ascii_uppercase()
+        .bytes()
+        .filter_map(|b| char::from_u32(0x1F1E6 + u32::from(b - b'A')))
+        .collect()
+}
+
+/// Minutes as hours with one decimal.
+pub fn hours(minutes: i64) -> String {
+    format!("{:.1}", minutes as f64 / 60.0)
+}
+
+impl Display for UserStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "📊 <b>Ваша статистика</b>")?;
+        write!(f, "\n🎬 Фильмов просмотрено: {}", self.films_watched)?;
+        write!(f, "\n📺 Сериалов просмотрено: {}", self.serials_watched)?;
+        write!(
+            f,
+            "\n⏱️ Всего часов: {} (фильмы: {}, сериалы: {})",
+            hours(self.film_minutes + self.serial_minutes),
+            hours(self.film_minutes),
+            hours(self.serial_minutes)
+        )?;
+        if let Some(average) = self.average_rating() {
+            write!(
+                f,
+                "\n⭐ Средняя оценка: {average:.2} (оценок: {})",
+                self.rating_count
+            )?;
+        }
+        if !self.rating_distribution.is_empty() {
+            write!(f, "\n\n<b>Оценки</b>")?;
+            let max = self
+                .rating_distribution
+                .values()
+                .copied()
+                .max()
+                .unwrap_or(1);
+            for (rating, count) in self.rating_distribution.iter().rev() {
+                write!(f, "\n<code>{rating:>2}</code> {} {count}", bar(*count, max))?;
+            }
+        }
+        let genres = top(&self.genres);
+        if !genres.is_empty() {
+            write!(f, "\n\n<b>Любимые жанры</b>")?;
+            for (genre, count) in genres {
+                write!(f, "\n🎭 {} — {count}", escape_html(genre))?;
+            }
+        }
+        let countries = top(&self.countries);
+        if !countries.is_empty() {
+            write!(f, "\n\n<b>Страны</b>")?;
+            for (country, count) in countries {
+                write!(f, "\n{} {} — {count}", flag(country), escape_html(country))?;
+            }
+        }
+        if !self.decades.is_empty() {
+            write!(f, "\n\n<b>По десятилетиям</b>")?;
+            let max = self.decades.values().copied().max().unwrap_or(1);
+            for (decade, count) in &self.decades {
+                write!(f, "\n{decade}-е {} {count}", bar(*count, max))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mongodb::bson::DateTime;
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use crate::app::{
+    models::{
+        EpisodeNumber, GroupFilm, GroupMember, GroupSettings, Movie, ReleaseWatch, Serial,
+        TitleSnapshot,
+    },
+    releases,
+    stats::UserStats,
+    storage::{AddOutcome, Page, WatchListStore},
+};
+
+fn page<'a, T: Clone + 'a>(records: impl Iterator<Item = &'a T>, skip: u64, limit: u64) -> Page<T> {
+    let matching: Vec<&T> = records.collect();
+    Page {
+        total: matching.len() as u64,
+        items: matching
+            .into_iter()
+            .skip(skip as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Process-local storage, used for offline development and tests.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryStorage {
+    movies: Arc<RwLock<Vec<Movie>>>,
+    serials: Arc<RwLock<Vec<Serial>>>,
+    groups: Arc<RwLock<Vec<GroupSettings>>>,
+    group_films: Arc<RwLock<Vec<GroupFilm>>>,
+    posters: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl InMemoryStorage {
+    #[instrument(name = "new in-memory storage")]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    async fn update_group_film(&self, chat_id: i64, film_id: i64, f: impl FnOnce(&mut GroupFilm)) {
+        let mut films = self.group_films.write().await;
+        if let Some(film) = films
+            .iter_mut()
+            .find(|f| f.chat_id == chat_id && f.film_id == film_id)
+        {
+            f(film);
+        }
+    }
+}
+
+#[async_trait]
+impl WatchListStore for InMemoryStorage {
+    #[instrument(name = "get users movies", skip(self))]
+    async fn get_users_movies(&self, user_id: u64) -> Result<Vec<Movie>> {
+        let movies = self.movies.read().await;
+        Ok(movies
+            .iter()
+            .filter(|m| m.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+    #[instrument(name = "get users watched movies list", skip(self))]
+    async fn get_users_watched_movies_list(&self, user_id: u64) -> Result<Vec<Movie>> {
+        let movies = self.movies.read().await;
+        Ok(movies
+            .iter()
+            .filter(|m| m.user_id == user_id && m.viewing.watched)
+            .cloned()
+            .collect())
+    }
+    #[instrument(name = "get users movies page", skip(self))]
+    async fn get_users_movies_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Movie>> {
+        let movies = self.movies.read().await;
+        let records = movies
+            .iter()
+            .filter(|m| m.user_id == user_id && m.viewing.watched == watched);
+        Ok(page(records, skip, limit))
+    }
+    #[instrument(name = "add film to watch list", skip(self, snapshot))]
+    async fn add_film_to_watch_list(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        snapshot: TitleSnapshot,
+        region: String,
+    ) -> Result<AddOutcome> {
+        let mut movies = self.movies.write().await;
+        if let Some(movie) = movies
+            .iter_mut()
+            .find(|m| m.user_id == user_id && m.film_id == film_id)
+        {
+            movie.snapshot = Some(snapshot);
+            return Ok(AddOutcome::from_existing(Some(movie.viewing.watched)));
+        }
+        let mut movie = Movie::new(user_id, film_id);
+        movie.snapshot = Some(snapshot);
+        movie.region = Some(region);
+        movie.track_release(&releases::today());
+        movies.push(movie);
+        Ok(AddOutcome::Added)
+    }
+    #[instrument(name = "import film", skip_all)]
+    async fn import_film(&self, movie: Movie, region: String) -> Result<AddOutcome> {
+        let mut movies = self.movies.write().await;
+        if let Some(existing) = movies
+            .iter_mut()
+            .find(|m| m.user_id == movie.user_id && m.film_id == movie.film_id)
+        {
+            let watched = existing.viewing.watched;
+            existing.viewing.merge_imported(&movie.viewing);
+            return Ok(AddOutcome::from_existing(Some(watched)));
+        }
+        let mut movie = movie;
+        movie.region = Some(region);
+        movie.track_release(&releases::today());
+        movies.push(movie);
+        Ok(AddOutcome::Added)
+    }
+    #[instrument(name = "mark film as watched", skip(self))]
+    async fn watch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let mut movies = self.movies.write().await;
+        if let Some(movie) = movies
+            .iter_mut()
+            .find(|m| m.user_id == user_id && m.film_id == film_id)
+        {
+            movie.viewing.watch();
+        }
+        Ok(())
+    }
+    #[instrument(name = "mark film as unwatched", skip(self))]
+    async fn unwatch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let mut movies = self.movies.write().await;
+        if let Some(movie) = movies
+            .iter_mut()
+            .find(|m| m.user_id == user_id && m.film_id == film_id)
+        {
+            movie.viewing.unwatch();
+        }
+        Ok(())
+    }
+    #[instrument(name = "rate film", skip(self))]
+    async fn rate_movie(&self, user_id: u64, film_id: i64, rate: f64) -> Result<()> {
+        let mut movies = self.movies.write().await;
+        if let Some(movie) = movies
+            .iter_mut()
+            .find(|m| m.user_id == user_id && m.film_id == film_id)
+        {
+            movie.viewing.rate(rate);
+        }
+        Ok(())
+    }
+    #[instrument(name = "set film release watch", skip(self))]
+    async fn set_film_release_watch(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        release_watch: Option<ReleaseWatch>,
+    ) -> Result<()> {
+        let mut movies = self.movies.write().await;
+        if let Some(movie) = movies
+            .iter_mut()
+            .find(|m| m.user_id == user_id && m.film_id == film_id)
+        {
+            movie.release_watch = release_watch;
+        }
+        Ok(())
+    }
+    #[instrument(name = "get films awaiting release", skip(self))]
+    async fn films_awaiting_release(&self) -> Result<Vec<Movie>> {
+        let movies = self.movies.read().await;
+        Ok(movies
+            .iter()
+            .filter(|m| !m.viewing.watched && m.release_watch.is_some())
+            .cloned()
+            .collect())
+    }
+    #[instrument(name = "delete film from watch list", skip(self))]
+    async fn delete_film_from_watch_list(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let mut movies = self.movies.write().await;
+        if let Some(i) = movies
+            .iter()
+            .position(|m| m.user_id == user_id && m.film_id == film_id)
+        {
+            movies.remove(i);
+        }
+        Ok(())
+    }
+    #[instrument(name = "set film snapshot", skip(self, snapshot))]
+    async fn set_film_snapshot(&self, film_id: i64, snapshot: TitleSnapshot) -> Result<()> {
+        let mut movies = self.movies.write().await;
+        let today = releases::today();
+        for movie in movies.iter_mut().filter(|m| m.film_id == film_id) {
+            movie.snapshot = Some(snapshot.clone());
+            movie.track_release(&today);
+        }
+        Ok(())
+    }
+    #[instrument(name = "get stale film snapshots", skip(self))]
+    async fn stale_film_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>> {
+        let movies = self.movies.read().await;
+        let ids: BTreeSet<i64> = movies
+            .iter()
+            .filter(|m| m.snapshot_due(older_than))
+            .map(|m| m.film_id)
+            .collect();
+        Ok(ids.into_iter().take(limit).collect())
+    }
+    #[instrument(name = "defer film snapshot", skip(self))]
+    async fn defer_film_snapshot(&self, film_id: i64) -> Result<()> {
+        let mut movies = self.movies.write().await;
+        for movie in movies.iter_mut().filter(|m| m.film_id == film_id) {
+            movie.snapshot_failed_at = Some(DateTime::now());
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "get users serials", skip(self))]
+    async fn get_users_serials(&self, user_id: u64) -> Result<Vec<Serial>> {
+        let serials = self.serials.read().await;
+        Ok(serials
+            .iter()
+            .filter(|s| s.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+    #[instrument(name = "get serial", skip(self))]
+    async fn get_serial(&self, user_id: u64, serial_id: i64) -> Result<Option<Serial>> {
+        let serials = self.serials.read().await;
+        Ok(serials
+            .iter()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+            .cloned())
+    }
+    #[instrument(name = "get users watched serials list", skip(self))]
+    async fn get_users_watched_serials_list(&self, user_id: u64) -> Result<Vec<Serial>> {
+        let serials = self.serials.read().await;
+        Ok(serials
+            .iter()
+            .filter(|s| s.user_id == user_id && s.viewing.watched)
+            .cloned()
+            .collect())
+    }
+    #[instrument(name = "get users serials page", skip(self))]
+    async fn get_users_serials_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Serial>> {
+        let serials = self.serials.read().await;
+        let records = serials
+            .iter()
+            .filter(|s| s.user_id == user_id && s.viewing.watched == watched);
+        Ok(page(records, skip, limit))
+    }
+    #[instrument(name = "add serial to watch list", skip(self, snapshot))]
+    async fn add_serial_to_watch_list(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        snapshot: TitleSnapshot,
+    ) -> Result<AddOutcome> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.snapshot = Some(snapshot);
+            return Ok(AddOutcome::from_existing(Some(serial.viewing.watched)));
+        }
+        let mut serial = Serial::new(user_id, serial_id);
+        serial.snapshot = Some(snapshot);
+        serials.push(serial);
+        Ok(AddOutcome::Added)
+    }
+    #[instrument(name = "import serial", skip_all)]
+    async fn import_serial(&self, serial: Serial) -> Result<AddOutcome> {
+        let mut serials = self.serials.write().await;
+        if let Some(existing) = serials
+            .iter_mut()
+            .find(|s| s.user_id == serial.user_id && s.serial_id == serial.serial_id)
+        {
+            let watched = existing.viewing.watched;
+            existing.viewing.merge_imported(&serial.viewing);
+            return Ok(AddOutcome::from_existing(Some(watched)));
+        }
+        serials.push(serial);
+        Ok(AddOutcome::Added)
+    }
+    #[instrument(name = "mark serial as watched", skip(self))]
+    async fn watch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.viewing.watch();
+        }
+        Ok(())
+    }
+    #[instrument(name = "mark serial as unwatched", skip(self))]
+    async fn unwatch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.unwatch();
+        }
+        Ok(())
+    }
+    #[instrument(name = "rate serial", skip(self))]
+    async fn rate_serial(&self, user_id: u64, serial_id: i64, rate: f64) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.viewing.rate(rate);
+        }
+        Ok(())
+    }
+    #[instrument(name = "rate serial season", skip(self))]
+    async fn rate_serial_season(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        season: i64,
+        rate: f64,
+    ) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.rate_season(season, rate);
+        }
+        Ok(())
+    }
+    #[instrument(name = "set serial reminders", skip(self))]
+    async fn set_serial_reminders(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        enabled: bool,
+    ) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.reminders = enabled;
+        }
+        Ok(())
+    }
+    #[instrument(name = "get serials with reminders", skip(self))]
+    async fn serials_with_reminders(&self) -> Result<Vec<Serial>> {
+        let serials = self.serials.read().await;
+        Ok(serials.iter().filter(|s| s.reminders).cloned().collect())
+    }
+    #[instrument(name = "set serial reminded", skip(self))]
+    async fn set_serial_reminded(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        episode: EpisodeNumber,
+    ) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.reminded = Some(episode);
+        }
+        Ok(())
+    }
+    #[instrument(name = "set serial progress", skip(self))]
+    async fn set_serial_progress(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        progress: EpisodeNumber,
+    ) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(serial) = serials
+            .iter_mut()
+            .find(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serial.progress = Some(progress);
+        }
+        Ok(())
+    }
+    #[instrument(name = "delete serial from watch list", skip(self))]
+    async fn delete_serial_from_watch_list(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        if let Some(i) = serials
+            .iter()
+            .position(|s| s.user_id == user_id && s.serial_id == serial_id)
+        {
+            serials.remove(i);
+        }
+        Ok(())
+    }
+    #[instrument(name = "set serial snapshot", skip(self, snapshot))]
+    async fn set_serial_snapshot(&self, serial_id: i64, snapshot: TitleSnapshot) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        for serial in serials.iter_mut().filter(|s| s.serial_id == serial_id) {
+            serial.snapshot = Some(snapshot.clone());
+        }
+        Ok(())
+    }
+    #[instrument(name = "get stale serial snapshots", skip(self))]
+    async fn stale_serial_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>> {
+        let serials = self.serials.read().await;
+        let ids: BTreeSet<i64> = serials
+            .iter()
+            .filter(|s| s.snapshot_due(older_than))
+            .map(|s| s.serial_id)
+            .collect();
+        Ok(ids.into_iter().take(limit).collect())
+    }
+    #[instrument(name = "defer serial snapshot", skip(self))]
+    async fn defer_serial_snapshot(&self, serial_id: i64) -> Result<()> {
+        let mut serials = self.serials.write().await;
+        for serial in serials.iter_mut().filter(|s| s.serial_id == serial_id) {
+            serial.snapshot_failed_at = Some(DateTime::now());
+        }
+        Ok(())
+    }
+    #[instrument(name = "get group settings", skip(self))]
+    async fn group_settings(&self, chat_id: i64) -> Result<Option<GroupSettings>> {
+        let groups = self.groups.read().await;
+        Ok(groups.iter().find(|g| g.chat_id == chat_id).cloned())
+    }
+    #[instrument(name = "set group settings", skip(self))]
+    async fn set_group_settings(&self, settings: GroupSettings) -> Result<()> {
+        let mut groups = self.groups.write().await;
+        groups.retain(|g| g.chat_id != settings.chat_id);
+        groups.push(settings);
+        Ok(())
+    }
+    #[instrument(name = "get group films page", skip(self))]
+    async fn get_group_films_page(
+        &self,
+        chat_id: i64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<GroupFilm>> {
+        let films = self.group_films.read().await;
+        Ok(page(
+            films
+                .iter()
+                .filter(|f| f.chat_id == chat_id && f.watched == watched),
+            skip,
+            limit,
+        ))
+    }
+    #[instrument(name = "get group film", skip(self))]
+    async fn get_group_film(&self, chat_id: i64, film_id: i64) -> Result<Option<GroupFilm>> {
+        let films = self.group_films.read().await;
+        Ok(films
+            .iter()
+            .find(|f| f.chat_id == chat_id && f.film_id == film_id)
+            .cloned())
+    }
+    #[instrument(name = "add group film", skip_all)]
+    async fn add_group_film(&self, film: GroupFilm) -> Result<AddOutcome> {
+        let mut films = self.group_films.write().await;
+        let existing = films
+            .iter()
+            .find(|f| f.chat_id == film.chat_id && f.film_id == film.film_id)
+            .map(|f| f.watched);
+        if existing.is_none() {
+            films.push(film);
+        }
+        Ok(AddOutcome::from_existing(existing))
+    }
+    #[instrument(name = "mark group film as watched", skip(self))]
+    async fn watch_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+    ) -> Result<()> {
+        self.update_group_film(chat_id, film_id, |f| f.watch(member))
+            .await;
+        Ok(())
+    }
+    #[instrument(name = "attend group film", skip(self))]
+    async fn attend_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+    ) -> Result<()> {
+        self.update_group_film(chat_id, film_id, |f| f.attend(member))
+            .await;
+        Ok(())
+    }
+    #[instrument(name = "rate group film", skip(self))]
+    async fn rate_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+        rating: f64,
+    ) -> Result<()> {
+        self.update_group_film(chat_id, film_id, |f| f.rate(member, rating))
+            .await;
+        Ok(())
+    }
+    #[instrument(name = "delete group film", skip(self))]
+    async fn delete_group_film(&self, chat_id: i64, film_id: i64) -> Result<()> {
+        let mut films = self.group_films.write().await;
+        films.retain(|f| !(f.chat_id == chat_id && f.film_id == film_id));
+        Ok(())
+    }
+    #[instrument(name = "get poster file id", skip(self))]
+    async fn poster_file_id(&self, poster_path: &str) -> Result<Option<String>> {
+        let posters = self.posters.read().await;
+        Ok(posters.get(poster_path).cloned())
+    }
+    #[instrument(name = "set poster file id", skip(self))]
+    async fn set_poster_file_id(&self, poster_path: &str, file_id: &str) -> Result<()> {
+        let mut posters = self.posters.write().await;
+        posters.insert(poster_path.to_string(), file_id.to_string());
+        Ok(())
+    }
+    #[instrument(name = "get user stats", skip(self))]
+    async fn user_stats(&self, user_id: u64) -> Result<UserStats> {
+        let movies = self.get_users_movies(user_id).await?;
+        let serials = self.get_users_serials(user_id).await?;
+        Ok(UserStats::from_titles(&movies, &serials))
+    }
+}
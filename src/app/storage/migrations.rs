@@ -0,0 +1,156 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use mongodb::{
+    Collection, Database,
+    bson::{Bson, DateTime, Document, doc},
+};
+use tracing::instrument;
+
+use crate::app::{MOVIES, SERIALS};
+
+const METADATA: &str = "metadata";
+const SCHEMA_VERSION_ID: &str = "schema_version";
+
+/// A single schema change of the `content` database.
+///
+/// Every step must be idempotent: a crash between applying a step and
+/// recording its version makes it run again on the next start.
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    /// Keeps one document per `(user_id, <id_field>)`, preferring a watched, then a rated one.
+    RemoveDuplicates {
+        collection: &'static str,
+        id_field: &'static str,
+    },
+    /// Sets `added_at` from the creation time encoded in the document's `ObjectId`.
+    BackfillAddedAt { collection: &'static str },
+}
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    steps: &'static [Step],
+}
+
+/// Ordered list of all migrations, versions must be strictly increasing.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "remove duplicate watch list entries",
+        steps: &[
+            Step::RemoveDuplicates {
+                collection: MOVIES,
+                id_field: "film_id",
+            },
+            Step::RemoveDuplicates {
+                collection: SERIALS,
+                id_field: "serial_id",
+            },
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "backfill added_at from document ids",
+        steps: &[
+            Step::BackfillAddedAt { collection: MOVIES },
+            Step::BackfillAddedAt {
+                collection: SERIALS,
+            },
+        ],
+    },
+];
+
+/// Applies every migration newer than the recorded schema version.
+///
+/// With `dry_run` nothing is written, only what would change is logged.
+#[instrument(name = "run migrations", skip(database))]
+pub async fn run(database: &Database, dry_run: bool) -> Result<()> {
+    let metadata: Collection<Document> = database.collection(METADATA);
+    let current = schema_version(&metadata).await?;
+    tracing::info!("Current schema version: {current}");
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let mut affected = 0;
+        for step in migration.steps {
+            affected += apply(database, *step, dry_run).await?;
+        }
+        if dry_run {
+            tracing::info!(
+                "Migration {v} ({d}) would change {affected} documents",
+                v = migration.version,
+                d = migration.description
+            );
+        } else {
+            set_schema_version(&metadata, migration.version).await?;
+            tracing::info!(
+                "Migration {v} ({d}) changed {affected} documents",
+                v = migration.version,
+                d = migration.description
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn schema_version(metadata: &Collection<Document>) -> Result<i64> {
+    let version = metadata
+        .find_one(doc! {"_id": SCHEMA_VERSION_ID})
+        .await?
+        .and_then(|d| d.get("version").and_then(Bson::as_i64))
+        .unwrap_or(0);
+    Ok(version)
+}
+
+async fn set_schema_version(metadata: &Collection<Document>, version: i64) -> Result<()> {
+    metadata
+        .update_one(
+            doc! {"_id": SCHEMA_VERSION_ID},
+            doc! {"$set": {"version": version, "updated_at": DateTime::now()}},
+        )
+        .upsert(true)
+        .await?;
+    Ok(())
+}
+
+async fn apply(database: &Database, step: Step, dry_run: bool) -> Result<u64> {
+    match step {
+        Step::RemoveDuplicates {
+            collection,
+            id_field,
+        } => {
+            let collection: Collection<Document> = database.collection(collection);
+            let pipeline = vec![
+                doc! {"$sort": {"watched": -1, "my_rating": -1, "_id": 1}},
+                doc! {"$group": {
+                    "_id": {"user_id": "$user_id", "item_id": format!("${id_field}")},
+                    "ids": {"$push": "$_id"},
+                    "count": {"$sum": 1},
+                }},
+                doc! {"$match": {"count": {"$gt": 1}}},
+            ];
+            let mut cursor = collection.aggregate(pipeline).await?;
+            let mut duplicates = Vec::new();
+            while let Some(group) = cursor.try_next().await? {
+                if let Ok(ids) = group.get_array("ids") {
+                    duplicates.extend(ids.iter().skip(1).cloned());
+                }
+            }
+            if dry_run || duplicates.is_empty() {
+                return Ok(duplicates.len() as u64);
+            }
+            let result = collection
+                .delete_many(doc! {"_id": {"$in": duplicates}})
+                .await?;
+            Ok(result.deleted_count)
+        }
+        Step::BackfillAddedAt { collection } => {
+            let collection: Collection<Document> = database.collection(collection);
+            let filter = doc! {"added_at": {"$exists": false}, "_id": {"$type": "objectId"}};
+            if dry_run {
+                return Ok(collection.count_documents(filter).await?);
+            }
+            let update = vec![doc! {"$set": {"added_at": {"$toDate": "$_id"}}}];
+            let result = collection.update_many(filter, update).await?;
+            Ok(result.modified_count)
+        }
+    }
+}
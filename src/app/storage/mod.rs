@@ -1,175 +1,181 @@
+mod memory;
+pub use memory::InMemoryStorage;
+pub mod migrations;
+mod mongo;
+pub use mongo::{MongoStorage, PosterRecord};
+mod sqlite;
+pub use sqlite::SqliteStorage;
+
+use std::sync::Arc;
+
 use anyhow::Result;
-use futures::TryStreamExt;
-use mongodb::{Collection, bson::doc};
-use tracing::instrument;
+use async_trait::async_trait;
 
-use crate::app::models::{Movie, Serial};
+use mongodb::bson::DateTime;
 
-#[derive(Clone, Debug)]
-pub struct Storage {
-    movies: Collection<Movie>,
-    serials: Collection<Serial>,
-}
+use crate::app::{
+    models::{
+        EpisodeNumber, GroupFilm, GroupMember, GroupSettings, Movie, ReleaseWatch, Serial,
+        TitleSnapshot,
+    },
+    stats::UserStats,
+};
 
-impl Storage {
-    #[instrument(name = "new storage", skip_all)]
-    pub fn new(movies: Collection<Movie>, serials: Collection<Serial>) -> Self {
-        Self { movies, serials }
-    }
-    #[instrument(name = "get users movies watch list", skip(self))]
-    pub async fn get_users_movie_watch_list(&self, user_id: u64) -> Result<Vec<Movie>> {
-        let mut cursor = self
-            .movies
-            .find(doc! {"user_id": user_id as i64, "watched": false})
-            .await?;
-        let mut result = Vec::new();
-        while let Some(movie) = cursor.try_next().await? {
-            result.push(movie);
-        }
-        Ok(result)
-    }
-    #[instrument(name = "get users watched movies list", skip(self))]
-    pub async fn get_users_watched_movies_list(&self, user_id: u64) -> Result<Vec<Movie>> {
-        let mut cursor = self
-            .movies
-            .find(doc! {"user_id": user_id as i64, "watched": true})
-            .await?;
-        let mut result = Vec::new();
-        while let Some(movie) = cursor.try_next().await? {
-            result.push(movie);
-        }
-        Ok(result)
-    }
-    #[instrument(name = "add film to watch list", skip(self))]
-    pub async fn add_film_to_watch_list(&self, user_id: u64, film_id: i64) -> Result<()> {
-        let movie = Movie::new(user_id, film_id);
-        tracing::info!("Movie: {movie:#?}");
-        let current_watch_list = self.get_users_movie_watch_list(user_id).await?;
-        if !current_watch_list.is_empty() {
-            for movie in current_watch_list {
-                if movie.film_id == film_id {
-                    return Ok(());
-                }
-            }
+/// Handle to the watch list backend shared between handlers.
+pub type Storage = Arc<dyn WatchListStore>;
+
+/// Result of adding a title to a user's watch list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddOutcome {
+    Added,
+    AlreadyInWatchList,
+    AlreadyWatched,
+}
+impl AddOutcome {
+    /// Outcome given the `watched` flag of the record that existed before the add, if any.
+    pub fn from_existing(watched: Option<bool>) -> Self {
+        match watched {
+            None => Self::Added,
+            Some(false) => Self::AlreadyInWatchList,
+            Some(true) => Self::AlreadyWatched,
         }
-        let res = self.movies.insert_one(movie).await?;
-        let c = res.inserted_id.as_str();
-        tracing::info!("Inserted id: {c:?}");
-        Ok(())
-    }
-    #[instrument(name = "mark film as watched", skip(self))]
-    pub async fn watch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
-        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
-        let update = doc! {"$set": doc!{"watched": true}};
-        let res = self.movies.update_one(filter, update).await?;
-        tracing::info!("Updated {} films in db", res.modified_count);
-        Ok(())
-    }
-    #[instrument(name = "mark film as unwatched", skip(self))]
-    pub async fn unwatch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
-        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
-        let none_rate: Option<f64> = None;
-        let update = doc! {"$set": doc!{"watched": false, "my_rating": none_rate}};
-        let res = self.movies.update_one(filter, update).await?;
-        tracing::info!("Updated {} films in db", res.modified_count);
-        Ok(())
-    }
-    #[instrument(name = "rate film", skip(self))]
-    pub async fn rate_movie(&self, user_id: u64, film_id: i64, rate: f64) -> Result<()> {
-        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
-        let update = doc! {"$set": doc!{"my_rating": Some(rate)}};
-        let res = self.movies.update_one(filter, update).await?;
-        tracing::info!("Updated {} films in db", res.modified_count);
-        Ok(())
-    }
-    #[instrument(name = "delete film from watch list", skip(self))]
-    pub async fn delete_film_from_watch_list(&self, user_id: u64, film_id: i64) -> Result<()> {
-        let filter = doc! {
-            "$and": [
-                doc! { "user_id": user_id as i64},
-                doc! {"film_id": film_id},
-            ]
-        };
-        let result = self.movies.delete_one(filter).await?;
-        tracing::info!("Deleted {} documents", result.deleted_count);
-        Ok(())
     }
 }
 
-impl Storage {
-    #[instrument(name = "get users serials watch list", skip(self))]
-    pub async fn get_users_serials_watch_list(&self, user_id: u64) -> Result<Vec<Serial>> {
-        let mut cursor = self
-            .serials
-            .find(doc! {"user_id": user_id as i64, "watched": false})
-            .await?;
-        let mut result = Vec::new();
-        while let Some(serial) = cursor.try_next().await? {
-            result.push(serial);
-        }
-        Ok(result)
-    }
-    #[instrument(name = "get users watched serials list", skip(self))]
-    pub async fn get_users_watched_serials_list(&self, user_id: u64) -> Result<Vec<Serial>> {
-        let mut cursor = self
-            .serials
-            .find(doc! {"user_id": user_id as i64, "watched": true})
-            .await?;
-        let mut result = Vec::new();
-        while let Some(serial) = cursor.try_next().await? {
-            result.push(serial);
-        }
-        Ok(result)
-    }
-    #[instrument(name = "add serial to watch list", skip(self))]
-    pub async fn add_serial_to_watch_list(&self, user_id: u64, serial_id: i64) -> Result<()> {
-        let serial = Serial::new(user_id, serial_id);
-        let current_watch_list = self.get_users_serials_watch_list(user_id).await?;
-        if !current_watch_list.is_empty() {
-            for serial in current_watch_list {
-                if serial.serial_id == serial_id {
-                    return Ok(());
-                }
-            }
-        }
-        self.serials.insert_one(serial).await?;
-        Ok(())
-    }
-    #[instrument(name = "mark serial as watched", skip(self))]
-    pub async fn watch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
-        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
-        let update = doc! {"$set": doc!{"watched": true}};
-        let res = self.serials.update_one(filter, update).await?;
-        tracing::info!("Updated {} serials in db", res.modified_count);
-        Ok(())
-    }
-    #[instrument(name = "mark serial as unwatched", skip(self))]
-    pub async fn unwatch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
-        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
-        let none_rate: Option<f64> = None;
-        let update = doc! {"$set": doc!{"watched": false, "my_rating": none_rate}};
-        let res = self.serials.update_one(filter, update).await?;
-        tracing::info!("Updated {} serials in db", res.modified_count);
-        Ok(())
-    }
-    #[instrument(name = "rate serial", skip(self))]
-    pub async fn rate_serial(&self, user_id: u64, serial_id: i64, rate: f64) -> Result<()> {
-        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
-        let update = doc! {"$set": doc!{"my_rating": Some(rate)}};
-        let res = self.serials.update_one(filter, update).await?;
-        tracing::info!("Updated {} serials in db", res.modified_count);
-        Ok(())
-    }
-    #[instrument(name = "delete serial from watch list", skip(self))]
-    pub async fn delete_serial_from_watch_list(&self, user_id: u64, serial_id: i64) -> Result<()> {
-        let filter = doc! {
-            "$and": [
-                doc! { "user_id": user_id as i64},
-                doc! {"serial_id": serial_id},
-            ]
-        };
-        let result = self.serials.delete_one(filter).await?;
-        tracing::info!("Deleted {} documents", result.deleted_count);
-        Ok(())
-    }
+/// One page of a user's list together with the size of the whole list.
+#[derive(Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+}
+
+/// Everything the bot needs to persist users' films and serials.
+#[async_trait]
+pub trait WatchListStore: Send + Sync + std::fmt::Debug {
+    /// Every film of the user, watched or not.
+    async fn get_users_movies(&self, user_id: u64) -> Result<Vec<Movie>>;
+    async fn get_users_watched_movies_list(&self, user_id: u64) -> Result<Vec<Movie>>;
+    /// Films of the watch list or the watched list in insertion order.
+    async fn get_users_movies_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Movie>>;
+    /// Adds the film or, if it is already listed, refreshes its snapshot;
+    /// release alerts of a new film that isn't out yet use the dates of `region`.
+    async fn add_film_to_watch_list(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        snapshot: TitleSnapshot,
+        region: String,
+    ) -> Result<AddOutcome>;
+    /// Inserts a complete record from an import; a film the user already has is left untouched.
+    /// Release alerts use the dates of `region`, as in `add_film_to_watch_list`.
+    async fn import_film(&self, movie: Movie, region: String) -> Result<AddOutcome>;
+    async fn watch_film(&self, user_id: u64, film_id: i64) -> Result<()>;
+    async fn unwatch_film(&self, user_id: u64, film_id: i64) -> Result<()>;
+    async fn rate_movie(&self, user_id: u64, film_id: i64, rate: f64) -> Result<()>;
+    async fn delete_film_from_watch_list(&self, user_id: u64, film_id: i64) -> Result<()>;
+    async fn set_film_release_watch(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        release_watch: Option<ReleaseWatch>,
+    ) -> Result<()>;
+    /// Unwatched films of all users that wait for a release.
+    async fn films_awaiting_release(&self) -> Result<Vec<Movie>>;
+    /// Replaces the snapshot of the film in every user's list, release alerts
+    /// it starts use the region stored with each user's film.
+    async fn set_film_snapshot(&self, film_id: i64, snapshot: TitleSnapshot) -> Result<()>;
+    /// Ids of films whose snapshot is missing or was refreshed before `older_than`,
+    /// films whose refresh failed after `older_than` are left out.
+    async fn stale_film_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>>;
+    /// Records a failed snapshot refresh, so the film doesn't take a slot in every batch.
+    async fn defer_film_snapshot(&self, film_id: i64) -> Result<()>;
+
+    async fn get_users_serials(&self, user_id: u64) -> Result<Vec<Serial>>;
+    async fn get_serial(&self, user_id: u64, serial_id: i64) -> Result<Option<Serial>>;
+    async fn get_users_watched_serials_list(&self, user_id: u64) -> Result<Vec<Serial>>;
+    async fn get_users_serials_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Serial>>;
+    async fn add_serial_to_watch_list(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        snapshot: TitleSnapshot,
+    ) -> Result<AddOutcome>;
+    async fn import_serial(&self, serial: Serial) -> Result<AddOutcome>;
+    async fn watch_serial(&self, user_id: u64, serial_id: i64) -> Result<()>;
+    /// Marks the serial unwatched and clears its progress.
+    async fn unwatch_serial(&self, user_id: u64, serial_id: i64) -> Result<()>;
+    async fn rate_serial(&self, user_id: u64, serial_id: i64, rate: f64) -> Result<()>;
+    async fn rate_serial_season(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        season: i64,
+        rate: f64,
+    ) -> Result<()>;
+    async fn set_serial_reminders(&self, user_id: u64, serial_id: i64, enabled: bool)
+    -> Result<()>;
+    /// Serials of all users with reminders turned on.
+    async fn serials_with_reminders(&self) -> Result<Vec<Serial>>;
+    async fn set_serial_reminded(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        episode: EpisodeNumber,
+    ) -> Result<()>;
+    async fn set_serial_progress(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        progress: EpisodeNumber,
+    ) -> Result<()>;
+    async fn delete_serial_from_watch_list(&self, user_id: u64, serial_id: i64) -> Result<()>;
+    async fn set_serial_snapshot(&self, serial_id: i64, snapshot: TitleSnapshot) -> Result<()>;
+    async fn stale_serial_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>>;
+    async fn defer_serial_snapshot(&self, serial_id: i64) -> Result<()>;
+    async fn group_settings(&self, chat_id: i64) -> Result<Option<GroupSettings>>;
+    async fn set_group_settings(&self, settings: GroupSettings) -> Result<()>;
+    /// Films of a group chat's shared list in insertion order.
+    async fn get_group_films_page(
+        &self,
+        chat_id: i64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<GroupFilm>>;
+    async fn get_group_film(&self, chat_id: i64, film_id: i64) -> Result<Option<GroupFilm>>;
+    /// Adds the film unless the group already has it.
+    async fn add_group_film(&self, film: GroupFilm) -> Result<AddOutcome>;
+    async fn watch_group_film(&self, chat_id: i64, film_id: i64, member: GroupMember)
+    -> Result<()>;
+    async fn attend_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+    ) -> Result<()>;
+    async fn rate_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+        rating: f64,
+    ) -> Result<()>;
+    async fn delete_group_film(&self, chat_id: i64, film_id: i64) -> Result<()>;
+    /// Telegram `file_id` of a poster the bot has already uploaded.
+    async fn poster_file_id(&self, poster_path: &str) -> Result<Option<String>>;
+    async fn set_poster_file_id(&self, poster_path: &str, file_id: &str) -> Result<()>;
+    /// Viewing statistics built from the snapshots in the user's lists.
+    async fn user_stats(&self, user_id: u64) -> Result<UserStats>;
 }
@@ -0,0 +1,884 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use mongodb::{
+    Collection, IndexModel,
+    bson::{self, Bson, DateTime, Document, bson, doc},
+    options::{IndexOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::app::{
+    models::{
+        EpisodeNumber, GroupFilm, GroupMember, GroupSettings, MemberRating, Movie, ReleaseWatch,
+        SeasonRating, Serial, TitleSnapshot,
+    },
+    releases,
+    stats::UserStats,
+    storage::{AddOutcome, Page, WatchListStore},
+};
+
+/// Telegram `file_id` of an uploaded poster, keyed by the TMDB `poster_path`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PosterRecord {
+    #[serde(rename = "_id")]
+    poster_path: String,
+    file_id: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct MongoStorage {
+    movies: Collection<Movie>,
+    serials: Collection<Serial>,
+    groups: Collection<GroupSettings>,
+    group_films: Collection<GroupFilm>,
+    posters: Collection<PosterRecord>,
+}
+
+impl MongoStorage {
+    #[instrument(name = "new storage", skip_all)]
+    pub fn new(
+        movies: Collection<Movie>,
+        serials: Collection<Serial>,
+        groups: Collection<GroupSettings>,
+        group_films: Collection<GroupFilm>,
+        posters: Collection<PosterRecord>,
+    ) -> Self {
+        Self {
+            movies,
+            serials,
+            groups,
+            group_films,
+            posters,
+        }
+    }
+    /// Creates the unique `(user_id, film_id)` / `(user_id, serial_id)` /
+    /// `(chat_id, film_id)` indexes the upserts in `add_*` rely on.
+    #[instrument(name = "ensure storage indexes", skip_all)]
+    pub async fn ensure_indexes(&self) -> Result<()> {
+        self.movies
+            .create_index(unique_index(doc! {"user_id": 1, "film_id": 1}, "user_film"))
+            .await?;
+        self.serials
+            .create_index(unique_index(
+                doc! {"user_id": 1, "serial_id": 1},
+                "user_serial",
+            ))
+            .await?;
+        self.group_films
+            .create_index(unique_index(doc! {"chat_id": 1, "film_id": 1}, "chat_film"))
+            .await?;
+        Ok(())
+    }
+}
+
+async fn page<T>(
+    collection: &Collection<T>,
+    filter: Document,
+    skip: u64,
+    limit: u64,
+) -> Result<Page<T>>
+where
+    T: serde::de::DeserializeOwned + Send + Sync,
+{
+    let total = collection.count_documents(filter.clone()).await?;
+    let items = collection
+        .find(filter)
+        .sort(doc! {"_id": 1})
+        .skip(skip)
+        .limit(limit as i64)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(Page { items, total })
+}
+
+fn stale_snapshot_filter(older_than: DateTime) -> Document {
+    doc! {
+        "$or": [
+            {"snapshot": {"$exists": false}},
+            {"snapshot.refreshed_at": {"$lt": older_than}},
+        ],
+        "snapshot_failed_at": {"$not": {"$gte": older_than}},
+    }
+}
+
+fn unique_index(keys: Document, name: &str) -> IndexModel {
+    let options = IndexOptions::builder()
+        .unique(true)
+        .name(name.to_string())
+        .build();
+    IndexModel::builder().keys(keys).options(options).build()
+}
+
+/// Fields of a freshly created record that are not part of the upsert filter.
+fn insert_only_fields<T: serde::Serialize>(record: &T, filter: &Document) -> Result<Document> {
+    let mut document = bson::to_document(record)?;
+    document.remove("_id");
+    for key in filter.keys() {
+        document.remove(key);
+    }
+    Ok(document)
+}
+
+/// Adds the member to the attendees of a group film unless they are already there.
+async fn attend(
+    group_films: &Collection<GroupFilm>,
+    chat_id: i64,
+    film_id: i64,
+    member: &GroupMember,
+) -> Result<u64> {
+    let filter = doc! {
+        "chat_id": chat_id,
+        "film_id": film_id,
+        "attendees.user_id": {"$ne": member.user_id as i64},
+    };
+    let update = doc! {"$push": {"attendees": bson::to_bson(member)?}};
+    let res = group_films.update_one(filter, update).await?;
+    Ok(res.modified_count)
+}
+
+/// Seen episodes of a serial before and including the given season and episode fields.
+fn episodes_up_to(season: &str, episode: &str) -> Bson {
+    bson!({
+        "$add": [
+            {"$sum": {"$map": {
+                "input": {"$filter": {
+                    "input": {"$ifNull": ["$snapshot.seasons", []]},
+                    "as": "s",
+                    "cond": {"$lt": ["$$s.season_number", season]},
+                }},
+                "as": "s",
+                "in": "$$s.episode_count",
+            }}},
+            episode,
+        ]
+    })
+}
+
+/// Same as `Serial::episodes_seen`, as an aggregation expression.
+fn episodes_seen() -> Bson {
+    bson!({
+        "$switch": {
+            "branches": [
+                {
+                    "case": {"$ne": [{"$ifNull": ["$progress", null]}, null]},
+                    "then": episodes_up_to("$progress.season", "$progress.episode"),
+                },
+                {
+                    "case": {"$and": [
+                        "$watched",
+                        {"$ne": [{"$ifNull": ["$snapshot.last_aired", null]}, null]},
+                    ]},
+                    "then": episodes_up_to("$snapshot.last_aired.season", "$snapshot.last_aired.episode"),
+                },
+                {"case": "$watched", "then": {"$sum": "$snapshot.seasons.episode_count"}},
+            ],
+            "default": 0,
+        }
+    })
+}
+
+/// One `$facet` stage computing every part of `UserStats` for a collection.
+fn stats_pipeline(user_id: u64, minutes: Bson) -> Vec<Document> {
+    let watched = doc! {"$match": {"watched": true}};
+    vec![
+        doc! {"$match": {"user_id": user_id as i64}},
+        doc! {"$facet": {
+            "watched": [watched.clone(), {"$count": "count"}],
+            "minutes": [{"$group": {"_id": null, "total": {"$sum": minutes}}}],
+            "ratings": [
+                watched.clone(),
+                {"$project": {
+                    "rating": {"$ifNull": ["$my_rating", {"$avg": "$season_ratings.rating"}]}
+                }},
+                {"$match": {"rating": {"$ne": null}}},
+                {"$group": {
+                    "_id": {"$floor": "$rating"},
+                    "count": {"$sum": 1},
+                    "sum": {"$sum": "$rating"},
+                }},
+            ],
+            "genres": [
+                watched.clone(),
+                {"$unwind": "$snapshot.genres"},
+                {"$group": {"_id": "$snapshot.genres", "count": {"$sum": 1}}},
+            ],
+            "countries": [
+                watched.clone(),
+                {"$unwind": "$snapshot.countries"},
+                {"$group": {"_id": "$snapshot.countries", "count": {"$sum": 1}}},
+            ],
+            "decades": [
+                watched,
+                {"$match": {"snapshot.year": {"$type": "number"}}},
+                {"$group": {
+                    "_id": {"$multiply": [{"$floor": {"$divide": ["$snapshot.year", 10]}}, 10]},
+                    "count": {"$sum": 1},
+                }},
+            ],
+        }},
+    ]
+}
+
+/// Output of `stats_pipeline`; numbers are read as doubles since
+/// the server may return any numeric type.
+#[derive(Debug, Deserialize)]
+struct StatsFacets {
+    watched: Vec<CountRow>,
+    minutes: Vec<MinutesRow>,
+    ratings: Vec<RatingRow>,
+    genres: Vec<KeyCount<String>>,
+    countries: Vec<KeyCount<String>>,
+    decades: Vec<KeyCount<f64>>,
+}
+#[derive(Debug, Deserialize)]
+struct CountRow {
+    count: f64,
+}
+#[derive(Debug, Deserialize)]
+struct MinutesRow {
+    total: f64,
+}
+#[derive(Debug, Deserialize)]
+struct RatingRow {
+    #[serde(rename = "_id")]
+    rating: f64,
+    count: f64,
+    sum: f64,
+}
+#[derive(Debug, Deserialize)]
+struct KeyCount<K> {
+    #[serde(rename = "_id")]
+    key: K,
+    count: f64,
+}
+impl StatsFacets {
+    fn into_stats(self, films: bool) -> UserStats {
+        let watched = self.watched.first().map_or(0, |r| r.count as u64);
+        let minutes = self.minutes.first().map_or(0, |r| r.total as i64);
+        let mut stats = UserStats::default();
+        if films {
+            stats.films_watched = watched;
+            stats.film_minutes = minutes;
+        } else {
+            stats.serials_watched = watched;
+            stats.serial_minutes = minutes;
+        }
+        for row in self.ratings {
+            stats.rating_sum += row.sum;
+            stats.rating_count += row.count as u64;
+            stats
+                .rating_distribution
+                .insert(row.rating as i64, row.count as u64);
+        }
+        stats.genres = self
+            .genres
+            .into_iter()
+            .map(|r| (r.key, r.count as u64))
+            .collect();
+        stats.countries = self
+            .countries
+            .into_iter()
+            .map(|r| (r.key, r.count as u64))
+            .collect();
+        stats.decades = self
+            .decades
+            .into_iter()
+            .map(|r| (r.key as i32, r.count as u64))
+            .collect();
+        stats
+    }
+}
+
+async fn aggregate_stats<T: Send + Sync>(
+    collection: &Collection<T>,
+    pipeline: Vec<Document>,
+    films: bool,
+) -> Result<UserStats> {
+    let mut cursor = collection.aggregate(pipeline).await?;
+    let facets = match cursor.try_next().await? {
+        Some(document) => bson::from_document::<StatsFacets>(document)?.into_stats(films),
+        None => UserStats::default(),
+    };
+    Ok(facets)
+}
+
+#[async_trait]
+impl WatchListStore for MongoStorage {
+    #[instrument(name = "get users movies", skip(self))]
+    async fn get_users_movies(&self, user_id: u64) -> Result<Vec<Movie>> {
+        let cursor = self
+            .movies
+            .find(doc! {"user_id": user_id as i64})
+            .sort(doc! {"_id": 1})
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+    #[instrument(name = "get users watched movies list", skip(self))]
+    async fn get_users_watched_movies_list(&self, user_id: u64) -> Result<Vec<Movie>> {
+        let mut cursor = self
+            .movies
+            .find(doc! {"user_id": user_id as i64, "watched": true})
+            .await?;
+        let mut result = Vec::new();
+        while let Some(movie) = cursor.try_next().await? {
+            result.push(movie);
+        }
+        Ok(result)
+    }
+    #[instrument(name = "get users movies page", skip(self))]
+    async fn get_users_movies_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Movie>> {
+        let filter = doc! {"user_id": user_id as i64, "watched": watched};
+        page(&self.movies, filter, skip, limit).await
+    }
+    #[instrument(name = "add film to watch list", skip(self, snapshot))]
+    async fn add_film_to_watch_list(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        snapshot: TitleSnapshot,
+        region: String,
+    ) -> Result<AddOutcome> {
+        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
+        let mut movie = Movie::new(user_id, film_id);
+        movie.snapshot = Some(snapshot.clone());
+        movie.region = Some(region);
+        movie.track_release(&releases::today());
+        let mut on_insert = insert_only_fields(&movie, &filter)?;
+        on_insert.remove("snapshot");
+        let existing = self
+            .movies
+            .find_one_and_update(
+                filter,
+                doc! {
+                    "$setOnInsert": on_insert,
+                    "$set": {"snapshot": bson::to_bson(&snapshot)?},
+                },
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .await?;
+        let outcome = AddOutcome::from_existing(existing.map(|m| m.viewing.watched));
+        tracing::info!("Add film outcome: {outcome:?}");
+        Ok(outcome)
+    }
+    #[instrument(name = "import film", skip_all)]
+    async fn import_film(&self, mut movie: Movie, region: String) -> Result<AddOutcome> {
+        movie.region = Some(region);
+        movie.track_release(&releases::today());
+        let filter = doc! {"user_id": movie.user_id as i64, "film_id": movie.film_id};
+        let on_insert = insert_only_fields(&movie, &filter)?;
+        let existing = self
+            .movies
+            .find_one_and_update(filter.clone(), doc! {"$setOnInsert": on_insert})
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .await?;
+        let outcome = AddOutcome::from_existing(existing.as_ref().map(|m| m.viewing.watched));
+        if let Some(mut existing) = existing
+            && movie.viewing.has_progress()
+        {
+            existing.viewing.merge_imported(&movie.viewing);
+            let update = doc! {"$set": bson::to_document(&existing.viewing)?};
+            self.movies.update_one(filter, update).await?;
+        }
+        Ok(outcome)
+    }
+    #[instrument(name = "mark film as watched", skip(self))]
+    async fn watch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
+        let now = DateTime::now();
+        let update = doc! {
+            "$set": doc!{"watched": true, "watched_at": now},
+            "$push": doc!{"watch_history": now},
+        };
+        let res = self.movies.update_one(filter, update).await?;
+        tracing::info!("Updated {} films in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "mark film as unwatched", skip(self))]
+    async fn unwatch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
+        let none_rate: Option<f64> = None;
+        let update = doc! {
+            "$set": doc!{"watched": false, "my_rating": none_rate},
+            "$unset": doc!{"watched_at": "", "rated_at": ""},
+        };
+        let res = self.movies.update_one(filter, update).await?;
+        tracing::info!("Updated {} films in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "rate film", skip(self))]
+    async fn rate_movie(&self, user_id: u64, film_id: i64, rate: f64) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
+        let update = doc! {"$set": doc!{"my_rating": Some(rate), "rated_at": DateTime::now()}};
+        let res = self.movies.update_one(filter, update).await?;
+        tracing::info!("Updated {} films in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "set film release watch", skip(self))]
+    async fn set_film_release_watch(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        release_watch: Option<ReleaseWatch>,
+    ) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "film_id": film_id};
+        let update = match release_watch {
+            Some(release_watch) => doc! {"$set": {"release_watch": bson::to_bson(&release_watch)?}},
+            None => doc! {"$unset": {"release_watch": ""}},
+        };
+        let res = self.movies.update_one(filter, update).await?;
+        tracing::info!("Updated {} films in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "get films awaiting release", skip(self))]
+    async fn films_awaiting_release(&self) -> Result<Vec<Movie>> {
+        let filter = doc! {"release_watch": {"$exists": true}, "watched": false};
+        let movies = self.movies.find(filter).await?.try_collect().await?;
+        Ok(movies)
+    }
+    #[instrument(name = "delete film from watch list", skip(self))]
+    async fn delete_film_from_watch_list(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let filter = doc! {
+            "$and": [
+                doc! { "user_id": user_id as i64},
+                doc! {"film_id": film_id},
+            ]
+        };
+        let result = self.movies.delete_one(filter).await?;
+        tracing::info!("Deleted {} documents", result.deleted_count);
+        Ok(())
+    }
+    #[instrument(name = "set film snapshot", skip(self, snapshot))]
+    async fn set_film_snapshot(&self, film_id: i64, snapshot: TitleSnapshot) -> Result<()> {
+        let filter = doc! {"film_id": film_id};
+        let update = doc! {"$set": {"snapshot": bson::to_bson(&snapshot)?}};
+        let res = self.movies.update_many(filter, update).await?;
+        tracing::info!("Updated {} film snapshots in db", res.modified_count);
+        // Same rule as `Movie::track_release`, applied to every list the film is on.
+        if snapshot.is_upcoming(&releases::today()) {
+            let filter = doc! {
+                "film_id": film_id,
+                "watched": false,
+                "release_watch": {"$exists": false},
+            };
+            // A pipeline update, so each film takes the region stored with it.
+            let mut release_watch =
+                bson::to_document(&ReleaseWatch::new(releases::DEFAULT_REGION))?;
+            release_watch.insert(
+                "region",
+                doc! {"$ifNull": ["$region", releases::DEFAULT_REGION]},
+            );
+            let update = vec![doc! {"$set": {"release_watch": release_watch}}];
+            let res = self.movies.update_many(filter, update).await?;
+            tracing::info!("Started release alerts for {} films", res.modified_count);
+        }
+        Ok(())
+    }
+    #[instrument(name = "get stale film snapshots", skip(self))]
+    async fn stale_film_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>> {
+        let mut filter = stale_snapshot_filter(older_than);
+        // Snapshots taken before the release status was kept, see `Movie::snapshot_due`.
+        filter
+            .get_array_mut("$or")?
+            .push(bson!({"snapshot.status": {"$exists": false}}));
+        let ids = self.movies.distinct("film_id", filter).await?;
+        Ok(ids.iter().filter_map(Bson::as_i64).take(limit).collect())
+    }
+    #[instrument(name = "defer film snapshot", skip(self))]
+    async fn defer_film_snapshot(&self, film_id: i64) -> Result<()> {
+        let update = doc! {"$set": {"snapshot_failed_at": DateTime::now()}};
+        self.movies
+            .update_many(doc! {"film_id": film_id}, update)
+            .await?;
+        Ok(())
+    }
+    #[instrument(name = "get users serials", skip(self))]
+    async fn get_users_serials(&self, user_id: u64) -> Result<Vec<Serial>> {
+        let cursor = self
+            .serials
+            .find(doc! {"user_id": user_id as i64})
+            .sort(doc! {"_id": 1})
+            .await?;
+        Ok(cursor.try_collect().await?)
+    }
+    #[instrument(name = "get serial", skip(self))]
+    async fn get_serial(&self, user_id: u64, serial_id: i64) -> Result<Option<Serial>> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        Ok(self.serials.find_one(filter).await?)
+    }
+    #[instrument(name = "get users watched serials list", skip(self))]
+    async fn get_users_watched_serials_list(&self, user_id: u64) -> Result<Vec<Serial>> {
+        let mut cursor = self
+            .serials
+            .find(doc! {"user_id": user_id as i64, "watched": true})
+            .await?;
+        let mut result = Vec::new();
+        while let Some(serial) = cursor.try_next().await? {
+            result.push(serial);
+        }
+        Ok(result)
+    }
+    #[instrument(name = "get users serials page", skip(self))]
+    async fn get_users_serials_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Serial>> {
+        let filter = doc! {"user_id": user_id as i64, "watched": watched};
+        page(&self.serials, filter, skip, limit).await
+    }
+    #[instrument(name = "add serial to watch list", skip(self, snapshot))]
+    async fn add_serial_to_watch_list(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        snapshot: TitleSnapshot,
+    ) -> Result<AddOutcome> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let on_insert = insert_only_fields(&Serial::new(user_id, serial_id), &filter)?;
+        let existing = self
+            .serials
+            .find_one_and_update(
+                filter,
+                doc! {
+                    "$setOnInsert": on_insert,
+                    "$set": {"snapshot": bson::to_bson(&snapshot)?},
+                },
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .await?;
+        let outcome = AddOutcome::from_existing(existing.map(|s| s.viewing.watched));
+        tracing::info!("Add serial outcome: {outcome:?}");
+        Ok(outcome)
+    }
+    #[instrument(name = "import serial", skip_all)]
+    async fn import_serial(&self, serial: Serial) -> Result<AddOutcome> {
+        let filter = doc! {"user_id": serial.user_id as i64, "serial_id": serial.serial_id};
+        let on_insert = insert_only_fields(&serial, &filter)?;
+        let existing = self
+            .serials
+            .find_one_and_update(filter.clone(), doc! {"$setOnInsert": on_insert})
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .await?;
+        let outcome = AddOutcome::from_existing(existing.as_ref().map(|s| s.viewing.watched));
+        if let Some(mut existing) = existing
+            && serial.viewing.has_progress()
+        {
+            existing.viewing.merge_imported(&serial.viewing);
+            let update = doc! {"$set": bson::to_document(&existing.viewing)?};
+            self.serials.update_one(filter, update).await?;
+        }
+        Ok(outcome)
+    }
+    #[instrument(name = "mark serial as watched", skip(self))]
+    async fn watch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let now = DateTime::now();
+        let update = doc! {
+            "$set": doc!{"watched": true, "watched_at": now},
+            "$push": doc!{"watch_history": now},
+        };
+        let res = self.serials.update_one(filter, update).await?;
+        tracing::info!("Updated {} serials in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "mark serial as unwatched", skip(self))]
+    async fn unwatch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let none_rate: Option<f64> = None;
+        let update = doc! {
+            "$set": doc!{"watched": false, "my_rating": none_rate},
+            "$unset": doc!{"watched_at": "", "rated_at": "", "progress": ""},
+        };
+        let res = self.serials.update_one(filter, update).await?;
+        tracing::info!("Updated {} serials in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "rate serial", skip(self))]
+    async fn rate_serial(&self, user_id: u64, serial_id: i64, rate: f64) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let update = doc! {"$set": doc!{"my_rating": Some(rate), "rated_at": DateTime::now()}};
+        let res = self.serials.update_one(filter, update).await?;
+        tracing::info!("Updated {} serials in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "rate serial season", skip(self))]
+    async fn rate_serial_season(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        season: i64,
+        rate: f64,
+    ) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let rating = SeasonRating {
+            season,
+            rating: rate,
+            rated_at: DateTime::now(),
+        };
+        // One pipeline update replaces the season's rating atomically.
+        let update = vec![doc! {
+            "$set": {
+                "season_ratings": {
+                    "$sortArray": {
+                        "input": {
+                            "$concatArrays": [
+                                {
+                                    "$filter": {
+                                        "input": {"$ifNull": ["$season_ratings", []]},
+                                        "cond": {"$ne": ["$$this.season", season]},
+                                    }
+                                },
+                                [bson::to_bson(&rating)?],
+                            ]
+                        },
+                        "sortBy": {"season": 1},
+                    }
+                }
+            }
+        }];
+        let res = self.serials.update_one(filter, update).await?;
+        tracing::info!("Updated {} serials in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "set serial reminders", skip(self))]
+    async fn set_serial_reminders(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        enabled: bool,
+    ) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let update = doc! {"$set": {"reminders": enabled}};
+        let res = self.serials.update_one(filter, update).await?;
+        tracing::info!("Updated {} serials in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "get serials with reminders", skip(self))]
+    async fn serials_with_reminders(&self) -> Result<Vec<Serial>> {
+        let serials = self
+            .serials
+            .find(doc! {"reminders": true})
+            .await?
+            .try_collect()
+            .await?;
+        Ok(serials)
+    }
+    #[instrument(name = "set serial reminded", skip(self))]
+    async fn set_serial_reminded(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        episode: EpisodeNumber,
+    ) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let update = doc! {"$set": {"reminded": bson::to_bson(&episode)?}};
+        let res = self.serials.update_one(filter, update).await?;
+        tracing::info!("Updated {} serials in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "set serial progress", skip(self))]
+    async fn set_serial_progress(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        progress: EpisodeNumber,
+    ) -> Result<()> {
+        let filter = doc! {"user_id": user_id as i64, "serial_id": serial_id};
+        let update = doc! {"$set": {"progress": bson::to_bson(&progress)?}};
+        let res = self.serials.update_one(filter, update).await?;
+        tracing::info!("Updated {} serials in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "delete serial from watch list", skip(self))]
+    async fn delete_serial_from_watch_list(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let filter = doc! {
+            "$and": [
+                doc! { "user_id": user_id as i64},
+                doc! {"serial_id": serial_id},
+            ]
+        };
+        let result = self.serials.delete_one(filter).await?;
+        tracing::info!("Deleted {} documents", result.deleted_count);
+        Ok(())
+    }
+    #[instrument(name = "set serial snapshot", skip(self, snapshot))]
+    async fn set_serial_snapshot(&self, serial_id: i64, snapshot: TitleSnapshot) -> Result<()> {
+        let filter = doc! {"serial_id": serial_id};
+        let update = doc! {"$set": {"snapshot": bson::to_bson(&snapshot)?}};
+        let res = self.serials.update_many(filter, update).await?;
+        tracing::info!("Updated {} serial snapshots in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "get stale serial snapshots", skip(self))]
+    async fn stale_serial_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>> {
+        let ids = self
+            .serials
+            .distinct("serial_id", stale_snapshot_filter(older_than))
+            .await?;
+        Ok(ids.iter().filter_map(Bson::as_i64).take(limit).collect())
+    }
+    #[instrument(name = "defer serial snapshot", skip(self))]
+    async fn defer_serial_snapshot(&self, serial_id: i64) -> Result<()> {
+        let update = doc! {"$set": {"snapshot_failed_at": DateTime::now()}};
+        self.serials
+            .update_many(doc! {"serial_id": serial_id}, update)
+            .await?;
+        Ok(())
+    }
+    #[instrument(name = "get group settings", skip(self))]
+    async fn group_settings(&self, chat_id: i64) -> Result<Option<GroupSettings>> {
+        Ok(self.groups.find_one(doc! {"_id": chat_id}).await?)
+    }
+    #[instrument(name = "set group settings", skip(self))]
+    async fn set_group_settings(&self, settings: GroupSettings) -> Result<()> {
+        self.groups
+            .replace_one(doc! {"_id": settings.chat_id}, &settings)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+    #[instrument(name = "get group films page", skip(self))]
+    async fn get_group_films_page(
+        &self,
+        chat_id: i64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<GroupFilm>> {
+        let filter = doc! {"chat_id": chat_id, "watched": watched};
+        page(&self.group_films, filter, skip, limit).await
+    }
+    #[instrument(name = "get group film", skip(self))]
+    async fn get_group_film(&self, chat_id: i64, film_id: i64) -> Result<Option<GroupFilm>> {
+        let filter = doc! {"chat_id": chat_id, "film_id": film_id};
+        Ok(self.group_films.find_one(filter).await?)
+    }
+    #[instrument(name = "add group film", skip_all)]
+    async fn add_group_film(&self, film: GroupFilm) -> Result<AddOutcome> {
+        let filter = doc! {"chat_id": film.chat_id, "film_id": film.film_id};
+        let on_insert = insert_only_fields(&film, &filter)?;
+        let existing = self
+            .group_films
+            .find_one_and_update(filter, doc! {"$setOnInsert": on_insert})
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .await?;
+        Ok(AddOutcome::from_existing(existing.map(|f| f.watched)))
+    }
+    #[instrument(name = "mark group film as watched", skip(self))]
+    async fn watch_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+    ) -> Result<()> {
+        let filter = doc! {"chat_id": chat_id, "film_id": film_id};
+        let update = doc! {"$set": {"watched": true, "watched_at": DateTime::now()}};
+        let res = self.group_films.update_one(filter, update).await?;
+        attend(&self.group_films, chat_id, film_id, &member).await?;
+        tracing::info!("Updated {} group films in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "attend group film", skip(self))]
+    async fn attend_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+    ) -> Result<()> {
+        let modified = attend(&self.group_films, chat_id, film_id, &member).await?;
+        tracing::info!("Updated {modified} group films in db");
+        Ok(())
+    }
+    #[instrument(name = "rate group film", skip(self))]
+    async fn rate_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+        rating: f64,
+    ) -> Result<()> {
+        let filter = doc! {"chat_id": chat_id, "film_id": film_id};
+        let rating = MemberRating {
+            user_id: member.user_id,
+            name: member.name.clone(),
+            rating,
+            rated_at: DateTime::now(),
+        };
+        // One pipeline update replaces the member's rating atomically.
+        let update = vec![doc! {
+            "$set": {
+                "ratings": {
+                    "$concatArrays": [
+                        {
+                            "$filter": {
+                                "input": {"$ifNull": ["$ratings", []]},
+                                "cond": {"$ne": ["$$this.user_id", member.user_id as i64]},
+                            }
+                        },
+                        [bson::to_bson(&rating)?],
+                    ]
+                }
+            }
+        }];
+        let res = self.group_films.update_one(filter, update).await?;
+        attend(&self.group_films, chat_id, film_id, &member).await?;
+        tracing::info!("Updated {} group films in db", res.modified_count);
+        Ok(())
+    }
+    #[instrument(name = "delete group film", skip(self))]
+    async fn delete_group_film(&self, chat_id: i64, film_id: i64) -> Result<()> {
+        let filter = doc! {"chat_id": chat_id, "film_id": film_id};
+        let res = self.group_films.delete_one(filter).await?;
+        tracing::info!("Deleted {} group films from db", res.deleted_count);
+        Ok(())
+    }
+    #[instrument(name = "get poster file id", skip(self))]
+    async fn poster_file_id(&self, poster_path: &str) -> Result<Option<String>> {
+        let poster = self.posters.find_one(doc! {"_id": poster_path}).await?;
+        Ok(poster.map(|p| p.file_id))
+    }
+    #[instrument(name = "set poster file id", skip(self))]
+    async fn set_poster_file_id(&self, poster_path: &str, file_id: &str) -> Result<()> {
+        let poster = PosterRecord {
+            poster_path: poster_path.to_string(),
+            file_id: file_id.to_string(),
+        };
+        self.posters
+            .replace_one(doc! {"_id": poster_path}, poster)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+    #[instrument(name = "get user stats", skip(self))]
+    async fn user_stats(&self, user_id: u64) -> Result<UserStats> {
+        let film_minutes = bson!({"$cond": ["$watched", {"$ifNull": ["$snapshot.runtime", 0]}, 0]});
+        let serial_minutes =
+            bson!({"$multiply": [episodes_seen(), {"$ifNull": ["$snapshot.runtime", 0]}]});
+        let mut stats =
+            aggregate_stats(&self.movies, stats_pipeline(user_id, film_minutes), true).await?;
+        let serials = aggregate_stats(
+            &self.serials,
+            stats_pipeline(user_id, serial_minutes),
+            false,
+        )
+        .await?;
+        stats.merge(serials);
+        Ok(stats)
+    }
+}
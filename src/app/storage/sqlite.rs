@@ -0,0 +1,1003 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use mongodb::bson::DateTime;
+use rusqlite::{Connection, OptionalExtension, Params, params};
+use serde::{Serialize, de::DeserializeOwned};
+use tracing::instrument;
+
+use crate::app::{
+    models::{
+        EpisodeNumber, GroupFilm, GroupMember, GroupSettings, Movie, ReleaseWatch, Serial,
+        TitleSnapshot, Viewing,
+    },
+    releases,
+    stats::UserStats,
+    storage::{AddOutcome, Page, WatchListStore},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS movies (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    film_id INTEGER NOT NULL,
+    watched INTEGER NOT NULL DEFAULT 0,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS movies_user_watched ON movies (user_id, watched);
+CREATE TABLE IF NOT EXISTS serials (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    serial_id INTEGER NOT NULL,
+    watched INTEGER NOT NULL DEFAULT 0,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS serials_user_watched ON serials (user_id, watched);
+CREATE TABLE IF NOT EXISTS groups (
+    chat_id INTEGER PRIMARY KEY,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS group_films (
+    id INTEGER PRIMARY KEY,
+    chat_id INTEGER NOT NULL,
+    film_id INTEGER NOT NULL,
+    watched INTEGER NOT NULL DEFAULT 0,
+    data TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS group_films_chat_film ON group_films (chat_id, film_id);
+CREATE TABLE IF NOT EXISTS posters (
+    poster_path TEXT PRIMARY KEY,
+    file_id TEXT NOT NULL
+);
+";
+
+/// A schema change applied once, the applied version is kept in `PRAGMA user_version`.
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
+
+/// Ordered list of all migrations, versions must be strictly increasing.
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "remove duplicate watch list entries",
+    // Like the Mongo migration, a watched copy wins, then a rated one, then the oldest.
+    sql: "
+DELETE FROM movies WHERE id IN (
+    SELECT id FROM (
+        SELECT id, ROW_NUMBER() OVER (
+            PARTITION BY user_id, film_id
+            ORDER BY watched DESC, json_extract(data, '$.my_rating') IS NOT NULL DESC, id
+        ) AS copy FROM movies
+    ) WHERE copy > 1
+);
+CREATE UNIQUE INDEX IF NOT EXISTS movies_user_film ON movies (user_id, film_id);
+DELETE FROM serials WHERE id IN (
+    SELECT id FROM (
+        SELECT id, ROW_NUMBER() OVER (
+            PARTITION BY user_id, serial_id
+            ORDER BY watched DESC,
+                json_extract(data, '$.my_rating') IS NOT NULL
+                    OR COALESCE(json_array_length(data, '$.season_ratings'), 0) > 0 DESC,
+                id
+        ) AS copy FROM serials
+    ) WHERE copy > 1
+);
+CREATE UNIQUE INDEX IF NOT EXISTS serials_user_serial ON serials (user_id, serial_id);
+",
+}];
+
+/// Applies every migration newer than the database's `user_version`.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let current: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction()?;
+        let before = tx.total_changes();
+        tx.execute_batch(migration.sql)?;
+        let changed = tx.total_changes() - before;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+        tracing::info!(
+            "Migration {v} ({d}) changed {changed} rows",
+            v = migration.version,
+            d = migration.description
+        );
+    }
+    Ok(())
+}
+
+/// Embedded storage for small deployments.
+///
+/// Lookup columns are kept next to the full record, which is stored as JSON,
+/// so model changes don't require a table migration.
+#[derive(Clone, Debug)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    #[instrument(name = "open sqlite storage", skip_all)]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+    async fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| anyhow!("SQLite connection mutex is poisoned"))?;
+            f(&conn)
+        })
+        .await?
+    }
+}
+
+trait Record: Serialize + DeserializeOwned + Send + 'static {
+    const TABLE: &'static str;
+    const ID_COLUMN: &'static str;
+    fn viewing(&self) -> &Viewing;
+    fn viewing_mut(&mut self) -> &mut Viewing;
+    fn snapshot_mut(&mut self) -> &mut Option<TitleSnapshot>;
+    fn snapshot_due(&self, older_than: DateTime) -> bool;
+    fn defer_snapshot(&mut self);
+    /// Starts release alerts where the record kind has them.
+    fn track_release(&mut self, _today: &str) {}
+}
+impl Record for Movie {
+    const TABLE: &'static str = "movies";
+    const ID_COLUMN: &'static str = "film_id";
+    fn viewing(&self) -> &Viewing {
+        &self.viewing
+    }
+    fn viewing_mut(&mut self) -> &mut Viewing {
+        &mut self.viewing
+    }
+    fn snapshot_mut(&mut self) -> &mut Option<TitleSnapshot> {
+        &mut self.snapshot
+    }
+    fn snapshot_due(&self, older_than: DateTime) -> bool {
+        self.snapshot_due(older_than)
+    }
+    fn defer_snapshot(&mut self) {
+        self.snapshot_failed_at = Some(DateTime::now());
+    }
+    fn track_release(&mut self, today: &str) {
+        self.track_release(today)
+    }
+}
+impl Record for Serial {
+    const TABLE: &'static str = "serials";
+    const ID_COLUMN: &'static str = "serial_id";
+    fn viewing(&self) -> &Viewing {
+        &self.viewing
+    }
+    fn viewing_mut(&mut self) -> &mut Viewing {
+        &mut self.viewing
+    }
+    fn snapshot_mut(&mut self) -> &mut Option<TitleSnapshot> {
+        &mut self.snapshot
+    }
+    fn snapshot_due(&self, older_than: DateTime) -> bool {
+        self.snapshot_due(older_than)
+    }
+    fn defer_snapshot(&mut self) {
+        self.snapshot_failed_at = Some(DateTime::now());
+    }
+}
+
+/// Records of the user, all of them when `watched` is `None`.
+fn list<R: Record>(conn: &Connection, user_id: u64, watched: Option<bool>) -> Result<Vec<R>> {
+    let sql = format!(
+        "SELECT data FROM {t} WHERE user_id = ?1 AND (?2 IS NULL OR watched = ?2) ORDER BY id",
+        t = R::TABLE
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params![user_id as i64, watched], |row| {
+        row.get::<_, String>(0)
+    })?;
+    let mut result = Vec::new();
+    for data in rows {
+        result.push(serde_json::from_str(&data?)?);
+    }
+    Ok(result)
+}
+
+fn get<R: Record>(conn: &Connection, user_id: u64, item_id: i64) -> Result<Option<R>> {
+    let sql = format!(
+        "SELECT data FROM {t} WHERE user_id = ?1 AND {c} = ?2",
+        t = R::TABLE,
+        c = R::ID_COLUMN
+    );
+    let data: Option<String> = conn
+        .query_row(&sql, params![user_id as i64, item_id], |row| row.get(0))
+        .optional()?;
+    Ok(data.map(|d| serde_json::from_str(&d)).transpose()?)
+}
+
+fn page<R: Record>(
+    conn: &Connection,
+    user_id: u64,
+    watched: bool,
+    skip: u64,
+    limit: u64,
+) -> Result<Page<R>> {
+    let count_sql = format!(
+        "SELECT COUNT(*) FROM {t} WHERE user_id = ?1 AND watched = ?2",
+        t = R::TABLE
+    );
+    let total: i64 = conn.query_row(&count_sql, params![user_id as i64, watched], |row| {
+        row.get(0)
+    })?;
+    let sql = format!(
+        "SELECT data FROM {t} WHERE user_id = ?1 AND watched = ?2 ORDER BY id LIMIT ?3 OFFSET ?4",
+        t = R::TABLE
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(
+        params![user_id as i64, watched, limit as i64, skip as i64],
+        |row| row.get::<_, String>(0),
+    )?;
+    let mut items = Vec::new();
+    for data in rows {
+        items.push(serde_json::from_str(&data?)?);
+    }
+    Ok(Page {
+        items,
+        total: total as u64,
+    })
+}
+
+/// Inserts the record unless the user already has the item, returns whether it was inserted.
+fn insert<R: Record>(conn: &Connection, user_id: u64, item_id: i64, record: &R) -> Result<bool> {
+    let sql = format!(
+        "INSERT INTO {t} (user_id, {c}, watched, data) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (user_id, {c}) DO NOTHING",
+        t = R::TABLE,
+        c = R::ID_COLUMN
+    );
+    let inserted = conn.execute(
+        &sql,
+        params![
+            user_id as i64,
+            item_id,
+            record.viewing().watched,
+            serde_json::to_string(record)?
+        ],
+    )?;
+    Ok(inserted > 0)
+}
+
+/// Inserts the new record, or refreshes the snapshot of the one the user already has.
+fn add<R: Record>(
+    conn: &Connection,
+    user_id: u64,
+    item_id: i64,
+    mut record: R,
+) -> Result<AddOutcome> {
+    let snapshot = record.snapshot_mut().clone();
+    if insert(conn, user_id, item_id, &record)? {
+        return Ok(AddOutcome::Added);
+    }
+    let mut watched = false;
+    update(conn, user_id, item_id, |r: &mut R| {
+        *r.snapshot_mut() = snapshot.clone();
+        watched = r.viewing().watched;
+    })?;
+    Ok(AddOutcome::from_existing(Some(watched)))
+}
+
+fn import<R: Record>(
+    conn: &Connection,
+    user_id: u64,
+    item_id: i64,
+    record: R,
+) -> Result<AddOutcome> {
+    if insert(conn, user_id, item_id, &record)? {
+        return Ok(AddOutcome::Added);
+    }
+    let mut watched = false;
+    update(conn, user_id, item_id, |r: &mut R| {
+        watched = r.viewing().watched;
+        r.viewing_mut().merge_imported(record.viewing());
+    })?;
+    Ok(AddOutcome::from_existing(Some(watched)))
+}
+
+fn update<R: Record>(
+    conn: &Connection,
+    user_id: u64,
+    item_id: i64,
+    f: impl FnMut(&mut R),
+) -> Result<usize> {
+    let filter = format!("user_id = ?1 AND {c} = ?2", c = R::ID_COLUMN);
+    update_rows(conn, &filter, params![user_id as i64, item_id], f)
+}
+
+fn update_rows<R: Record>(
+    conn: &Connection,
+    filter: &str,
+    params: impl Params,
+    mut f: impl FnMut(&mut R),
+) -> Result<usize> {
+    // Records are rewritten whole, so the read and the write share a transaction.
+    let tx = conn.unchecked_transaction()?;
+    let sql = format!("SELECT id, data FROM {t} WHERE {filter}", t = R::TABLE);
+    let mut stmt = tx.prepare(&sql)?;
+    let rows = stmt
+        .query_map(params, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let sql = format!(
+        "UPDATE {t} SET watched = ?1, data = ?2 WHERE id = ?3",
+        t = R::TABLE
+    );
+    let mut updated = 0;
+    for (id, data) in rows {
+        let mut record: R = serde_json::from_str(&data)?;
+        f(&mut record);
+        updated += tx.execute(
+            &sql,
+            params![
+                record.viewing().watched,
+                serde_json::to_string(&record)?,
+                id
+            ],
+        )?;
+    }
+    drop(stmt);
+    tx.commit()?;
+    Ok(updated)
+}
+
+fn set_snapshot<R: Record>(
+    conn: &Connection,
+    item_id: i64,
+    snapshot: TitleSnapshot,
+) -> Result<usize> {
+    let filter = format!("{c} = ?1", c = R::ID_COLUMN);
+    let today = releases::today();
+    update_rows(conn, &filter, params![item_id], |r: &mut R| {
+        *r.snapshot_mut() = Some(snapshot.clone());
+        r.track_release(&today);
+    })
+}
+
+fn defer_snapshot<R: Record>(conn: &Connection, item_id: i64) -> Result<usize> {
+    let filter = format!("{c} = ?1", c = R::ID_COLUMN);
+    update_rows(conn, &filter, params![item_id], R::defer_snapshot)
+}
+
+fn stale_snapshots<R: Record>(
+    conn: &Connection,
+    older_than: DateTime,
+    limit: usize,
+) -> Result<Vec<i64>> {
+    let sql = format!("SELECT {c}, data FROM {t}", t = R::TABLE, c = R::ID_COLUMN);
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut ids = BTreeSet::new();
+    for row in rows {
+        let (item_id, data) = row?;
+        let record: R = serde_json::from_str(&data)?;
+        if record.snapshot_due(older_than) {
+            ids.insert(item_id);
+        }
+    }
+    Ok(ids.into_iter().take(limit).collect())
+}
+
+fn delete<R: Record>(conn: &Connection, user_id: u64, item_id: i64) -> Result<usize> {
+    let sql = format!(
+        "DELETE FROM {t} WHERE user_id = ?1 AND {c} = ?2",
+        t = R::TABLE,
+        c = R::ID_COLUMN
+    );
+    let deleted = conn.execute(&sql, params![user_id as i64, item_id])?;
+    Ok(deleted)
+}
+
+fn group_film(conn: &Connection, chat_id: i64, film_id: i64) -> Result<Option<GroupFilm>> {
+    let data = conn
+        .query_row(
+            "SELECT data FROM group_films WHERE chat_id = ?1 AND film_id = ?2",
+            params![chat_id, film_id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+    Ok(data.map(|d| serde_json::from_str(&d)).transpose()?)
+}
+
+fn group_films_page(
+    conn: &Connection,
+    chat_id: i64,
+    watched: bool,
+    skip: u64,
+    limit: u64,
+) -> Result<Page<GroupFilm>> {
+    let total: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM group_films WHERE chat_id = ?1 AND watched = ?2",
+        params![chat_id, watched],
+        |row| row.get(0),
+    )?;
+    let mut stmt = conn.prepare(
+        "SELECT data FROM group_films WHERE chat_id = ?1 AND watched = ?2 ORDER BY id LIMIT ?3 OFFSET ?4",
+    )?;
+    let rows = stmt.query_map(
+        params![chat_id, watched, limit as i64, skip as i64],
+        |row| row.get::<_, String>(0),
+    )?;
+    let mut items = Vec::new();
+    for data in rows {
+        items.push(serde_json::from_str(&data?)?);
+    }
+    Ok(Page {
+        items,
+        total: total as u64,
+    })
+}
+
+fn add_group_film(conn: &Connection, film: &GroupFilm) -> Result<AddOutcome> {
+    if let Some(existing) = group_film(conn, film.chat_id, film.film_id)? {
+        return Ok(AddOutcome::from_existing(Some(existing.watched)));
+    }
+    conn.execute(
+        "INSERT INTO group_films (chat_id, film_id, watched, data) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            film.chat_id,
+            film.film_id,
+            film.watched,
+            serde_json::to_string(film)?
+        ],
+    )?;
+    Ok(AddOutcome::Added)
+}
+
+fn update_group_film(
+    conn: &Connection,
+    chat_id: i64,
+    film_id: i64,
+    f: impl FnOnce(&mut GroupFilm),
+) -> Result<usize> {
+    let Some(mut film) = group_film(conn, chat_id, film_id)? else {
+        return Ok(0);
+    };
+    f(&mut film);
+    let updated = conn.execute(
+        "UPDATE group_films SET watched = ?1, data = ?2 WHERE chat_id = ?3 AND film_id = ?4",
+        params![
+            film.watched,
+            serde_json::to_string(&film)?,
+            chat_id,
+            film_id
+        ],
+    )?;
+    Ok(updated)
+}
+
+#[async_trait]
+impl WatchListStore for SqliteStorage {
+    #[instrument(name = "get users movies", skip(self))]
+    async fn get_users_movies(&self, user_id: u64) -> Result<Vec<Movie>> {
+        self.call(move |conn| list(conn, user_id, None)).await
+    }
+    #[instrument(name = "get users watched movies list", skip(self))]
+    async fn get_users_watched_movies_list(&self, user_id: u64) -> Result<Vec<Movie>> {
+        self.call(move |conn| list(conn, user_id, Some(true))).await
+    }
+    #[instrument(name = "get users movies page", skip(self))]
+    async fn get_users_movies_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Movie>> {
+        self.call(move |conn| page(conn, user_id, watched, skip, limit))
+            .await
+    }
+    #[instrument(name = "add film to watch list", skip(self, snapshot))]
+    async fn add_film_to_watch_list(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        snapshot: TitleSnapshot,
+        region: String,
+    ) -> Result<AddOutcome> {
+        let mut movie = Movie::new(user_id, film_id);
+        movie.snapshot = Some(snapshot);
+        movie.region = Some(region);
+        movie.track_release(&releases::today());
+        self.call(move |conn| add(conn, user_id, film_id, movie))
+            .await
+    }
+    #[instrument(name = "import film", skip_all)]
+    async fn import_film(&self, mut movie: Movie, region: String) -> Result<AddOutcome> {
+        movie.region = Some(region);
+        movie.track_release(&releases::today());
+        self.call(move |conn| import(conn, movie.user_id, movie.film_id, movie))
+            .await
+    }
+    #[instrument(name = "mark film as watched", skip(self))]
+    async fn watch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let updated = self
+            .call(move |conn| update(conn, user_id, film_id, |m: &mut Movie| m.viewing.watch()))
+            .await?;
+        tracing::info!("Updated {updated} films in db");
+        Ok(())
+    }
+    #[instrument(name = "mark film as unwatched", skip(self))]
+    async fn unwatch_film(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let updated = self
+            .call(move |conn| update(conn, user_id, film_id, |m: &mut Movie| m.viewing.unwatch()))
+            .await?;
+        tracing::info!("Updated {updated} films in db");
+        Ok(())
+    }
+    #[instrument(name = "rate film", skip(self))]
+    async fn rate_movie(&self, user_id: u64, film_id: i64, rate: f64) -> Result<()> {
+        let updated = self
+            .call(move |conn| update(conn, user_id, film_id, |m: &mut Movie| m.viewing.rate(rate)))
+            .await?;
+        tracing::info!("Updated {updated} films in db");
+        Ok(())
+    }
+    #[instrument(name = "set film release watch", skip(self))]
+    async fn set_film_release_watch(
+        &self,
+        user_id: u64,
+        film_id: i64,
+        release_watch: Option<ReleaseWatch>,
+    ) -> Result<()> {
+        let updated = self
+            .call(move |conn| {
+                update(conn, user_id, film_id, |m: &mut Movie| {
+                    m.release_watch = release_watch.clone()
+                })
+            })
+            .await?;
+        tracing::info!("Updated {updated} films in db");
+        Ok(())
+    }
+    #[instrument(name = "get films awaiting release", skip(self))]
+    async fn films_awaiting_release(&self) -> Result<Vec<Movie>> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT data FROM movies WHERE watched = 0 \
+                 AND json_extract(data, '$.release_watch') IS NOT NULL ORDER BY id",
+            )?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut movies = Vec::new();
+            for data in rows {
+                movies.push(serde_json::from_str(&data?)?);
+            }
+            Ok(movies)
+        })
+        .await
+    }
+    #[instrument(name = "delete film from watch list", skip(self))]
+    async fn delete_film_from_watch_list(&self, user_id: u64, film_id: i64) -> Result<()> {
+        let deleted = self
+            .call(move |conn| delete::<Movie>(conn, user_id, film_id))
+            .await?;
+        tracing::info!("Deleted {deleted} rows");
+        Ok(())
+    }
+    #[instrument(name = "set film snapshot", skip(self, snapshot))]
+    async fn set_film_snapshot(&self, film_id: i64, snapshot: TitleSnapshot) -> Result<()> {
+        let updated = self
+            .call(move |conn| set_snapshot::<Movie>(conn, film_id, snapshot))
+            .await?;
+        tracing::info!("Updated {updated} film snapshots in db");
+        Ok(())
+    }
+    #[instrument(name = "get stale film snapshots", skip(self))]
+    async fn stale_film_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>> {
+        self.call(move |conn| stale_snapshots::<Movie>(conn, older_than, limit))
+            .await
+    }
+    #[instrument(name = "defer film snapshot", skip(self))]
+    async fn defer_film_snapshot(&self, film_id: i64) -> Result<()> {
+        self.call(move |conn| defer_snapshot::<Movie>(conn, film_id))
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "get users serials", skip(self))]
+    async fn get_users_serials(&self, user_id: u64) -> Result<Vec<Serial>> {
+        self.call(move |conn| list(conn, user_id, None)).await
+    }
+    #[instrument(name = "get serial", skip(self))]
+    async fn get_serial(&self, user_id: u64, serial_id: i64) -> Result<Option<Serial>> {
+        self.call(move |conn| get(conn, user_id, serial_id)).await
+    }
+    #[instrument(name = "get users watched serials list", skip(self))]
+    async fn get_users_watched_serials_list(&self, user_id: u64) -> Result<Vec<Serial>> {
+        self.call(move |conn| list(conn, user_id, Some(true))).await
+    }
+    #[instrument(name = "get users serials page", skip(self))]
+    async fn get_users_serials_page(
+        &self,
+        user_id: u64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<Serial>> {
+        self.call(move |conn| page(conn, user_id, watched, skip, limit))
+            .await
+    }
+    #[instrument(name = "add serial to watch list", skip(self, snapshot))]
+    async fn add_serial_to_watch_list(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        snapshot: TitleSnapshot,
+    ) -> Result<AddOutcome> {
+        let mut serial = Serial::new(user_id, serial_id);
+        serial.snapshot = Some(snapshot);
+        self.call(move |conn| add(conn, user_id, serial_id, serial))
+            .await
+    }
+    #[instrument(name = "import serial", skip_all)]
+    async fn import_serial(&self, serial: Serial) -> Result<AddOutcome> {
+        self.call(move |conn| import(conn, serial.user_id, serial.serial_id, serial))
+            .await
+    }
+    #[instrument(name = "mark serial as watched", skip(self))]
+    async fn watch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let updated = self
+            .call(move |conn| update(conn, user_id, serial_id, |s: &mut Serial| s.viewing.watch()))
+            .await?;
+        tracing::info!("Updated {updated} serials in db");
+        Ok(())
+    }
+    #[instrument(name = "mark serial as unwatched", skip(self))]
+    async fn unwatch_serial(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let updated = self
+            .call(move |conn| update(conn, user_id, serial_id, |s: &mut Serial| s.unwatch()))
+            .await?;
+        tracing::info!("Updated {updated} serials in db");
+        Ok(())
+    }
+    #[instrument(name = "rate serial", skip(self))]
+    async fn rate_serial(&self, user_id: u64, serial_id: i64, rate: f64) -> Result<()> {
+        let updated = self
+            .call(move |conn| {
+                update(conn, user_id, serial_id, |s: &mut Serial| {
+                    s.viewing.rate(rate)
+                })
+            })
+            .await?;
+        tracing::info!("Updated {updated} serials in db");
+        Ok(())
+    }
+    #[instrument(name = "rate serial season", skip(self))]
+    async fn rate_serial_season(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        season: i64,
+        rate: f64,
+    ) -> Result<()> {
+        let updated = self
+            .call(move |conn| {
+                update(conn, user_id, serial_id, |s: &mut Serial| {
+                    s.rate_season(season, rate)
+                })
+            })
+            .await?;
+        tracing::info!("Updated {updated} serials in db");
+        Ok(())
+    }
+    #[instrument(name = "set serial reminders", skip(self))]
+    async fn set_serial_reminders(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        enabled: bool,
+    ) -> Result<()> {
+        let updated = self
+            .call(move |conn| {
+                update(conn, user_id, serial_id, |s: &mut Serial| {
+                    s.reminders = enabled
+                })
+            })
+            .await?;
+        tracing::info!("Updated {updated} serials in db");
+        Ok(())
+    }
+    #[instrument(name = "get serials with reminders", skip(self))]
+    async fn serials_with_reminders(&self) -> Result<Vec<Serial>> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT data FROM serials WHERE json_extract(data, '$.reminders') = 1 ORDER BY id",
+            )?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            let mut serials = Vec::new();
+            for data in rows {
+                serials.push(serde_json::from_str(&data?)?);
+            }
+            Ok(serials)
+        })
+        .await
+    }
+    #[instrument(name = "set serial reminded", skip(self))]
+    async fn set_serial_reminded(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        episode: EpisodeNumber,
+    ) -> Result<()> {
+        let updated = self
+            .call(move |conn| {
+                update(conn, user_id, serial_id, |s: &mut Serial| {
+                    s.reminded = Some(episode)
+                })
+            })
+            .await?;
+        tracing::info!("Updated {updated} serials in db");
+        Ok(())
+    }
+    #[instrument(name = "set serial progress", skip(self))]
+    async fn set_serial_progress(
+        &self,
+        user_id: u64,
+        serial_id: i64,
+        progress: EpisodeNumber,
+    ) -> Result<()> {
+        let updated = self
+            .call(move |conn| {
+                update(conn, user_id, serial_id, |s: &mut Serial| {
+                    s.progress = Some(progress)
+                })
+            })
+            .await?;
+        tracing::info!("Updated {updated} serials in db");
+        Ok(())
+    }
+    #[instrument(name = "delete serial from watch list", skip(self))]
+    async fn delete_serial_from_watch_list(&self, user_id: u64, serial_id: i64) -> Result<()> {
+        let deleted = self
+            .call(move |conn| delete::<Serial>(conn, user_id, serial_id))
+            .await?;
+        tracing::info!("Deleted {deleted} rows");
+        Ok(())
+    }
+    #[instrument(name = "set serial snapshot", skip(self, snapshot))]
+    async fn set_serial_snapshot(&self, serial_id: i64, snapshot: TitleSnapshot) -> Result<()> {
+        let updated = self
+            .call(move |conn| set_snapshot::<Serial>(conn, serial_id, snapshot))
+            .await?;
+        tracing::info!("Updated {updated} serial snapshots in db");
+        Ok(())
+    }
+    #[instrument(name = "get stale serial snapshots", skip(self))]
+    async fn stale_serial_snapshots(&self, older_than: DateTime, limit: usize) -> Result<Vec<i64>> {
+        self.call(move |conn| stale_snapshots::<Serial>(conn, older_than, limit))
+            .await
+    }
+    #[instrument(name = "defer serial snapshot", skip(self))]
+    async fn defer_serial_snapshot(&self, serial_id: i64) -> Result<()> {
+        self.call(move |conn| defer_snapshot::<Serial>(conn, serial_id))
+            .await?;
+        Ok(())
+    }
+    #[instrument(name = "get group settings", skip(self))]
+    async fn group_settings(&self, chat_id: i64) -> Result<Option<GroupSettings>> {
+        self.call(move |conn| {
+            let data = conn
+                .query_row(
+                    "SELECT data FROM groups WHERE chat_id = ?1",
+                    params![chat_id],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?;
+            Ok(data.map(|d| serde_json::from_str(&d)).transpose()?)
+        })
+        .await
+    }
+    #[instrument(name = "set group settings", skip(self))]
+    async fn set_group_settings(&self, settings: GroupSettings) -> Result<()> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO groups (chat_id, data) VALUES (?1, ?2)
+                 ON CONFLICT (chat_id) DO UPDATE SET data = excluded.data",
+                params![settings.chat_id, serde_json::to_string(&settings)?],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+    #[instrument(name = "get group films page", skip(self))]
+    async fn get_group_films_page(
+        &self,
+        chat_id: i64,
+        watched: bool,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Page<GroupFilm>> {
+        self.call(move |conn| group_films_page(conn, chat_id, watched, skip, limit))
+            .await
+    }
+    #[instrument(name = "get group film", skip(self))]
+    async fn get_group_film(&self, chat_id: i64, film_id: i64) -> Result<Option<GroupFilm>> {
+        self.call(move |conn| group_film(conn, chat_id, film_id))
+            .await
+    }
+    #[instrument(name = "add group film", skip_all)]
+    async fn add_group_film(&self, film: GroupFilm) -> Result<AddOutcome> {
+        self.call(move |conn| add_group_film(conn, &film)).await
+    }
+    #[instrument(name = "mark group film as watched", skip(self))]
+    async fn watch_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+    ) -> Result<()> {
+        self.call(move |conn| update_group_film(conn, chat_id, film_id, |f| f.watch(member)))
+            .await?;
+        Ok(())
+    }
+    #[instrument(name = "attend group film", skip(self))]
+    async fn attend_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+    ) -> Result<()> {
+        self.call(move |conn| update_group_film(conn, chat_id, film_id, |f| f.attend(member)))
+            .await?;
+        Ok(())
+    }
+    #[instrument(name = "rate group film", skip(self))]
+    async fn rate_group_film(
+        &self,
+        chat_id: i64,
+        film_id: i64,
+        member: GroupMember,
+        rating: f64,
+    ) -> Result<()> {
+        self.call(move |conn| {
+            update_group_film(conn, chat_id, film_id, |f| f.rate(member, rating))
+        })
+        .await?;
+        Ok(())
+    }
+    #[instrument(name = "delete group film", skip(self))]
+    async fn delete_group_film(&self, chat_id: i64, film_id: i64) -> Result<()> {
+        let deleted = self
+            .call(move |conn| {
+                Ok(conn.execute(
+                    "DELETE FROM group_films WHERE chat_id = ?1 AND film_id = ?2",
+                    params![chat_id, film_id],
+                )?)
+            })
+            .await?;
+        tracing::info!("Deleted {deleted} rows");
+        Ok(())
+    }
+    #[instrument(name = "get poster file id", skip(self))]
+    async fn poster_file_id(&self, poster_path: &str) -> Result<Option<String>> {
+        let poster_path = poster_path.to_string();
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT file_id FROM posters WHERE poster_path = ?1",
+                    params![poster_path],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+        .await
+    }
+    #[instrument(name = "set poster file id", skip(self))]
+    async fn set_poster_file_id(&self, poster_path: &str, file_id: &str) -> Result<()> {
+        let poster_path = poster_path.to_string();
+        let file_id = file_id.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO posters (poster_path, file_id) VALUES (?1, ?2)
+                 ON CONFLICT (poster_path) DO UPDATE SET file_id = excluded.file_id",
+                params![poster_path, file_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+    #[instrument(name = "get user stats", skip(self))]
+    async fn user_stats(&self, user_id: u64) -> Result<UserStats> {
+        let movies = self.get_users_movies(user_id).await?;
+        let serials = self.get_users_serials(user_id).await?;
+        Ok(UserStats::from_titles(&movies, &serials))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_movie(conn: &Connection, movie: &Movie) {
+        conn.execute(
+            "INSERT INTO movies (user_id, film_id, watched, data) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                movie.user_id as i64,
+                movie.film_id,
+                movie.viewing.watched,
+                serde_json::to_string(movie).unwrap()
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn duplicates_migration_keeps_the_watched_and_rated_copy() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let unwatched = Movie::new(1, 550);
+        let mut watched = Movie::new(1, 550);
+        watched.viewing.watch();
+        let mut rated = Movie::new(1, 550);
+        rated.viewing.watch();
+        rated.viewing.rate(9.0);
+        insert_movie(&conn, &unwatched);
+        insert_movie(&conn, &watched);
+        insert_movie(&conn, &rated);
+        insert_movie(&conn, &Movie::new(2, 550));
+
+        migrate(&mut conn).unwrap();
+        let kept: Vec<Movie> = list(&conn, 1, None).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].viewing.my_rating, Some(9.0));
+        assert_eq!(list::<Movie>(&conn, 2, None).unwrap().len(), 1);
+
+        // Applied migrations don't run again and the unique index now rejects duplicates.
+        migrate(&mut conn).unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 1);
+        assert!(
+            conn.execute(
+                "INSERT INTO movies (user_id, film_id, watched, data) VALUES (1, 550, 0, '{}')",
+                [],
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn import_takes_the_rating_over_an_unwatched_entry() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        migrate(&mut conn).unwrap();
+        insert_movie(&conn, &Movie::new(1, 550));
+        let mut imported = Movie::new(1, 550);
+        imported.viewing.watch();
+        imported.viewing.rate(7.0);
+
+        let outcome = import(&conn, 1, 550, imported).unwrap();
+        assert!(matches!(outcome, AddOutcome::AlreadyInWatchList));
+        let movies: Vec<Movie> = list(&conn, 1, Some(true)).unwrap();
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].viewing.my_rating, Some(7.0));
+        assert_eq!(movies[0].viewing.watch_history.len(), 1);
+    }
+}
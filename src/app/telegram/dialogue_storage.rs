@@ -0,0 +1,241 @@
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use futures::{TryStreamExt, future::BoxFuture};
+use mongodb::{
+    Collection, IndexModel,
+    bson::{DateTime, Document, doc, from_document},
+    error::Error,
+    options::IndexOptions,
+};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    dispatching::dialogue::{InMemStorage, InMemStorageError, Storage},
+    types::ChatId,
+};
+
+use crate::app::telegram::State;
+
+/// Dialogue storage selected at startup, in-memory unless configured otherwise.
+#[derive(Debug)]
+pub enum DialogueStorage {
+    InMemory(Arc<InMemStorage<State>>),
+    Mongo(Arc<MongoDialogueStorage>),
+}
+
+#[derive(Debug)]
+pub enum DialogueStorageError {
+    InMemory(InMemStorageError),
+    Mongo(Error),
+}
+
+impl Display for DialogueStorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DialogueStorageError::InMemory(e) => write!(f, "in-memory dialogue storage: {e}"),
+            DialogueStorageError::Mongo(e) => write!(f, "mongo dialogue storage: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DialogueStorageError {}
+
+impl DialogueStorage {
+    pub fn in_memory() -> Arc<Self> {
+        Arc::new(Self::InMemory(InMemStorage::new()))
+    }
+
+    pub async fn mongo(dialogues: Collection<DialogueRecord>, ttl: Duration) -> Arc<Self> {
+        Arc::new(Self::Mongo(
+            MongoDialogueStorage::open(dialogues, ttl).await,
+        ))
+    }
+}
+
+impl Storage<State> for DialogueStorage {
+    type Error = DialogueStorageError;
+
+    fn remove_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            match self.as_ref() {
+                DialogueStorage::InMemory(s) => s
+                    .clone()
+                    .remove_dialogue(chat_id)
+                    .await
+                    .map_err(DialogueStorageError::InMemory),
+                DialogueStorage::Mongo(s) => s
+                    .clone()
+                    .remove_dialogue(chat_id)
+                    .await
+                    .map_err(DialogueStorageError::Mongo),
+            }
+        })
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+        dialogue: State,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            match self.as_ref() {
+                DialogueStorage::InMemory(s) => s
+                    .clone()
+                    .update_dialogue(chat_id, dialogue)
+                    .await
+                    .map_err(DialogueStorageError::InMemory),
+                DialogueStorage::Mongo(s) => s
+                    .clone()
+                    .update_dialogue(chat_id, dialogue)
+                    .await
+                    .map_err(DialogueStorageError::Mongo),
+            }
+        })
+    }
+
+    fn get_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<Option<State>, Self::Error>> {
+        Box::pin(async move {
+            match self.as_ref() {
+                DialogueStorage::InMemory(s) => s
+                    .clone()
+                    .get_dialogue(chat_id)
+                    .await
+                    .map_err(DialogueStorageError::InMemory),
+                DialogueStorage::Mongo(s) => s
+                    .clone()
+                    .get_dialogue(chat_id)
+                    .await
+                    .map_err(DialogueStorageError::Mongo),
+            }
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DialogueRecord {
+    #[serde(rename = "_id")]
+    chat_id: i64,
+    state: State,
+    updated_at: DateTime,
+}
+
+const TTL_INDEX: &str = "dialogue_ttl";
+
+/// Dialogue storage in Mongo, states not updated within `ttl` are treated as gone.
+#[derive(Debug)]
+pub struct MongoDialogueStorage {
+    dialogues: Collection<DialogueRecord>,
+    ttl: Duration,
+}
+
+impl MongoDialogueStorage {
+    /// Creates the storage and the TTL index that lets Mongo purge stale states.
+    pub async fn open(dialogues: Collection<DialogueRecord>, ttl: Duration) -> Arc<Self> {
+        if let Err(e) = ensure_ttl_index(&dialogues, ttl).await {
+            tracing::warn!("Failed to create dialogue TTL index: {e}");
+        }
+        Arc::new(Self { dialogues, ttl })
+    }
+
+    fn expired_before(&self) -> DateTime {
+        DateTime::from_millis(DateTime::now().timestamp_millis() - self.ttl.as_millis() as i64)
+    }
+}
+
+/// Creates the TTL index, or recreates it when it was built with another TTL:
+/// `createIndex` refuses to change the options of an existing index.
+async fn ensure_ttl_index(
+    dialogues: &Collection<DialogueRecord>,
+    ttl: Duration,
+) -> Result<(), Error> {
+    let indexes: Vec<IndexModel> = dialogues.list_indexes().await?.try_collect().await?;
+    let existing = indexes
+        .into_iter()
+        .filter_map(|index| index.options)
+        .find(|options| options.name.as_deref() == Some(TTL_INDEX));
+    if let Some(options) = existing {
+        if options.expire_after.map(|e| e.as_secs()) == Some(ttl.as_secs()) {
+            return Ok(());
+        }
+        tracing::info!(
+            "Dialogue TTL changed from {old:?} to {ttl:?}, recreating the index",
+            old = options.expire_after
+        );
+        dialogues.drop_index(TTL_INDEX).await?;
+    }
+    let index = IndexModel::builder()
+        .keys(doc! {"updated_at": 1})
+        .options(
+            IndexOptions::builder()
+                .name(TTL_INDEX.to_string())
+                .expire_after(ttl)
+                .build(),
+        )
+        .build();
+    dialogues.create_index(index).await?;
+    Ok(())
+}
+
+impl Storage<State> for MongoDialogueStorage {
+    type Error = Error;
+
+    fn remove_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            self.dialogues.delete_one(doc! {"_id": chat_id.0}).await?;
+            Ok(())
+        })
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+        dialogue: State,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let record = DialogueRecord {
+                chat_id: chat_id.0,
+                state: dialogue,
+                updated_at: DateTime::now(),
+            };
+            self.dialogues
+                .replace_one(doc! {"_id": chat_id.0}, record)
+                .upsert(true)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn get_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<Option<State>, Self::Error>> {
+        Box::pin(async move {
+            // The TTL monitor only runs once a minute, so expiry is checked here too.
+            let record = self
+                .dialogues
+                .clone_with_type::<Document>()
+                .find_one(doc! {"_id": chat_id.0, "updated_at": {"$gte": self.expired_before()}})
+                .await?;
+            let Some(record) = record else {
+                return Ok(None);
+            };
+            // A state saved by an older deploy may no longer decode, the chat starts over then.
+            match from_document::<DialogueRecord>(record) {
+                Ok(record) => Ok(Some(record.state)),
+                Err(e) => {
+                    tracing::warn!("Dropping undecodable dialogue of chat {chat_id}: {e}");
+                    self.dialogues.delete_one(doc! {"_id": chat_id.0}).await?;
+                    Ok(None)
+                }
+            }
+        })
+    }
+}
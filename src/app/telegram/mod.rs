@@ -1,15 +1,19 @@
+mod dialogue_storage;
 mod router;
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, sync::Arc};
 
 use anyhow::{Result, anyhow};
 use teloxide::{
-    dispatching::dialogue::InMemStorage,
     prelude::*,
-    types::{InlineKeyboardButton, KeyboardButton, KeyboardMarkup},
+    types::{Chat, InlineKeyboardButton, KeyboardButton, KeyboardMarkup},
     utils::command::BotCommands,
 };
 
-use crate::app::{storage::Storage, tmdb::Tmdb};
+use serde::{Deserialize, Serialize};
+
+use crate::app::{import::PendingImports, storage::Storage, tmdb::Tmdb};
+
+pub use dialogue_storage::{DialogueRecord, DialogueStorage};
 
 const CANCEL_CALLBACK: &str = "cancel";
 const SEARCH_FILM_CALLBACK: &str = "search_films";
@@ -28,8 +32,27 @@ const MARK_SERIAL_WATCHED_CALLBACK: &str = "mark_serial_watched";
 const MARK_SERIAL_UNWATCHED_CALLBACK: &str = "mark_serial_unwatched";
 const RATE_SERIAL_CALLBACK: &str = "rate_serial";
 const DELETE_SERIAL_CALLBACK: &str = "delete_serial";
+const USER_LIST_PAGE_CALLBACK: &str = "user_list";
+const RESOLVE_IMPORT_CALLBACK: &str = "import_pick";
+const SKIP_IMPORT_CALLBACK: &str = "import_skip";
+const SERIAL_PROGRESS_CALLBACK: &str = "serial_progress";
+const PROGRESS_SEASON_CALLBACK: &str = "progress_season";
+const SET_SERIAL_PROGRESS_CALLBACK: &str = "set_progress";
+const SHOW_SEASONS_CALLBACK: &str = "seasons";
+const SEASON_EPISODES_CALLBACK: &str = "season";
+const GET_EPISODE_DETAILS_CALLBACK: &str = "episode";
+const RATE_SERIAL_SEASON_CALLBACK: &str = "rate_season";
+const CHOOSE_RATING_SEASON_CALLBACK: &str = "rate_season_pick";
+const SERIAL_REMINDERS_CALLBACK: &str = "reminders";
+const GROUP_LIST_PAGE_CALLBACK: &str = "group_list";
+const MARK_GROUP_FILM_WATCHED_CALLBACK: &str = "group_watched";
+const ATTEND_GROUP_FILM_CALLBACK: &str = "group_attend";
+const RATE_GROUP_FILM_CALLBACK: &str = "group_rate";
+const SET_GROUP_FILM_RATING_CALLBACK: &str = "group_rating";
+const DELETE_GROUP_FILM_CALLBACK: &str = "group_delete";
+const GROUP_DELETE_RESTRICTION_CALLBACK: &str = "group_restrict";
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub enum State {
     #[default]
     Start,
@@ -41,9 +64,13 @@ pub enum State {
     SerialRateReceived {
         serial_id: i64,
     },
+    SerialSeasonRateReceived {
+        serial_id: i64,
+        season: i64,
+    },
 }
 
-pub type MyDialogue = Dialogue<State, InMemStorage<State>>;
+pub type MyDialogue = Dialogue<State, DialogueStorage>;
 
 /// These commands are supported:
 #[derive(teloxide::macros::BotCommands, Clone)]
@@ -55,6 +82,12 @@ pub enum Command {
     Start,
     /// Cancel.
     Cancel,
+    /// Export your films and serials as CSV and JSON.
+    Export,
+    /// Show your viewing statistics.
+    Stats,
+    /// Show the shared watch list of this group chat.
+    Group,
 }
 
 const FILM_TO_WATCH: &str = "🤔 Отложенные фильмы";
@@ -63,6 +96,13 @@ const SEARCH_FILM: &str = "🔎 Найти фильм";
 const WATCHED_FILMS: &str = "💼 Просмотренные фильмы";
 const SEARCH_SERIAL: &str = "🔎 Найти сериал";
 const WATCHED_SERIALS: &str = "💼 Просмотренные сериалы";
+const YEAR_REVIEW: &str = "🎉 Итоги года";
+const GROUP_WATCH_LIST: &str = "👥 Список группы";
+
+/// Group and supergroup chats share one watch list between their members.
+pub fn is_group_chat(chat: &Chat) -> bool {
+    chat.is_group() || chat.is_supergroup()
+}
 
 #[derive(Debug, Clone)]
 pub enum TextCommand {
@@ -72,6 +112,8 @@ pub enum TextCommand {
     WatchedFilms,
     SearchSerial,
     WatchedSerials,
+    YearReview,
+    GroupWatchList,
 }
 impl TextCommand {
     pub fn keyboard() -> KeyboardMarkup {
@@ -88,6 +130,19 @@ impl TextCommand {
                 TextCommand::WatchedFilms.into(),
                 TextCommand::WatchedSerials.into(),
             ])
+            .append_row(vec![TextCommand::YearReview.into()])
+            .resize_keyboard()
+    }
+    /// The personal lists make no sense in a group, so groups get the shared list instead.
+    pub fn keyboard_for(chat: &Chat) -> KeyboardMarkup {
+        if !is_group_chat(chat) {
+            return Self::keyboard();
+        }
+        KeyboardMarkup::default()
+            .append_row(vec![
+                TextCommand::SearchFilm.into(),
+                TextCommand::GroupWatchList.into(),
+            ])
             .resize_keyboard()
     }
 }
@@ -105,6 +160,8 @@ impl Display for TextCommand {
             TextCommand::WatchedFilms => WATCHED_FILMS,
             TextCommand::SearchSerial => SEARCH_SERIAL,
             TextCommand::WatchedSerials => WATCHED_SERIALS,
+            TextCommand::YearReview => YEAR_REVIEW,
+            TextCommand::GroupWatchList => GROUP_WATCH_LIST,
         };
         write!(f, "{string}")
     }
@@ -119,10 +176,48 @@ impl FromStr for TextCommand {
             WATCHED_FILMS => Ok(Self::WatchedFilms),
             SEARCH_SERIAL => Ok(Self::SearchSerial),
             WATCHED_SERIALS => Ok(Self::WatchedSerials),
+            YEAR_REVIEW => Ok(Self::YearReview),
+            GROUP_WATCH_LIST => Ok(Self::GroupWatchList),
             _ => Err(anyhow!("Not a text command")),
         }
     }
 }
+const FILMS_TO_WATCH_LIST: &str = "films";
+const WATCHED_FILMS_LIST: &str = "watched_films";
+const SERIALS_TO_WATCH_LIST: &str = "serials";
+const WATCHED_SERIALS_LIST: &str = "watched_serials";
+
+/// One of the user's paginated lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserList {
+    FilmsToWatch,
+    WatchedFilms,
+    SerialsToWatch,
+    WatchedSerials,
+}
+impl Display for UserList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            UserList::FilmsToWatch => FILMS_TO_WATCH_LIST,
+            UserList::WatchedFilms => WATCHED_FILMS_LIST,
+            UserList::SerialsToWatch => SERIALS_TO_WATCH_LIST,
+            UserList::WatchedSerials => WATCHED_SERIALS_LIST,
+        };
+        write!(f, "{string}")
+    }
+}
+impl FromStr for UserList {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            FILMS_TO_WATCH_LIST => Ok(Self::FilmsToWatch),
+            WATCHED_FILMS_LIST => Ok(Self::WatchedFilms),
+            SERIALS_TO_WATCH_LIST => Ok(Self::SerialsToWatch),
+            WATCHED_SERIALS_LIST => Ok(Self::WatchedSerials),
+            _ => Err(anyhow!("Not a user list")),
+        }
+    }
+}
 #[derive(Clone, Debug)]
 pub enum MyCallback {
     Cancel,
@@ -144,6 +239,28 @@ pub enum MyCallback {
     MarkSerialUnWatched { id: i64 },
     RateSerial { id: i64 },
     DeleteSerial { id: i64 },
+    UserListNextPage { list: UserList, page: u32 },
+    UserListPreviousPage { list: UserList, page: u32 },
+    ResolveImport { key: u64, id: i64 },
+    SkipImport { key: u64 },
+    SerialProgress { id: i64 },
+    ChooseProgressSeason { id: i64, season: i64 },
+    SetSerialProgress { id: i64, season: i64, episode: i64 },
+    ShowSeasons { id: i64 },
+    SeasonEpisodesNextPage { id: i64, season: i64, page: u32 },
+    SeasonEpisodesPreviousPage { id: i64, season: i64, page: u32 },
+    GetEpisodeDetails { id: i64, season: i64, episode: i64 },
+    RateSerialSeason { id: i64 },
+    ChooseRatingSeason { id: i64, season: i64 },
+    SerialReminders { id: i64, enabled: bool },
+    GroupListNextPage { watched: bool, page: u32 },
+    GroupListPreviousPage { watched: bool, page: u32 },
+    MarkGroupFilmWatched { id: i64 },
+    AttendGroupFilm { id: i64 },
+    RateGroupFilm { id: i64 },
+    SetGroupFilmRating { id: i64, rating: u8 },
+    DeleteGroupFilm { id: i64 },
+    GroupDeleteRestriction { restrict: bool },
 }
 impl MyCallback {
     fn data(&self) -> String {
@@ -185,6 +302,65 @@ impl MyCallback {
             }
             MyCallback::RateSerial { id } => format!("{RATE_SERIAL_CALLBACK}:{id}"),
             MyCallback::DeleteSerial { id } => format!("{DELETE_SERIAL_CALLBACK}:{id}"),
+            MyCallback::UserListNextPage { list, page }
+            | MyCallback::UserListPreviousPage { list, page } => {
+                format!("{USER_LIST_PAGE_CALLBACK}:{list}:{page}")
+            }
+            MyCallback::ResolveImport { key, id } => {
+                format!("{RESOLVE_IMPORT_CALLBACK}:{key}:{id}")
+            }
+            MyCallback::SkipImport { key } => format!("{SKIP_IMPORT_CALLBACK}:{key}"),
+            MyCallback::SerialProgress { id } => format!("{SERIAL_PROGRESS_CALLBACK}:{id}"),
+            MyCallback::ChooseProgressSeason { id, season } => {
+                format!("{PROGRESS_SEASON_CALLBACK}:{id}:{season}")
+            }
+            MyCallback::SetSerialProgress {
+                id,
+                season,
+                episode,
+            } => format!("{SET_SERIAL_PROGRESS_CALLBACK}:{id}:{season}:{episode}"),
+            MyCallback::ShowSeasons { id } => format!("{SHOW_SEASONS_CALLBACK}:{id}"),
+            MyCallback::SeasonEpisodesNextPage { id, season, page }
+            | MyCallback::SeasonEpisodesPreviousPage { id, season, page } => {
+                format!("{SEASON_EPISODES_CALLBACK}:{id}:{season}:{page}")
+            }
+            MyCallback::GetEpisodeDetails {
+                id,
+                season,
+                episode,
+            } => format!("{GET_EPISODE_DETAILS_CALLBACK}:{id}:{season}:{episode}"),
+            MyCallback::RateSerialSeason { id } => format!("{RATE_SERIAL_SEASON_CALLBACK}:{id}"),
+            MyCallback::ChooseRatingSeason { id, season } => {
+                format!("{CHOOSE_RATING_SEASON_CALLBACK}:{id}:{season}")
+            }
+            MyCallback::SerialReminders { id, enabled } => {
+                format!(
+                    "{SERIAL_REMINDERS_CALLBACK}:{id}:{e}",
+                    e = u8::from(*enabled)
+                )
+            }
+            MyCallback::GroupListNextPage { watched, page }
+            | MyCallback::GroupListPreviousPage { watched, page } => {
+                format!(
+                    "{GROUP_LIST_PAGE_CALLBACK}:{w}:{page}",
+                    w = u8::from(*watched)
+                )
+            }
+            MyCallback::MarkGroupFilmWatched { id } => {
+                format!("{MARK_GROUP_FILM_WATCHED_CALLBACK}:{id}")
+            }
+            MyCallback::AttendGroupFilm { id } => format!("{ATTEND_GROUP_FILM_CALLBACK}:{id}"),
+            MyCallback::RateGroupFilm { id } => format!("{RATE_GROUP_FILM_CALLBACK}:{id}"),
+            MyCallback::SetGroupFilmRating { id, rating } => {
+                format!("{SET_GROUP_FILM_RATING_CALLBACK}:{id}:{rating}")
+            }
+            MyCallback::DeleteGroupFilm { id } => format!("{DELETE_GROUP_FILM_CALLBACK}:{id}"),
+            MyCallback::GroupDeleteRestriction { restrict } => {
+                format!(
+                    "{GROUP_DELETE_RESTRICTION_CALLBACK}:{r}",
+                    r = u8::from(*restrict)
+                )
+            }
         }
     }
 }
@@ -197,14 +373,19 @@ impl Display for MyCallback {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let string = match self {
             MyCallback::Cancel => "🔙 Вернуться в меню",
-            MyCallback::SearchFilmsNextPage { .. } | MyCallback::SearchSerialNextPage { .. } => {
-                "⏭️ Дальше"
-            }
+            MyCallback::SearchFilmsNextPage { .. }
+            | MyCallback::SearchSerialNextPage { .. }
+            | MyCallback::UserListNextPage { .. }
+            | MyCallback::SeasonEpisodesNextPage { .. }
+            | MyCallback::GroupListNextPage { .. } => "⏭️ Дальше",
             MyCallback::SearchFilmsPreviousPage { .. }
-            | MyCallback::SearchSerialPreviousPage { .. } => "⏮️ Назад",
-            MyCallback::GetFilmsDetails { .. } | MyCallback::GetSerialDetails { .. } => {
-                "🕵️ Подробнее"
-            }
+            | MyCallback::SearchSerialPreviousPage { .. }
+            | MyCallback::UserListPreviousPage { .. }
+            | MyCallback::SeasonEpisodesPreviousPage { .. }
+            | MyCallback::GroupListPreviousPage { .. } => "⏮️ Назад",
+            MyCallback::GetFilmsDetails { .. }
+            | MyCallback::GetSerialDetails { .. }
+            | MyCallback::GetEpisodeDetails { .. } => "🕵️ Подробнее",
             MyCallback::AddFilmToWatchList { .. } | MyCallback::AddSerialToWatchList { .. } => {
                 "🤔 Буду смотреть"
             }
@@ -212,13 +393,32 @@ impl Display for MyCallback {
             MyCallback::MarkFilmWatched { .. } | MyCallback::MarkSerialWatched { .. } => {
                 "✅ Отметить просмотренным"
             }
-            MyCallback::RateFilm { .. } | MyCallback::RateSerial { .. } => "🧮 Поставить оценку",
+            MyCallback::RateFilm { .. }
+            | MyCallback::RateSerial { .. }
+            | MyCallback::RateGroupFilm { .. } => "🧮 Поставить оценку",
             MyCallback::MarkFilmUnWatched { .. } | MyCallback::MarkSerialUnWatched { .. } => {
                 "👁️  Отметить непросмотренным"
             }
-            MyCallback::DeleteFilm { .. } | MyCallback::DeleteSerial { .. } => {
-                "🗑️ Удалить из списка"
+            MyCallback::DeleteFilm { .. }
+            | MyCallback::DeleteSerial { .. }
+            | MyCallback::DeleteGroupFilm { .. } => "🗑️ Удалить из списка",
+            MyCallback::ResolveImport { .. } => "✅ Выбрать",
+            MyCallback::SkipImport { .. } => "🚫 Пропустить",
+            MyCallback::SerialProgress { .. } => "📺 Отметить серии",
+            MyCallback::ChooseProgressSeason { .. } => "📀 Сезон",
+            MyCallback::SetSerialProgress { .. } => "🎞️ Серия",
+            MyCallback::ShowSeasons { .. } => "📀 Сезоны",
+            MyCallback::RateSerialSeason { .. } => "📀 Оценить сезон",
+            MyCallback::ChooseRatingSeason { .. } => "📀 Сезон",
+            MyCallback::SerialReminders { enabled: true, .. } => "🔔 Напоминать о сериях",
+            MyCallback::SerialReminders { enabled: false, .. } => "🔕 Не напоминать",
+            MyCallback::MarkGroupFilmWatched { .. } => "🍿 Посмотрели",
+            MyCallback::AttendGroupFilm { .. } => "🙋 Я тоже смотрел(а)",
+            MyCallback::SetGroupFilmRating { .. } => "⭐ Оценка",
+            MyCallback::GroupDeleteRestriction { restrict: true } => {
+                "🔒 Удалять могут только владелец и автор"
             }
+            MyCallback::GroupDeleteRestriction { restrict: false } => "🔓 Удалять могут все",
         };
         write!(f, "{string}")
     }
@@ -306,6 +506,123 @@ impl FromStr for MyCallback {
                     let id = data.parse()?;
                     return Ok(Self::DeleteSerial { id });
                 }
+                USER_LIST_PAGE_CALLBACK => {
+                    if let Some((list, page)) = data.split_once(':') {
+                        let list = list.parse()?;
+                        let page = page.parse()?;
+                        return Ok(Self::UserListNextPage { list, page });
+                    }
+                }
+                RESOLVE_IMPORT_CALLBACK => {
+                    if let Some((key, id)) = data.split_once(':') {
+                        let key = key.parse()?;
+                        let id = id.parse()?;
+                        return Ok(Self::ResolveImport { key, id });
+                    }
+                }
+                SKIP_IMPORT_CALLBACK => {
+                    let key = data.parse()?;
+                    return Ok(Self::SkipImport { key });
+                }
+                SERIAL_PROGRESS_CALLBACK => {
+                    let id = data.parse()?;
+                    return Ok(Self::SerialProgress { id });
+                }
+                PROGRESS_SEASON_CALLBACK => {
+                    if let Some((id, season)) = data.split_once(':') {
+                        let id = id.parse()?;
+                        let season = season.parse()?;
+                        return Ok(Self::ChooseProgressSeason { id, season });
+                    }
+                }
+                SET_SERIAL_PROGRESS_CALLBACK => {
+                    if let [id, season, episode] = data.split(':').collect::<Vec<_>>()[..] {
+                        let id = id.parse()?;
+                        let season = season.parse()?;
+                        let episode = episode.parse()?;
+                        return Ok(Self::SetSerialProgress {
+                            id,
+                            season,
+                            episode,
+                        });
+                    }
+                }
+                SHOW_SEASONS_CALLBACK => {
+                    let id = data.parse()?;
+                    return Ok(Self::ShowSeasons { id });
+                }
+                SEASON_EPISODES_CALLBACK => {
+                    if let [id, season, page] = data.split(':').collect::<Vec<_>>()[..] {
+                        let id = id.parse()?;
+                        let season = season.parse()?;
+                        let page = page.parse()?;
+                        return Ok(Self::SeasonEpisodesNextPage { id, season, page });
+                    }
+                }
+                RATE_SERIAL_SEASON_CALLBACK => {
+                    let id = data.parse()?;
+                    return Ok(Self::RateSerialSeason { id });
+                }
+                CHOOSE_RATING_SEASON_CALLBACK => {
+                    if let Some((id, season)) = data.split_once(':') {
+                        let id = id.parse()?;
+                        let season = season.parse()?;
+                        return Ok(Self::ChooseRatingSeason { id, season });
+                    }
+                }
+                SERIAL_REMINDERS_CALLBACK => {
+                    if let Some((id, enabled)) = data.split_once(':') {
+                        let id = id.parse()?;
+                        let enabled = enabled == "1";
+                        return Ok(Self::SerialReminders { id, enabled });
+                    }
+                }
+                GROUP_LIST_PAGE_CALLBACK => {
+                    if let Some((watched, page)) = data.split_once(':') {
+                        let watched = watched == "1";
+                        let page = page.parse()?;
+                        return Ok(Self::GroupListNextPage { watched, page });
+                    }
+                }
+                MARK_GROUP_FILM_WATCHED_CALLBACK => {
+                    let id = data.parse()?;
+                    return Ok(Self::MarkGroupFilmWatched { id });
+                }
+                ATTEND_GROUP_FILM_CALLBACK => {
+                    let id = data.parse()?;
+                    return Ok(Self::AttendGroupFilm { id });
+                }
+                RATE_GROUP_FILM_CALLBACK => {
+                    let id = data.parse()?;
+                    return Ok(Self::RateGroupFilm { id });
+                }
+                SET_GROUP_FILM_RATING_CALLBACK => {
+                    if let Some((id, rating)) = data.split_once(':') {
+                        let id = id.parse()?;
+                        let rating = rating.parse()?;
+                        return Ok(Self::SetGroupFilmRating { id, rating });
+                    }
+                }
+                DELETE_GROUP_FILM_CALLBACK => {
+                    let id = data.parse()?;
+                    return Ok(Self::DeleteGroupFilm { id });
+                }
+                GROUP_DELETE_RESTRICTION_CALLBACK => {
+                    let restrict = data == "1";
+                    return Ok(Self::GroupDeleteRestriction { restrict });
+                }
+                GET_EPISODE_DETAILS_CALLBACK => {
+                    if let [id, season, episode] = data.split(':').collect::<Vec<_>>()[..] {
+                        let id = id.parse()?;
+                        let season = season.parse()?;
+                        let episode = episode.parse()?;
+                        return Ok(Self::GetEpisodeDetails {
+                            id,
+                            season,
+                            episode,
+                        });
+                    }
+                }
                 _ => {}
             }
         }
@@ -313,16 +630,21 @@ impl FromStr for MyCallback {
     }
 }
 #[tracing::instrument(name = "telegram bot", skip_all)]
-pub async fn run(storage: Storage, tmdb_client: Tmdb) -> Result<()> {
+pub async fn run(
+    storage: Storage,
+    dialogue_storage: Arc<DialogueStorage>,
+    tmdb_client: Tmdb,
+) -> Result<()> {
     let bot = Bot::from_env();
     tracing::info!("🚀 Starting 🤖  bot");
     bot.delete_webhook().drop_pending_updates(true).await?;
     bot.set_my_commands(Command::bot_commands()).await?;
     Dispatcher::builder(bot, router::main_router())
         .dependencies(dptree::deps![
-            InMemStorage::<State>::new(),
+            dialogue_storage,
             storage,
-            tmdb_client
+            tmdb_client,
+            PendingImports::new()
         ])
         .default_handler(|upd| async move {
             tracing::warn!("Unhandled update: {upd:?}");
@@ -1,14 +1,20 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
+use mongodb::bson::DateTime;
 use teloxide::{
     prelude::*,
-    types::{InlineKeyboardMarkup, InputFile, KeyboardRemove, ParseMode},
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, KeyboardRemove, ParseMode},
 };
 use tracing::instrument;
 
+use super::{add_film_to_group_list, send_card, send_next_pending_import, send_user_list_page};
+
 use crate::app::{
-    storage::Storage,
-    telegram::{MyCallback, MyDialogue, State, TextCommand},
-    tmdb::Tmdb,
+    import::{self, PendingImports},
+    models::{EpisodeNumber, TitleSnapshot},
+    releases,
+    storage::{AddOutcome, Storage},
+    telegram::{MyCallback, MyDialogue, State, TextCommand, is_group_chat},
+    tmdb::{ImageKind, Tmdb, escape_html},
 };
 const BACK_STICKER: &str =
     "CAACAgIAAxkBAAEPRV9osZ-0Phhpaqp1o508hNxXSdFLbgAC7BUAAukAARhItE_tlWzTa_g2BA";
@@ -26,7 +32,7 @@ pub async fn cancel_callback_handler(
     if let Some(msg) = q.regular_message() {
         let sticker = InputFile::file_id(BACK_STICKER.into());
         bot.send_sticker(msg.chat.id, sticker)
-            .reply_markup(TextCommand::keyboard())
+            .reply_markup(TextCommand::keyboard_for(&msg.chat))
             .await?;
     }
     Ok(())
@@ -37,57 +43,78 @@ pub async fn get_film_details_callback_handler(
     dialogue: MyDialogue,
     q: CallbackQuery,
     cb: MyCallback,
+    storage: Storage,
     tmdb_client: Tmdb,
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::GetFilmsDetails { id } = cb {
-            let film = tmdb_client.get_films_details(id).await?;
-            let file = tmdb_client.get_image(&film.poster_path).await?;
-            let text = film.to_string();
-            let mu = InlineKeyboardMarkup::default()
-                .append_row(vec![
-                    MyCallback::AddFilmToWatchList { id: film.id }.into(),
-                    MyCallback::GetFilmsCredits { id: film.id }.into(),
-                ])
-                .append_row(vec![MyCallback::Cancel.into()]);
-            bot.send_photo(msg.chat.id, file)
-                .caption(text)
-                .parse_mode(ParseMode::Html)
-                .reply_markup(mu)
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::GetFilmsDetails { id } = cb
+    {
+        let film = tmdb_client.get_films_details(id).await?;
+        let text = film.to_string();
+        let mu = InlineKeyboardMarkup::default()
+            .append_row(vec![
+                MyCallback::AddFilmToWatchList { id: film.id }.into(),
+                MyCallback::GetFilmsCredits { id: film.id }.into(),
+            ])
+            .append_row(vec![MyCallback::Cancel.into()]);
+        send_card(
+            &bot,
+            msg.chat.id,
+            &storage,
+            &tmdb_client,
+            detail_image(&film.backdrop_path, &film.poster_path),
+            text,
+            mu,
+        )
+        .await?;
     }
     Ok(())
 }
+/// The wide backdrop suits a detail card best, the poster stands in for titles without one.
+fn detail_image<'a>(backdrop_path: &'a str, poster_path: &'a str) -> Option<(&'a str, ImageKind)> {
+    if !backdrop_path.is_empty() {
+        Some((backdrop_path, ImageKind::Backdrop))
+    } else if !poster_path.is_empty() {
+        Some((poster_path, ImageKind::DetailPoster))
+    } else {
+        None
+    }
+}
 #[instrument(name = "get serial details callback", skip_all)]
 pub async fn get_serial_details_callback_handler(
     bot: Bot,
     dialogue: MyDialogue,
     q: CallbackQuery,
     cb: MyCallback,
+    storage: Storage,
     tmdb_client: Tmdb,
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::GetSerialDetails { id } = cb {
-            let tv_show = tmdb_client.get_tv_show_details(id).await?;
-            let file = tmdb_client.get_image(&tv_show.poster_path).await?;
-            let text = tv_show.to_string();
-            let mu = InlineKeyboardMarkup::default()
-                .append_row(vec![
-                    MyCallback::AddSerialToWatchList { id: tv_show.id }.into(),
-                    MyCallback::GetSerialCredits { id: tv_show.id }.into(),
-                ])
-                .append_row(vec![MyCallback::Cancel.into()]);
-            bot.send_photo(msg.chat.id, file)
-                .caption(text)
-                .parse_mode(ParseMode::Html)
-                .reply_markup(mu)
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::GetSerialDetails { id } = cb
+    {
+        let tv_show = tmdb_client.get_tv_show_details(id).await?;
+        let text = tv_show.to_string();
+        let mu = InlineKeyboardMarkup::default()
+            .append_row(vec![
+                MyCallback::AddSerialToWatchList { id: tv_show.id }.into(),
+                MyCallback::GetSerialCredits { id: tv_show.id }.into(),
+            ])
+            .append_row(vec![MyCallback::ShowSeasons { id: tv_show.id }.into()])
+            .append_row(vec![MyCallback::Cancel.into()]);
+        send_card(
+            &bot,
+            msg.chat.id,
+            &storage,
+            &tmdb_client,
+            detail_image(&tv_show.backdrop_path, &tv_show.poster_path),
+            text,
+            mu,
+        )
+        .await?;
     }
     Ok(())
 }
@@ -101,21 +128,21 @@ pub async fn get_film_credits_callback_handler(
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::GetFilmsCredits { id } = cb {
-            let credits = tmdb_client.get_films_credits(id).await?;
-            let text = credits.to_string();
-            let mu = InlineKeyboardMarkup::default()
-                .append_row(vec![
-                    MyCallback::AddFilmToWatchList { id }.into(),
-                    MyCallback::GetFilmsDetails { id }.into(),
-                ])
-                .append_row(vec![MyCallback::Cancel.into()]);
-            bot.send_message(msg.chat.id, text)
-                .parse_mode(ParseMode::Html)
-                .reply_markup(mu)
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::GetFilmsCredits { id } = cb
+    {
+        let credits = tmdb_client.get_films_credits(id).await?;
+        let text = credits.to_string();
+        let mu = InlineKeyboardMarkup::default()
+            .append_row(vec![
+                MyCallback::AddFilmToWatchList { id }.into(),
+                MyCallback::GetFilmsDetails { id }.into(),
+            ])
+            .append_row(vec![MyCallback::Cancel.into()]);
+        bot.send_message(msg.chat.id, text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(mu)
+            .await?;
     }
     Ok(())
 }
@@ -129,21 +156,21 @@ pub async fn get_serial_credits_callback_handler(
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::GetSerialCredits { id } = cb {
-            let credits = tmdb_client.get_tv_show_credits(id).await?;
-            let text = credits.to_string();
-            let mu = InlineKeyboardMarkup::default()
-                .append_row(vec![
-                    MyCallback::AddSerialToWatchList { id }.into(),
-                    MyCallback::GetSerialDetails { id }.into(),
-                ])
-                .append_row(vec![MyCallback::Cancel.into()]);
-            bot.send_message(msg.chat.id, text)
-                .parse_mode(ParseMode::Html)
-                .reply_markup(mu)
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::GetSerialCredits { id } = cb
+    {
+        let credits = tmdb_client.get_tv_show_credits(id).await?;
+        let text = credits.to_string();
+        let mu = InlineKeyboardMarkup::default()
+            .append_row(vec![
+                MyCallback::AddSerialToWatchList { id }.into(),
+                MyCallback::GetSerialDetails { id }.into(),
+            ])
+            .append_row(vec![MyCallback::Cancel.into()]);
+        bot.send_message(msg.chat.id, text)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(mu)
+            .await?;
     }
     Ok(())
 }
@@ -159,17 +186,55 @@ pub async fn add_film_to_watch_list_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::AddFilmToWatchList { id } = cb {
-            storage.add_film_to_watch_list(user_id, id).await?;
-            let film = tmdb_client.get_films_details(id).await?;
-            bot.send_message(
-                msg.chat.id,
-                format!("Фильм:\n{film}\n Добавлен в список для просмотра"),
-            )
-            .reply_markup(TextCommand::keyboard())
-            .parse_mode(ParseMode::Html)
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::AddFilmToWatchList { id } = cb
+    {
+        if is_group_chat(&msg.chat) {
+            return add_film_to_group_list(&bot, &msg.chat, &q.from, id, &storage, &tmdb_client)
+                .await;
+        }
+        let film = tmdb_client.get_films_details(id).await?;
+        let snapshot = TitleSnapshot::from(&film);
+        let upcoming = snapshot.is_upcoming(&releases::today());
+        let region = releases::region(q.from.language_code.as_deref());
+        let outcome = storage
+            .add_film_to_watch_list(user_id, id, snapshot, region)
             .await?;
+        match outcome {
+            AddOutcome::Added => {
+                let mut text = format!("Фильм:\n{film}\n Добавлен в список для просмотра");
+                if upcoming {
+                    text.push_str("\n🔔 Сообщу, когда фильм выйдет в кино или в цифре");
+                }
+                bot.send_message(msg.chat.id, text)
+                    .reply_markup(TextCommand::keyboard())
+                    .parse_mode(ParseMode::Html)
+                    .await?;
+            }
+            AddOutcome::AlreadyInWatchList => {
+                bot.send_message(
+                    msg.chat.id,
+                    format!("Фильм:\n{film}\n Уже есть в вашем списке для просмотра"),
+                )
+                .reply_markup(TextCommand::keyboard())
+                .parse_mode(ParseMode::Html)
+                .await?;
+            }
+            AddOutcome::AlreadyWatched => {
+                let mu = InlineKeyboardMarkup::default().append_row(vec![
+                    MyCallback::MarkFilmUnWatched { id }.into(),
+                    MyCallback::RateFilm { id }.into(),
+                ]);
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Фильм:\n{film}\n Уже отмечен просмотренным. Чтобы вернуть его в список для просмотра, отметьте его непросмотренным"
+                    ),
+                )
+                .reply_markup(mu)
+                .parse_mode(ParseMode::Html)
+                .await?;
+            }
         }
     }
     Ok(())
@@ -186,17 +251,47 @@ pub async fn add_serial_to_watch_list_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::AddSerialToWatchList { id } = cb {
-            storage.add_serial_to_watch_list(user_id, id).await?;
-            let tv_show = tmdb_client.get_tv_show_details(id).await?;
-            bot.send_message(
-                msg.chat.id,
-                format!("Сериал:\n{tv_show}\n Добавлен в список для просмотра"),
-            )
-            .reply_markup(TextCommand::keyboard())
-            .parse_mode(ParseMode::Html)
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::AddSerialToWatchList { id } = cb
+    {
+        let tv_show = tmdb_client.get_tv_show_details(id).await?;
+        let outcome = storage
+            .add_serial_to_watch_list(user_id, id, TitleSnapshot::from(&tv_show))
             .await?;
+        match outcome {
+            AddOutcome::Added => {
+                bot.send_message(
+                    msg.chat.id,
+                    format!("Сериал:\n{tv_show}\n Добавлен в список для просмотра"),
+                )
+                .reply_markup(TextCommand::keyboard())
+                .parse_mode(ParseMode::Html)
+                .await?;
+            }
+            AddOutcome::AlreadyInWatchList => {
+                bot.send_message(
+                    msg.chat.id,
+                    format!("Сериал:\n{tv_show}\n Уже есть в вашем списке для просмотра"),
+                )
+                .reply_markup(TextCommand::keyboard())
+                .parse_mode(ParseMode::Html)
+                .await?;
+            }
+            AddOutcome::AlreadyWatched => {
+                let mu = InlineKeyboardMarkup::default().append_row(vec![
+                    MyCallback::MarkSerialUnWatched { id }.into(),
+                    MyCallback::RateSerial { id }.into(),
+                ]);
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Сериал:\n{tv_show}\n Уже отмечен просмотренным. Чтобы вернуть его в список для просмотра, отметьте его непросмотренным"
+                    ),
+                )
+                .reply_markup(mu)
+                .parse_mode(ParseMode::Html)
+                .await?;
+            }
         }
     }
     Ok(())
@@ -212,16 +307,16 @@ pub async fn mark_film_watched_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::MarkFilmWatched { id } = cb {
-            storage.watch_film(user_id, id).await?;
-            bot.send_message(msg.chat.id, "Оцените фильм по 10-ти бальной шкале")
-                .reply_markup(KeyboardRemove::new())
-                .await?;
-            dialogue
-                .update(State::FilmRateReceived { film_id: id })
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::MarkFilmWatched { id } = cb
+    {
+        storage.watch_film(user_id, id).await?;
+        bot.send_message(msg.chat.id, "Оцените фильм по 10-ти бальной шкале")
+            .reply_markup(KeyboardRemove::new())
+            .await?;
+        dialogue
+            .update(State::FilmRateReceived { film_id: id })
+            .await?;
     }
     Ok(())
 }
@@ -236,16 +331,16 @@ pub async fn mark_serial_watched_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::MarkSerialWatched { id } = cb {
-            storage.watch_serial(user_id, id).await?;
-            bot.send_message(msg.chat.id, "Оцените сериал по 10-ти бальной шкале")
-                .reply_markup(KeyboardRemove::new())
-                .await?;
-            dialogue
-                .update(State::SerialRateReceived { serial_id: id })
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::MarkSerialWatched { id } = cb
+    {
+        storage.watch_serial(user_id, id).await?;
+        bot.send_message(msg.chat.id, "Оцените сериал по 10-ти бальной шкале")
+            .reply_markup(KeyboardRemove::new())
+            .await?;
+        dialogue
+            .update(State::SerialRateReceived { serial_id: id })
+            .await?;
     }
     Ok(())
 }
@@ -260,12 +355,12 @@ pub async fn mark_film_unwatched_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::MarkFilmUnWatched { id } = cb {
-            storage.unwatch_film(user_id, id).await?;
-            bot.send_message(msg.chat.id, "Фильм отмечен непросмотренным")
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::MarkFilmUnWatched { id } = cb
+    {
+        storage.unwatch_film(user_id, id).await?;
+        bot.send_message(msg.chat.id, "Фильм отмечен непросмотренным")
+            .await?;
     }
     Ok(())
 }
@@ -280,12 +375,12 @@ pub async fn mark_serial_unwatched_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::MarkSerialUnWatched { id } = cb {
-            storage.unwatch_serial(user_id, id).await?;
-            bot.send_message(msg.chat.id, "Сериал отмечен непросмотренным")
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::MarkSerialUnWatched { id } = cb
+    {
+        storage.unwatch_serial(user_id, id).await?;
+        bot.send_message(msg.chat.id, "Сериал отмечен непросмотренным")
+            .await?;
     }
     Ok(())
 }
@@ -297,15 +392,15 @@ pub async fn rate_film_callback_handler(
     cb: MyCallback,
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::RateFilm { id } = cb {
-            bot.send_message(msg.chat.id, "Оцените фильм по 10-ти бальной шкале")
-                .reply_markup(KeyboardRemove::new())
-                .await?;
-            dialogue
-                .update(State::FilmRateReceived { film_id: id })
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::RateFilm { id } = cb
+    {
+        bot.send_message(msg.chat.id, "Оцените фильм по 10-ти бальной шкале")
+            .reply_markup(KeyboardRemove::new())
+            .await?;
+        dialogue
+            .update(State::FilmRateReceived { film_id: id })
+            .await?;
     }
     Ok(())
 }
@@ -317,15 +412,70 @@ pub async fn rate_serial_callback_handler(
     cb: MyCallback,
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::RateSerial { id } = cb {
-            bot.send_message(msg.chat.id, "Оцените сериал по 10-ти бальной шкале")
-                .reply_markup(KeyboardRemove::new())
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::RateSerial { id } = cb
+    {
+        bot.send_message(msg.chat.id, "Оцените сериал по 10-ти бальной шкале")
+            .reply_markup(KeyboardRemove::new())
+            .await?;
+        dialogue
+            .update(State::SerialRateReceived { serial_id: id })
+            .await?;
+    }
+    Ok(())
+}
+#[instrument(name = "rate serial season", skip_all)]
+pub async fn rate_serial_season_callback_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    cb: MyCallback,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    let Some(msg) = q.regular_message() else {
+        return Ok(());
+    };
+    match cb {
+        MyCallback::RateSerialSeason { id } => {
+            let tv_show = tmdb_client.get_tv_show_details(id).await?;
+            let buttons: Vec<InlineKeyboardButton> = tv_show
+                .seasons
+                .iter()
+                .filter(|s| s.season_number > 0 && s.episode_count > 0)
+                .map(|s| {
+                    let cb = MyCallback::ChooseRatingSeason {
+                        id,
+                        season: s.season_number,
+                    };
+                    InlineKeyboardButton::callback(format!("Сезон {}", s.season_number), cb.data())
+                })
+                .collect();
+            if buttons.is_empty() {
+                bot.send_message(msg.chat.id, "У сериала пока нет сезонов")
+                    .await?;
+                return Ok(());
+            }
+            let mu = InlineKeyboardMarkup::new(buttons.chunks(PICKER_ROW_SIZE).map(<[_]>::to_vec));
+            bot.send_message(msg.chat.id, "Какой сезон вы хотите оценить?")
+                .reply_markup(mu)
                 .await?;
+        }
+        MyCallback::ChooseRatingSeason { id, season } => {
+            bot.send_message(
+                msg.chat.id,
+                format!("Оцените {season}-й сезон по 10-ти бальной шкале"),
+            )
+            .reply_markup(KeyboardRemove::new())
+            .await?;
             dialogue
-                .update(State::SerialRateReceived { serial_id: id })
+                .update(State::SerialSeasonRateReceived {
+                    serial_id: id,
+                    season,
+                })
                 .await?;
         }
+        _ => {}
     }
     Ok(())
 }
@@ -340,12 +490,12 @@ pub async fn delete_film_from_list_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::DeleteFilm { id } = cb {
-            storage.delete_film_from_watch_list(user_id, id).await?;
-            bot.send_message(msg.chat.id, "Фильм удален из списка")
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::DeleteFilm { id } = cb
+    {
+        storage.delete_film_from_watch_list(user_id, id).await?;
+        bot.send_message(msg.chat.id, "Фильм удален из списка")
+            .await?;
     }
     Ok(())
 }
@@ -360,12 +510,12 @@ pub async fn delete_serial_from_list_callback_handler(
     bot.answer_callback_query(q.id.clone()).await?;
     dialogue.exit().await?;
     let user_id = q.from.id.0;
-    if let Some(msg) = q.regular_message() {
-        if let MyCallback::DeleteSerial { id } = cb {
-            storage.delete_serial_from_watch_list(user_id, id).await?;
-            bot.send_message(msg.chat.id, "Сериал удален из списка")
-                .await?;
-        }
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::DeleteSerial { id } = cb
+    {
+        storage.delete_serial_from_watch_list(user_id, id).await?;
+        bot.send_message(msg.chat.id, "Сериал удален из списка")
+            .await?;
     }
     Ok(())
 }
@@ -375,6 +525,7 @@ pub async fn search_film_pagination_callback_handler(
     dialogue: MyDialogue,
     q: CallbackQuery,
     cb: MyCallback,
+    storage: Storage,
     tmdb_client: Tmdb,
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
@@ -402,10 +553,6 @@ pub async fn search_film_pagination_callback_handler(
                         let text = film.to_string();
                         match film.poster_path.as_ref() {
                             Some(image_url) => {
-                                let file = tmdb_client
-                                    .get_image(&image_url)
-                                    .await
-                                    .context("Getting image")?;
                                 let mut mu = InlineKeyboardMarkup::default().append_row(vec![
                                     MyCallback::GetFilmsDetails { id: film.id }.into(),
                                     MyCallback::AddFilmToWatchList { id: film.id }.into(),
@@ -434,11 +581,16 @@ pub async fn search_film_pagination_callback_handler(
                                         mu = mu.append_row(row);
                                     }
                                 }
-                                bot.send_photo(msg.chat.id, file)
-                                    .caption(&text)
-                                    .parse_mode(ParseMode::Html)
-                                    .reply_markup(mu)
-                                    .await?;
+                                send_card(
+                                    &bot,
+                                    msg.chat.id,
+                                    &storage,
+                                    &tmdb_client,
+                                    Some((image_url, ImageKind::ListPoster)),
+                                    text.clone(),
+                                    mu,
+                                )
+                                .await?;
                             }
                             None => {
                                 let mut mu = InlineKeyboardMarkup::default().append_row(vec![
@@ -492,6 +644,7 @@ pub async fn search_serial_pagination_callback_handler(
     dialogue: MyDialogue,
     q: CallbackQuery,
     cb: MyCallback,
+    storage: Storage,
     tmdb_client: Tmdb,
 ) -> Result<()> {
     bot.answer_callback_query(q.id.clone()).await?;
@@ -521,10 +674,6 @@ pub async fn search_serial_pagination_callback_handler(
                         let text = serial.to_string();
                         match serial.poster_path.as_ref() {
                             Some(image_url) => {
-                                let file = tmdb_client
-                                    .get_image(&image_url)
-                                    .await
-                                    .context("Getting image")?;
                                 let mut mu = InlineKeyboardMarkup::default().append_row(vec![
                                     MyCallback::GetSerialDetails { id: serial.id }.into(),
                                     MyCallback::AddSerialToWatchList { id: serial.id }.into(),
@@ -553,11 +702,16 @@ pub async fn search_serial_pagination_callback_handler(
                                         mu = mu.append_row(row);
                                     }
                                 }
-                                bot.send_photo(msg.chat.id, file)
-                                    .caption(&text)
-                                    .parse_mode(ParseMode::Html)
-                                    .reply_markup(mu)
-                                    .await?;
+                                send_card(
+                                    &bot,
+                                    msg.chat.id,
+                                    &storage,
+                                    &tmdb_client,
+                                    Some((image_url, ImageKind::ListPoster)),
+                                    text.clone(),
+                                    mu,
+                                )
+                                .await?;
                             }
                             None => {
                                 let mut mu = InlineKeyboardMarkup::default().append_row(vec![
@@ -605,3 +759,390 @@ pub async fn search_serial_pagination_callback_handler(
     }
     Ok(())
 }
+#[instrument(name = "user list pagination", skip_all)]
+pub async fn user_list_pagination_callback_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    dialogue.exit().await?;
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::UserListNextPage { list, page }
+        | MyCallback::UserListPreviousPage { list, page } = cb
+    {
+        send_user_list_page(
+            &bot,
+            msg.chat.id,
+            q.from.id.0,
+            list,
+            page,
+            &storage,
+            &tmdb_client,
+        )
+        .await?;
+    }
+    Ok(())
+}
+#[instrument(name = "resolve import callback", skip_all)]
+pub async fn resolve_import_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+    tmdb_client: Tmdb,
+    pending: PendingImports,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    let Some(msg) = q.regular_message() else {
+        return Ok(());
+    };
+    let user_id = q.from.id.0;
+    let (key, tmdb_id) = match cb {
+        MyCallback::ResolveImport { key, id } => (key, Some(id)),
+        MyCallback::SkipImport { key } => (key, None),
+        _ => return Ok(()),
+    };
+    let Some(title) = pending.take(user_id, key).await else {
+        bot.send_message(msg.chat.id, "Эта запись уже обработана")
+            .await?;
+        return Ok(());
+    };
+    let label = title.label();
+    let region = releases::region(q.from.language_code.as_deref());
+    let text = match tmdb_id {
+        Some(tmdb_id) => {
+            match import::save(&storage, &tmdb_client, user_id, &region, title, tmdb_id).await? {
+                AddOutcome::Added => format!("{label}: добавлен"),
+                _ => format!("{label}: уже есть в ваших списках"),
+            }
+        }
+        None => format!("{label}: пропущен"),
+    };
+    bot.send_message(msg.chat.id, text).await?;
+    send_next_pending_import(&bot, msg.chat.id, &pending, user_id).await
+}
+/// Buttons per row in the season and episode pickers.
+const PICKER_ROW_SIZE: usize = 6;
+
+#[instrument(name = "serial progress callback", skip_all)]
+pub async fn serial_progress_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    let Some(msg) = q.regular_message() else {
+        return Ok(());
+    };
+    match cb {
+        MyCallback::SerialProgress { id } => {
+            let details = tmdb_client.get_tv_show_details(id).await?;
+            let snapshot = TitleSnapshot::from(&details);
+            let last_season = snapshot.last_aired.map_or(0, |last| last.season);
+            let buttons: Vec<InlineKeyboardButton> = snapshot
+                .seasons
+                .iter()
+                .filter(|s| s.season_number <= last_season && s.episode_count > 0)
+                .map(|s| {
+                    let cb = MyCallback::ChooseProgressSeason {
+                        id,
+                        season: s.season_number,
+                    };
+                    InlineKeyboardButton::callback(format!("Сезон {}", s.season_number), cb.data())
+                })
+                .collect();
+            storage.set_serial_snapshot(id, snapshot).await?;
+            if buttons.is_empty() {
+                bot.send_message(msg.chat.id, "У сериала ещё не вышло ни одной серии")
+                    .await?;
+                return Ok(());
+            }
+            let mu = InlineKeyboardMarkup::new(buttons.chunks(PICKER_ROW_SIZE).map(<[_]>::to_vec));
+            bot.send_message(msg.chat.id, "Какой сезон вы сейчас смотрите?")
+                .reply_markup(mu)
+                .await?;
+        }
+        MyCallback::ChooseProgressSeason { id, season } => {
+            let details = tmdb_client.get_season_details(id, season).await?;
+            let today = DateTime::now().to_chrono().format("%Y-%m-%d").to_string();
+            let buttons: Vec<InlineKeyboardButton> = details
+                .episodes
+                .iter()
+                .filter(|e| e.is_aired(&today))
+                .map(|e| {
+                    let cb = MyCallback::SetSerialProgress {
+                        id,
+                        season,
+                        episode: e.episode_number,
+                    };
+                    InlineKeyboardButton::callback(e.episode_number.to_string(), cb.data())
+                })
+                .collect();
+            if buttons.is_empty() {
+                bot.send_message(msg.chat.id, "В этом сезоне ещё не вышло ни одной серии")
+                    .await?;
+                return Ok(());
+            }
+            let mu = InlineKeyboardMarkup::new(buttons.chunks(PICKER_ROW_SIZE).map(<[_]>::to_vec));
+            bot.send_message(
+                msg.chat.id,
+                format!("Сезон {season}: до какой серии вы досмотрели?"),
+            )
+            .reply_markup(mu)
+            .await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+#[instrument(name = "set serial progress callback", skip_all)]
+pub async fn set_serial_progress_callback_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    let user_id = q.from.id.0;
+    let Some(msg) = q.regular_message() else {
+        return Ok(());
+    };
+    let MyCallback::SetSerialProgress {
+        id,
+        season,
+        episode,
+    } = cb
+    else {
+        return Ok(());
+    };
+    let Some(mut serial) = storage.get_serial(user_id, id).await? else {
+        bot.send_message(msg.chat.id, "Сначала добавьте сериал в список")
+            .await?;
+        return Ok(());
+    };
+    let progress = EpisodeNumber { season, episode };
+    storage.set_serial_progress(user_id, id, progress).await?;
+    serial.progress = Some(progress);
+    let details = tmdb_client.get_tv_show_details(id).await?;
+    let snapshot = TitleSnapshot::from(&details);
+    let finished = snapshot.last_aired.is_some_and(|last| progress >= last);
+    let mut text = serial
+        .progress_line(&snapshot)
+        .unwrap_or_else(|| format!("📺 {progress}"));
+    storage.set_serial_snapshot(id, snapshot).await?;
+    if finished && !serial.viewing.watched {
+        storage.watch_serial(user_id, id).await?;
+        text.push_str("\nВсе вышедшие серии просмотрены, сериал отмечен просмотренным");
+        text.push_str("\nОцените сериал по 10-ти бальной шкале");
+        bot.send_message(msg.chat.id, text)
+            .reply_markup(KeyboardRemove::new())
+            .await?;
+        dialogue
+            .update(State::SerialRateReceived { serial_id: id })
+            .await?;
+        return Ok(());
+    }
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+/// Episodes listed per page of a season.
+const EPISODES_PAGE_SIZE: usize = 8;
+
+#[instrument(name = "show seasons callback", skip_all)]
+pub async fn show_seasons_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::ShowSeasons { id } = cb
+    {
+        let tv_show = tmdb_client.get_tv_show_details(id).await?;
+        let seasons: Vec<_> = tv_show
+            .seasons
+            .iter()
+            .filter(|s| s.episode_count > 0)
+            .collect();
+        if seasons.is_empty() {
+            bot.send_message(msg.chat.id, "У сериала пока нет сезонов")
+                .await?;
+            return Ok(());
+        }
+        let buttons: Vec<InlineKeyboardButton> = seasons
+            .iter()
+            .map(|s| {
+                let cb = MyCallback::SeasonEpisodesNextPage {
+                    id,
+                    season: s.season_number,
+                    page: 1,
+                };
+                InlineKeyboardButton::callback(s.name.clone(), cb.data())
+            })
+            .collect();
+        let mut mu = InlineKeyboardMarkup::new(buttons.chunks(3).map(<[_]>::to_vec));
+        mu = mu.append_row(vec![MyCallback::Cancel.into()]);
+        let text: Vec<String> = seasons.iter().map(|s| s.to_string()).collect();
+        bot.send_message(msg.chat.id, text.join("\n\n"))
+            .parse_mode(ParseMode::Html)
+            .reply_markup(mu)
+            .await?;
+    }
+    Ok(())
+}
+#[instrument(name = "season episodes callback", skip_all)]
+pub async fn season_episodes_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    let Some(msg) = q.regular_message() else {
+        return Ok(());
+    };
+    let (id, season, page) = match cb {
+        MyCallback::SeasonEpisodesNextPage { id, season, page }
+        | MyCallback::SeasonEpisodesPreviousPage { id, season, page } => (id, season, page),
+        _ => return Ok(()),
+    };
+    let details = tmdb_client.get_season_details(id, season).await?;
+    let pages = details.episodes.len().div_ceil(EPISODES_PAGE_SIZE).max(1);
+    let page = (page as usize).clamp(1, pages);
+    let episodes = details
+        .episodes
+        .iter()
+        .skip((page - 1) * EPISODES_PAGE_SIZE)
+        .take(EPISODES_PAGE_SIZE);
+    let mut text = details.to_string();
+    let mut mu = InlineKeyboardMarkup::default();
+    for episode in episodes {
+        text.push_str(&format!(
+            "\n\n<b>{n}. {name}</b>",
+            n = episode.episode_number,
+            name = escape_html(&episode.name)
+        ));
+        if let Some(air_date) = episode.air_date.as_deref().filter(|d| !d.is_empty()) {
+            text.push_str(&format!("\n📅 {air_date}"));
+        }
+        if let Some(runtime) = episode.runtime.filter(|r| *r > 0) {
+            text.push_str(&format!(" ⏱️ {runtime} мин"));
+        }
+        let cb = MyCallback::GetEpisodeDetails {
+            id,
+            season,
+            episode: episode.episode_number,
+        };
+        let label = format!(
+            "{n}. {name}",
+            n = episode.episode_number,
+            name = episode.name
+        );
+        mu = mu.append_row(vec![InlineKeyboardButton::callback(label, cb.data())]);
+    }
+    let page = page as u32;
+    let mut navigation = Vec::new();
+    if page > 1 {
+        navigation.push(
+            MyCallback::SeasonEpisodesPreviousPage {
+                id,
+                season,
+                page: page - 1,
+            }
+            .into(),
+        );
+    }
+    if (page as usize) < pages {
+        navigation.push(
+            MyCallback::SeasonEpisodesNextPage {
+                id,
+                season,
+                page: page + 1,
+            }
+            .into(),
+        );
+    }
+    if !navigation.is_empty() {
+        mu = mu.append_row(navigation);
+        text.push_str(&format!("\n\nСтраница {page} из {pages}"));
+    }
+    mu = mu.append_row(vec![
+        MyCallback::ShowSeasons { id }.into(),
+        MyCallback::Cancel.into(),
+    ]);
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(mu)
+        .await?;
+    Ok(())
+}
+#[instrument(name = "get episode details callback", skip_all)]
+pub async fn get_episode_details_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::GetEpisodeDetails {
+            id,
+            season,
+            episode,
+        } = cb
+    {
+        let details = tmdb_client.get_episode_details(id, season, episode).await?;
+        let page = (episode.max(1) - 1) as u32 / EPISODES_PAGE_SIZE as u32 + 1;
+        let back = MyCallback::SeasonEpisodesNextPage { id, season, page };
+        let mu = InlineKeyboardMarkup::default()
+            .append_row(vec![InlineKeyboardButton::callback(
+                "🔙 К списку серий",
+                back.data(),
+            )])
+            .append_row(vec![MyCallback::Cancel.into()]);
+        let still_path = details.still_path.as_deref().filter(|p| !p.is_empty());
+        send_card(
+            &bot,
+            msg.chat.id,
+            &storage,
+            &tmdb_client,
+            still_path.map(|p| (p, ImageKind::Still)),
+            details.to_string(),
+            mu,
+        )
+        .await?;
+    }
+    Ok(())
+}
+#[instrument(name = "serial reminders callback", skip_all)]
+pub async fn serial_reminders_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    let user_id = q.from.id.0;
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::SerialReminders { id, enabled } = cb
+    {
+        storage.set_serial_reminders(user_id, id, enabled).await?;
+        let text = if enabled {
+            "🔔 Напомню, когда выйдет новая серия"
+        } else {
+            "🔕 Напоминания о новых сериях отключены"
+        };
+        bot.send_message(msg.chat.id, text).await?;
+    }
+    Ok(())
+}
@@ -1,9 +1,19 @@
 use anyhow::Result;
 use teloxide::{
-    prelude::*, sugar::bot::BotMessagesExt, types::InputFile, utils::command::BotCommands,
+    prelude::*,
+    sugar::bot::BotMessagesExt,
+    types::{InputFile, ParseMode},
+    utils::command::BotCommands,
 };
+use tracing::instrument;
 
-use crate::app::telegram::{Command, MyDialogue, State, TextCommand};
+use crate::app::{
+    export::{self, ExportRow},
+    snapshots::{film_snapshot, serial_snapshot},
+    storage::Storage,
+    telegram::{Command, MyDialogue, State, TextCommand},
+    tmdb::Tmdb,
+};
 
 const START_STICKER: &str =
     "CAACAgIAAxkBAAEPPgForZX41qsn-O4_n0a-DwyMLC1D5wAC2BEAAo-jyEu9EaUKcvRilDYE";
@@ -13,19 +23,85 @@ pub async fn start_command_handler(bot: Bot, dialogue: MyDialogue, msg: Message)
     dialogue.update(State::Start).await?;
     let sticker = InputFile::file_id(START_STICKER.into());
     bot.send_sticker(msg.chat.id, sticker)
-        .reply_markup(TextCommand::keyboard())
+        .reply_markup(TextCommand::keyboard_for(&msg.chat))
         .await?;
     Ok(())
 }
 pub async fn help_command_handler(bot: Bot, msg: Message) -> Result<()> {
     bot.send_message(msg.chat.id, Command::descriptions().to_string())
-        .reply_markup(TextCommand::keyboard())
+        .reply_markup(TextCommand::keyboard_for(&msg.chat))
         .await?;
     Ok(())
 }
 pub async fn cancel_command_handler(bot: Bot, dialogue: MyDialogue, msg: Message) -> Result<()> {
     dialogue.exit().await?;
     bot.send_message(msg.chat.id, "CANCELED")
+        .reply_markup(TextCommand::keyboard_for(&msg.chat))
+        .await?;
+    Ok(())
+}
+#[instrument(name = "export command", skip_all)]
+pub async fn export_command_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    let Some(from) = msg.from else {
+        return Ok(());
+    };
+    let mut rows = Vec::new();
+    for movie in storage.get_users_movies(from.id.0).await? {
+        let snapshot = match film_snapshot(&storage, &tmdb_client, &movie).await {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                tracing::warn!("Exporting film {} without a snapshot: {e:#}", movie.film_id);
+                None
+            }
+        };
+        rows.push(ExportRow::film(&movie, snapshot.as_ref()));
+    }
+    for serial in storage.get_users_serials(from.id.0).await? {
+        let snapshot = match serial_snapshot(&storage, &tmdb_client, &serial).await {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                tracing::warn!(
+                    "Exporting serial {} without a snapshot: {e:#}",
+                    serial.serial_id
+                );
+                None
+            }
+        };
+        rows.push(ExportRow::serial(&serial, snapshot.as_ref()));
+    }
+    if rows.is_empty() {
+        bot.send_message(msg.chat.id, "Ваши списки пусты, экспортировать нечего")
+            .reply_markup(TextCommand::keyboard())
+            .await?;
+        return Ok(());
+    }
+    let csv = InputFile::memory(export::to_csv(&rows)?).file_name("films-bot-export.csv");
+    let json = InputFile::memory(export::to_json(&rows)?).file_name("films-bot-export.json");
+    bot.send_document(msg.chat.id, csv).await?;
+    bot.send_document(msg.chat.id, json)
+        .caption(format!("Экспортировано записей: {}", rows.len()))
+        .reply_markup(TextCommand::keyboard())
+        .await?;
+    Ok(())
+}
+#[instrument(name = "stats command", skip_all)]
+pub async fn stats_command_handler(bot: Bot, msg: Message, storage: Storage) -> Result<()> {
+    let Some(from) = msg.from else {
+        return Ok(());
+    };
+    let stats = storage.user_stats(from.id.0).await?;
+    let text = if stats.is_empty() {
+        String::from("Пока нечего считать: отметьте просмотренными хотя бы один фильм или сериал")
+    } else {
+        stats.to_string()
+    };
+    bot.send_message(msg.chat.id, text)
+        .parse_mode(ParseMode::Html)
         .reply_markup(TextCommand::keyboard())
         .await?;
     Ok(())
@@ -0,0 +1,140 @@
+use anyhow::Result;
+use teloxide::{
+    net::Download,
+    prelude::*,
+    types::{Document, InlineKeyboardButton, InlineKeyboardMarkup},
+};
+use tracing::instrument;
+
+use crate::app::{
+    import::{self, ImportSummary, PendingImports, imdb, kinopoisk, letterboxd},
+    releases,
+    storage::Storage,
+    telegram::{MyCallback, TextCommand},
+    tmdb::Tmdb,
+};
+
+/// Letterboxd and IMDb export CSV, Kinopoisk exports HTML disguised as `.xls`.
+const IMPORT_EXTENSIONS: [&str; 4] = ["csv", "xls", "html", "htm"];
+/// How many unmatched titles are listed in the import summary.
+const MAX_UNMATCHED_SHOWN: usize = 20;
+
+#[instrument(name = "import document", skip_all)]
+pub async fn import_document_handler(
+    bot: Bot,
+    msg: Message,
+    document: Document,
+    storage: Storage,
+    tmdb_client: Tmdb,
+    pending: PendingImports,
+) -> Result<()> {
+    let Some(from) = msg.from else {
+        return Ok(());
+    };
+    let file_name = document.file_name.clone().unwrap_or_default();
+    let extension = file_name
+        .rsplit('.')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if !IMPORT_EXTENSIONS.contains(&extension.as_str()) {
+        return send_unsupported_file(&bot, msg.chat.id).await;
+    }
+    let file = bot.get_file(document.file.id).await?;
+    let mut data = Vec::new();
+    bot.download_file(&file.path, &mut data).await?;
+    let user_id = from.id.0;
+    let region = releases::region(from.language_code.as_deref());
+    let summary = if letterboxd::is_letterboxd_export(&data) {
+        let films = letterboxd::parse(&file_name, &data)?;
+        send_import_started(&bot, msg.chat.id, &file_name).await?;
+        import::import_titles(&storage, &tmdb_client, &pending, user_id, &region, films).await?
+    } else if imdb::is_imdb_export(&data) {
+        let ratings = imdb::parse(&data)?;
+        send_import_started(&bot, msg.chat.id, &file_name).await?;
+        imdb::import(&storage, &tmdb_client, user_id, &region, ratings).await?
+    } else if kinopoisk::is_kinopoisk_export(&data) {
+        let titles = kinopoisk::parse(&data)?;
+        send_import_started(&bot, msg.chat.id, &file_name).await?;
+        import::import_titles(&storage, &tmdb_client, &pending, user_id, &region, titles).await?
+    } else {
+        return send_unsupported_file(&bot, msg.chat.id).await;
+    };
+    send_import_summary(&bot, msg.chat.id, &pending, user_id, summary).await
+}
+
+async fn send_unsupported_file(bot: &Bot, chat_id: ChatId) -> Result<()> {
+    bot.send_message(
+        chat_id,
+        "Не удалось распознать файл. Поддерживаются экспорты Letterboxd \
+         (diary.csv, ratings.csv, watched.csv, watchlist.csv), IMDb (ratings.csv) \
+         и Кинопоиска (оценки и «Буду смотреть» в xls или csv)",
+    )
+    .reply_markup(TextCommand::keyboard())
+    .await?;
+    Ok(())
+}
+
+async fn send_import_started(bot: &Bot, chat_id: ChatId, file_name: &str) -> Result<()> {
+    bot.send_message(
+        chat_id,
+        format!("Импортирую {file_name}, это может занять несколько минут…"),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Reports the import and asks about the first ambiguous row, the rest follow one by one.
+async fn send_import_summary(
+    bot: &Bot,
+    chat_id: ChatId,
+    pending: &PendingImports,
+    user_id: u64,
+    summary: ImportSummary,
+) -> Result<()> {
+    let mut text = format!(
+        "Импорт завершён\nДобавлено: {matched}\nУже были в списках: {listed}\nТребуют уточнения: {ambiguous}\nНе найдено: {unmatched}",
+        matched = summary.matched,
+        listed = summary.already_listed,
+        ambiguous = summary.ambiguous,
+        unmatched = summary.unmatched.len(),
+    );
+    for title in summary.unmatched.iter().take(MAX_UNMATCHED_SHOWN) {
+        text.push_str(&format!("\n• {title}"));
+    }
+    if summary.unmatched.len() > MAX_UNMATCHED_SHOWN {
+        text.push_str("\n…");
+    }
+    bot.send_message(chat_id, text)
+        .reply_markup(TextCommand::keyboard())
+        .await?;
+    send_next_pending_import(bot, chat_id, pending, user_id).await
+}
+
+/// Asks the user to pick a film for the oldest ambiguous row, if any is left.
+pub async fn send_next_pending_import(
+    bot: &Bot,
+    chat_id: ChatId,
+    pending: &PendingImports,
+    user_id: u64,
+) -> Result<()> {
+    let Some((row, left)) = pending.next(user_id).await else {
+        return Ok(());
+    };
+    let mut mu = InlineKeyboardMarkup::default();
+    for (id, label) in row.candidates {
+        let cb = MyCallback::ResolveImport { key: row.key, id };
+        mu = mu.append_row(vec![InlineKeyboardButton::callback(label, cb.data())]);
+    }
+    mu = mu.append_row(vec![MyCallback::SkipImport { key: row.key }.into()]);
+    bot.send_message(
+        chat_id,
+        format!(
+            "Что имелось в виду: {label}?\nОсталось уточнить: {left}",
+            label = row.label
+        ),
+    )
+    .reply_markup(mu)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,470 @@
+use anyhow::Result;
+use teloxide::{
+    prelude::*,
+    types::{Chat, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, User},
+};
+use tracing::instrument;
+
+use super::send_card;
+
+use crate::app::{
+    models::{GroupFilm, GroupMember, GroupSettings, TitleSnapshot},
+    storage::{AddOutcome, Storage},
+    telegram::{MyCallback, MyDialogue, TextCommand, is_group_chat},
+    tmdb::{ImageKind, Tmdb, escape_html},
+};
+
+/// Number of films shown per page of a group's list.
+const GROUP_PAGE_SIZE: u64 = 5;
+const NOT_A_GROUP: &str = "Общий список доступен только в групповых чатах";
+
+fn member(user: &User) -> GroupMember {
+    GroupMember {
+        user_id: user.id.0,
+        name: user.full_name(),
+    }
+}
+
+/// Settings of the group's list, the first member to use it becomes its owner.
+async fn settings_or_init(
+    storage: &Storage,
+    chat_id: ChatId,
+    user: &User,
+) -> Result<GroupSettings> {
+    if let Some(settings) = storage.group_settings(chat_id.0).await? {
+        return Ok(settings);
+    }
+    let settings = GroupSettings::new(chat_id.0, member(user));
+    storage.set_group_settings(settings.clone()).await?;
+    Ok(settings)
+}
+
+fn group_film_markup(film: &GroupFilm) -> InlineKeyboardMarkup {
+    let id = film.film_id;
+    if film.watched {
+        InlineKeyboardMarkup::default()
+            .append_row(vec![
+                MyCallback::AttendGroupFilm { id }.into(),
+                MyCallback::RateGroupFilm { id }.into(),
+            ])
+            .append_row(vec![MyCallback::DeleteGroupFilm { id }.into()])
+    } else {
+        InlineKeyboardMarkup::default()
+            .append_row(vec![
+                MyCallback::MarkGroupFilmWatched { id }.into(),
+                MyCallback::DeleteGroupFilm { id }.into(),
+            ])
+            .append_row(vec![MyCallback::GetFilmsDetails { id }.into()])
+    }
+}
+
+async fn group_film_snapshot(tmdb_client: &Tmdb, film: &GroupFilm) -> Result<TitleSnapshot> {
+    match &film.snapshot {
+        Some(snapshot) => Ok(snapshot.clone()),
+        None => {
+            let details = tmdb_client.get_films_details(film.film_id).await?;
+            Ok(TitleSnapshot::from(&details))
+        }
+    }
+}
+
+async fn send_group_film(
+    bot: &Bot,
+    chat_id: ChatId,
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    film: &GroupFilm,
+) -> Result<()> {
+    let snapshot = group_film_snapshot(tmdb_client, film).await?;
+    let caption = format!("{snapshot}{}", film.summary());
+    send_card(
+        bot,
+        chat_id,
+        storage,
+        tmdb_client,
+        snapshot
+            .poster_path
+            .as_deref()
+            .map(|p| (p, ImageKind::ListPoster)),
+        caption,
+        group_film_markup(film),
+    )
+    .await
+}
+
+/// Sends one page of the group's list followed by the list switch and settings.
+pub async fn send_group_list_page(
+    bot: &Bot,
+    chat_id: ChatId,
+    settings: &GroupSettings,
+    watched: bool,
+    page: u32,
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+) -> Result<()> {
+    let skip = u64::from(page.saturating_sub(1)) * GROUP_PAGE_SIZE;
+    let result = storage
+        .get_group_films_page(chat_id.0, watched, skip, GROUP_PAGE_SIZE)
+        .await?;
+    for film in &result.items {
+        send_group_film(bot, chat_id, storage, tmdb_client, film).await?;
+    }
+    let pages = result.total.div_ceil(GROUP_PAGE_SIZE);
+    let mut row = Vec::new();
+    if page > 1 {
+        row.push(
+            MyCallback::GroupListPreviousPage {
+                watched,
+                page: page - 1,
+            }
+            .into(),
+        );
+    }
+    if u64::from(page) < pages {
+        row.push(
+            MyCallback::GroupListNextPage {
+                watched,
+                page: page + 1,
+            }
+            .into(),
+        );
+    }
+    let other_list = if watched {
+        "🤔 Ещё не смотрели"
+    } else {
+        "🍿 Уже посмотрели"
+    };
+    let mu = InlineKeyboardMarkup::default()
+        .append_row(row)
+        .append_row(vec![InlineKeyboardButton::callback(
+            other_list,
+            MyCallback::GroupListNextPage {
+                watched: !watched,
+                page: 1,
+            }
+            .data(),
+        )])
+        .append_row(vec![
+            MyCallback::GroupDeleteRestriction {
+                restrict: !settings.restrict_delete,
+            }
+            .into(),
+        ]);
+    let title = if watched {
+        "🍿 Группа уже посмотрела"
+    } else {
+        "👥 Общий список группы"
+    };
+    let text = if result.total == 0 {
+        format!(
+            "{title}: пока пусто\nНайдите фильм и нажмите «🤔 Буду смотреть», чтобы добавить его сюда"
+        )
+    } else if result.items.is_empty() {
+        format!("{title}: на этой странице ничего нет")
+    } else {
+        format!(
+            "{title}: страница {page} из {pages}, всего в списке: {}",
+            result.total
+        )
+    };
+    let owner = format!("\nВладелец списка: {}", escape_html(&settings.owner.name));
+    bot.send_message(chat_id, text + &owner)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(mu)
+        .await?;
+    Ok(())
+}
+
+async fn show_group_list(
+    bot: Bot,
+    msg: Message,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    let Some(from) = msg.from.as_ref() else {
+        return Ok(());
+    };
+    if !is_group_chat(&msg.chat) {
+        bot.send_message(msg.chat.id, NOT_A_GROUP)
+            .reply_markup(TextCommand::keyboard())
+            .await?;
+        return Ok(());
+    }
+    let settings = settings_or_init(&storage, msg.chat.id, from).await?;
+    send_group_list_page(
+        &bot,
+        msg.chat.id,
+        &settings,
+        false,
+        1,
+        &storage,
+        &tmdb_client,
+    )
+    .await
+}
+
+#[instrument(name = "group command", skip_all)]
+pub async fn group_command_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    show_group_list(bot, msg, storage, tmdb_client).await
+}
+
+#[instrument(name = "group watch list", skip_all)]
+pub async fn group_watch_list_text_command_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    show_group_list(bot, msg, storage, tmdb_client).await
+}
+
+/// Adds a film found in a group chat to the group's shared list.
+pub async fn add_film_to_group_list(
+    bot: &Bot,
+    chat: &Chat,
+    user: &User,
+    film_id: i64,
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+) -> Result<()> {
+    settings_or_init(storage, chat.id, user).await?;
+    let film = tmdb_client.get_films_details(film_id).await?;
+    let group_film = GroupFilm::new(chat.id.0, film_id, member(user), TitleSnapshot::from(&film));
+    let text = match storage.add_group_film(group_film).await? {
+        AddOutcome::Added => format!(
+            "Фильм:\n{film}\n {} добавил(а) его в общий список группы",
+            escape_html(&user.full_name())
+        ),
+        AddOutcome::AlreadyInWatchList => {
+            format!("Фильм:\n{film}\n Уже есть в общем списке группы")
+        }
+        AddOutcome::AlreadyWatched => format!("Фильм:\n{film}\n Группа его уже посмотрела"),
+    };
+    bot.send_message(chat.id, text)
+        .reply_markup(TextCommand::keyboard_for(chat))
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+#[instrument(name = "group list pagination callback", skip_all)]
+pub async fn group_list_pagination_callback_handler(
+    bot: Bot,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    dialogue.exit().await?;
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::GroupListNextPage { watched, page }
+        | MyCallback::GroupListPreviousPage { watched, page } = cb
+    {
+        let settings = settings_or_init(&storage, msg.chat.id, &q.from).await?;
+        send_group_list_page(
+            &bot,
+            msg.chat.id,
+            &settings,
+            watched,
+            page,
+            &storage,
+            &tmdb_client,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[instrument(name = "mark group film watched callback", skip_all)]
+pub async fn mark_group_film_watched_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::MarkGroupFilmWatched { id } = cb
+    {
+        storage
+            .watch_group_film(msg.chat.id.0, id, member(&q.from))
+            .await?;
+        if let Some(film) = storage.get_group_film(msg.chat.id.0, id).await? {
+            bot.send_message(
+                msg.chat.id,
+                "🍿 Отмечено просмотренным. Кто ещё смотрел — отметьтесь и поставьте оценку",
+            )
+            .await?;
+            send_group_film(&bot, msg.chat.id, &storage, &tmdb_client, &film).await?;
+        }
+    }
+    Ok(())
+}
+
+#[instrument(name = "attend group film callback", skip_all)]
+pub async fn attend_group_film_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+) -> Result<()> {
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::AttendGroupFilm { id } = cb
+    {
+        storage
+            .attend_group_film(msg.chat.id.0, id, member(&q.from))
+            .await?;
+        bot.answer_callback_query(q.id.clone())
+            .text("🙋 Вы отмечены среди зрителей")
+            .await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+#[instrument(name = "rate group film callback", skip_all)]
+pub async fn rate_group_film_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+) -> Result<()> {
+    bot.answer_callback_query(q.id.clone()).await?;
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::RateGroupFilm { id } = cb
+    {
+        // Everyone in the chat answers at once, so ratings are picked with buttons.
+        let buttons: Vec<InlineKeyboardButton> = (1..=10)
+            .map(|rating| {
+                let cb = MyCallback::SetGroupFilmRating { id, rating };
+                InlineKeyboardButton::callback(rating.to_string(), cb.data())
+            })
+            .collect();
+        let mu = InlineKeyboardMarkup::new(buttons.chunks(5).map(|row| row.to_vec()));
+        bot.send_message(msg.chat.id, "Каждый может поставить свою оценку от 1 до 10")
+            .reply_markup(mu)
+            .await?;
+    }
+    Ok(())
+}
+
+#[instrument(name = "set group film rating callback", skip_all)]
+pub async fn set_group_film_rating_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+) -> Result<()> {
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::SetGroupFilmRating { id, rating } = cb
+    {
+        let chat_id = msg.chat.id.0;
+        storage
+            .rate_group_film(chat_id, id, member(&q.from), f64::from(rating))
+            .await?;
+        bot.answer_callback_query(q.id.clone())
+            .text(format!("⭐ Ваша оценка: {rating}"))
+            .await?;
+        if let Some(film) = storage.get_group_film(chat_id, id).await?
+            && let Some(average) = film.average_rating()
+        {
+            let title = film
+                .snapshot
+                .as_ref()
+                .map(|s| escape_html(&s.title))
+                .unwrap_or_default();
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "{} оценил(а) <b>{title}</b> на {rating}\n📊 Средняя оценка группы: {average:.2}",
+                    escape_html(&q.from.full_name())
+                ),
+            )
+            .parse_mode(ParseMode::Html)
+            .await?;
+        }
+        return Ok(());
+    }
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+#[instrument(name = "delete group film callback", skip_all)]
+pub async fn delete_group_film_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+) -> Result<()> {
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::DeleteGroupFilm { id } = cb
+    {
+        let settings = settings_or_init(&storage, msg.chat.id, &q.from).await?;
+        let Some(film) = storage.get_group_film(msg.chat.id.0, id).await? else {
+            bot.answer_callback_query(q.id.clone())
+                .text("Этого фильма уже нет в списке")
+                .await?;
+            return Ok(());
+        };
+        if !settings.can_delete(q.from.id.0, &film) {
+            bot.answer_callback_query(q.id.clone())
+                .text("Удалять фильмы могут только владелец списка и тот, кто добавил фильм")
+                .show_alert(true)
+                .await?;
+            return Ok(());
+        }
+        storage.delete_group_film(msg.chat.id.0, id).await?;
+        bot.answer_callback_query(q.id.clone()).await?;
+        bot.delete_message(msg.chat.id, msg.id).await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
+
+#[instrument(name = "group delete restriction callback", skip_all)]
+pub async fn group_delete_restriction_callback_handler(
+    bot: Bot,
+    q: CallbackQuery,
+    cb: MyCallback,
+    storage: Storage,
+) -> Result<()> {
+    if let Some(msg) = q.regular_message()
+        && let MyCallback::GroupDeleteRestriction { restrict } = cb
+    {
+        let mut settings = settings_or_init(&storage, msg.chat.id, &q.from).await?;
+        if !settings.is_owner(q.from.id.0) {
+            bot.answer_callback_query(q.id.clone())
+                .text("Менять настройки может только владелец списка")
+                .show_alert(true)
+                .await?;
+            return Ok(());
+        }
+        settings.restrict_delete = restrict;
+        storage.set_group_settings(settings).await?;
+        let text = if restrict {
+            "🔒 Теперь удалять фильмы могут только владелец списка и тот, кто добавил фильм"
+        } else {
+            "🔓 Теперь удалять фильмы могут все участники"
+        };
+        bot.answer_callback_query(q.id.clone()).await?;
+        let mu = InlineKeyboardMarkup::default().append_row(vec![
+            MyCallback::GroupDeleteRestriction {
+                restrict: !restrict,
+            }
+            .into(),
+        ]);
+        bot.send_message(msg.chat.id, text).reply_markup(mu).await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(q.id.clone()).await?;
+    Ok(())
+}
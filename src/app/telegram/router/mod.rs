@@ -6,21 +6,31 @@ mod text_command_handlers;
 use text_command_handlers::*;
 mod text_handlers;
 use text_handlers::*;
+mod document_handlers;
+use document_handlers::*;
+mod group_handlers;
+use group_handlers::*;
+#[cfg(test)]
+mod tests;
 
 use anyhow::Error;
+use std::ops::ControlFlow;
 use std::str::FromStr;
-use teloxide::dispatching::dialogue::InMemStorage;
 use teloxide::dispatching::{UpdateHandler, dialogue};
 use teloxide::prelude::*;
 
-use crate::app::telegram::{Command, MyCallback, State, TextCommand};
+use crate::app::telegram::{Command, DialogueStorage, MyCallback, State, TextCommand};
+use crate::app::tmdb::TmdbError;
 
 pub fn main_router() -> UpdateHandler<Error> {
     use dptree::case;
     let command_handler = teloxide::filter_command::<Command, _>()
         .branch(case![Command::Start].endpoint(start_command_handler))
         .branch(case![Command::Help].endpoint(help_command_handler))
-        .branch(case![Command::Cancel].endpoint(cancel_command_handler));
+        .branch(case![Command::Cancel].endpoint(cancel_command_handler))
+        .branch(case![Command::Export].endpoint(export_command_handler))
+        .branch(case![Command::Stats].endpoint(stats_command_handler))
+        .branch(case![Command::Group].endpoint(group_command_handler));
     let callback_handler = Update::filter_callback_query()
         .filter_map(my_callback_projection)
         .branch(case![MyCallback::Cancel].endpoint(cancel_callback_handler))
@@ -97,6 +107,87 @@ pub fn main_router() -> UpdateHandler<Error> {
                 page
             }]
             .endpoint(search_serial_pagination_callback_handler),
+        )
+        .branch(
+            case![MyCallback::UserListNextPage { list, page }]
+                .endpoint(user_list_pagination_callback_handler),
+        )
+        .branch(
+            case![MyCallback::UserListPreviousPage { list, page }]
+                .endpoint(user_list_pagination_callback_handler),
+        )
+        .branch(
+            case![MyCallback::ResolveImport { key, id }].endpoint(resolve_import_callback_handler),
+        )
+        .branch(case![MyCallback::SkipImport { key }].endpoint(resolve_import_callback_handler))
+        .branch(case![MyCallback::SerialProgress { id }].endpoint(serial_progress_callback_handler))
+        .branch(
+            case![MyCallback::ChooseProgressSeason { id, season }]
+                .endpoint(serial_progress_callback_handler),
+        )
+        .branch(
+            case![MyCallback::SetSerialProgress {
+                id,
+                season,
+                episode
+            }]
+            .endpoint(set_serial_progress_callback_handler),
+        )
+        .branch(case![MyCallback::ShowSeasons { id }].endpoint(show_seasons_callback_handler))
+        .branch(
+            case![MyCallback::SerialReminders { id, enabled }]
+                .endpoint(serial_reminders_callback_handler),
+        )
+        .branch(
+            case![MyCallback::RateSerialSeason { id }]
+                .endpoint(rate_serial_season_callback_handler),
+        )
+        .branch(
+            case![MyCallback::ChooseRatingSeason { id, season }]
+                .endpoint(rate_serial_season_callback_handler),
+        )
+        .branch(
+            case![MyCallback::SeasonEpisodesNextPage { id, season, page }]
+                .endpoint(season_episodes_callback_handler),
+        )
+        .branch(
+            case![MyCallback::SeasonEpisodesPreviousPage { id, season, page }]
+                .endpoint(season_episodes_callback_handler),
+        )
+        .branch(
+            case![MyCallback::GetEpisodeDetails {
+                id,
+                season,
+                episode
+            }]
+            .endpoint(get_episode_details_callback_handler),
+        )
+        .branch(
+            case![MyCallback::GroupListNextPage { watched, page }]
+                .endpoint(group_list_pagination_callback_handler),
+        )
+        .branch(
+            case![MyCallback::GroupListPreviousPage { watched, page }]
+                .endpoint(group_list_pagination_callback_handler),
+        )
+        .branch(
+            case![MyCallback::MarkGroupFilmWatched { id }]
+                .endpoint(mark_group_film_watched_callback_handler),
+        )
+        .branch(
+            case![MyCallback::AttendGroupFilm { id }].endpoint(attend_group_film_callback_handler),
+        )
+        .branch(case![MyCallback::RateGroupFilm { id }].endpoint(rate_group_film_callback_handler))
+        .branch(
+            case![MyCallback::SetGroupFilmRating { id, rating }]
+                .endpoint(set_group_film_rating_callback_handler),
+        )
+        .branch(
+            case![MyCallback::DeleteGroupFilm { id }].endpoint(delete_group_film_callback_handler),
+        )
+        .branch(
+            case![MyCallback::GroupDeleteRestriction { restrict }]
+                .endpoint(group_delete_restriction_callback_handler),
         );
     let text_command_handler = Update::filter_message()
         .filter_map(text_command_projection)
@@ -105,22 +196,56 @@ pub fn main_router() -> UpdateHandler<Error> {
         .branch(case![TextCommand::SearchFilm].endpoint(search_film_text_command_handler))
         .branch(case![TextCommand::SearchSerial].endpoint(search_serial_text_command_handler))
         .branch(case![TextCommand::WatchedFilms].endpoint(watched_movies_text_command_handler))
-        .branch(case![TextCommand::WatchedSerials].endpoint(watched_serials_text_command_handler));
+        .branch(case![TextCommand::WatchedSerials].endpoint(watched_serials_text_command_handler))
+        .branch(case![TextCommand::YearReview].endpoint(year_review_text_command_handler))
+        .branch(case![TextCommand::GroupWatchList].endpoint(group_watch_list_text_command_handler));
     let state_handler = Update::filter_message().branch(
         Message::filter_text()
             .branch(case![State::FilmTitleReceived].endpoint(search_film_title_received))
             .branch(case![State::SerialTitleReceived].endpoint(search_serial_title_received))
             .branch(case![State::FilmRateReceived { film_id }].endpoint(film_rate_received))
-            .branch(case![State::SerialRateReceived { serial_id }].endpoint(serial_rate_received)),
+            .branch(case![State::SerialRateReceived { serial_id }].endpoint(serial_rate_received))
+            .branch(
+                case![State::SerialSeasonRateReceived { serial_id, season }]
+                    .endpoint(serial_season_rate_received),
+            ),
     );
     let message_handler = Update::filter_message()
         .branch(command_handler)
         .branch(text_command_handler)
+        .branch(Message::filter_document().endpoint(import_document_handler))
         .branch(state_handler);
 
-    dialogue::enter::<Update, InMemStorage<State>, State, _>()
-        .branch(message_handler)
-        .branch(callback_handler)
+    tmdb_error_replies().chain(
+        dialogue::enter::<Update, DialogueStorage, State, _>()
+            .branch(message_handler)
+            .branch(callback_handler),
+    )
+}
+
+/// Tells the user why a handler failed on a TMDB request instead of going silent.
+fn tmdb_error_replies() -> UpdateHandler<Error> {
+    dptree::from_fn(
+        |deps: DependencyMap, cont: dptree::Cont<'static, Result<(), Error>>| async move {
+            let bot = deps.get::<Bot>();
+            let update = deps.get::<Update>();
+            match cont(deps).await {
+                ControlFlow::Break(Err(e)) => {
+                    let Some(tmdb_error) = e.downcast_ref::<TmdbError>() else {
+                        return ControlFlow::Break(Err(e));
+                    };
+                    tracing::warn!("Handler failed on TMDB: {e:#}");
+                    let Some(chat) = update.chat() else {
+                        return ControlFlow::Break(Ok(()));
+                    };
+                    let reply = bot.send_message(chat.id, tmdb_error.user_message()).await;
+                    ControlFlow::Break(reply.map(|_| ()).map_err(Error::from))
+                }
+                flow => flow,
+            }
+        },
+        dptree::HandlerSignature::Entry,
+    )
 }
 fn text_command_projection(msg: Message) -> Option<TextCommand> {
     let command = msg.text()?;
@@ -0,0 +1,286 @@
+use std::{ops::ControlFlow, sync::Arc};
+
+use serde_json::json;
+use teloxide::{prelude::*, types::Me};
+
+use super::main_router;
+use crate::app::{
+    import::PendingImports,
+    mock_server::{MockResponse, MockServer, RecordedRequest},
+    models::Movie,
+    storage::{InMemoryStorage, Storage},
+    telegram::{DialogueStorage, MyCallback},
+    tmdb::Tmdb,
+};
+
+const USER_ID: u64 = 42;
+const FILM_ID: i64 = 550;
+
+/// The dispatcher wired to the in-memory store, with Telegram and TMDB mocked.
+struct Harness {
+    bot: Bot,
+    me: Me,
+    storage: Storage,
+    tmdb: Tmdb,
+    dialogues: Arc<DialogueStorage>,
+    pending_imports: PendingImports,
+    telegram: MockServer,
+    _tmdb_server: MockServer,
+    next_update_id: i32,
+}
+impl Harness {
+    async fn new() -> Self {
+        let telegram = MockServer::start(telegram_response).await;
+        let tmdb_server = MockServer::start(tmdb_response).await;
+        let bot = Bot::new("1:test").set_api_url(telegram.url().parse().unwrap());
+        let me = serde_json::from_value(json!({
+            "id": 1,
+            "is_bot": true,
+            "first_name": "Films",
+            "username": "films_bot",
+            "can_join_groups": true,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+            "can_connect_to_business": false,
+            "has_main_web_app": false
+        }))
+        .unwrap();
+        let tmdb = Tmdb::new("token".to_string())
+            .unwrap()
+            .with_base_url(tmdb_server.url());
+        Self {
+            bot,
+            me,
+            storage: Arc::new(InMemoryStorage::new()),
+            tmdb,
+            dialogues: DialogueStorage::in_memory(),
+            pending_imports: PendingImports::new(),
+            telegram,
+            _tmdb_server: tmdb_server,
+            next_update_id: 0,
+        }
+    }
+
+    async fn dispatch(&mut self, kind: &str, payload: serde_json::Value) -> anyhow::Result<()> {
+        self.next_update_id += 1;
+        // Update kinds are told apart by the first key after `update_id`.
+        let update = format!(
+            r#"{{"update_id":{id},"{kind}":{payload}}}"#,
+            id = self.next_update_id
+        );
+        let update: Update = serde_json::from_str(&update).unwrap();
+        let deps = dptree::deps![
+            update,
+            self.bot.clone(),
+            self.me.clone(),
+            self.storage.clone(),
+            self.tmdb.clone(),
+            self.dialogues.clone(),
+            self.pending_imports.clone()
+        ];
+        match main_router().dispatch(deps).await {
+            ControlFlow::Break(result) => result,
+            ControlFlow::Continue(_) => panic!("update was not handled"),
+        }
+    }
+
+    async fn text(&mut self, text: &str) {
+        self.try_text(text).await.unwrap();
+    }
+
+    async fn try_text(&mut self, text: &str) -> anyhow::Result<()> {
+        self.dispatch("message", message(text)).await
+    }
+
+    async fn callback(&mut self, cb: MyCallback) {
+        let query = json!({
+            "id": "1",
+            "from": user(),
+            "message": message("card"),
+            "chat_instance": "1",
+            "data": cb.data()
+        });
+        self.dispatch("callback_query", query).await.unwrap();
+    }
+
+    /// Texts and captions the bot has sent so far.
+    fn sent(&self) -> Vec<String> {
+        self.telegram
+            .requests()
+            .iter()
+            .filter_map(|r| {
+                let body: serde_json::Value = serde_json::from_str(&r.body).ok()?;
+                let text = body.get("text").or_else(|| body.get("caption"))?;
+                Some(text.as_str()?.to_string())
+            })
+            .collect()
+    }
+
+    fn last_sent(&self) -> String {
+        self.sent().pop().unwrap_or_default()
+    }
+}
+
+fn user() -> serde_json::Value {
+    json!({"id": USER_ID, "is_bot": false, "first_name": "Test", "language_code": "ru"})
+}
+
+fn message(text: &str) -> serde_json::Value {
+    json!({
+        "message_id": 1,
+        "date": 1_700_000_000,
+        "chat": {"id": USER_ID, "type": "private", "first_name": "Test"},
+        "from": user(),
+        "text": text
+    })
+}
+
+fn telegram_response(request: &RecordedRequest) -> MockResponse {
+    let method = request.path.rsplit('/').next().unwrap_or_default();
+    let result = if method.eq_ignore_ascii_case("AnswerCallbackQuery") {
+        json!(true)
+    } else {
+        message("sent")
+    };
+    MockResponse::json(json!({"ok": true, "result": result}).to_string())
+}
+
+fn tmdb_response(request: &RecordedRequest) -> MockResponse {
+    if !request.path.starts_with(&format!("/movie/{FILM_ID}?")) {
+        return MockResponse::status(404);
+    }
+    MockResponse::json(
+        json!({
+            "adult": false,
+            "backdrop_path": "",
+            "belongs_to_collection": null,
+            "budget": 63_000_000,
+            "genres": [{"id": 18, "name": "драма"}],
+            "homepage": "",
+            "id": FILM_ID,
+            "imdb_id": "tt0137523",
+            "origin_country": ["US"],
+            "original_language": "en",
+            "original_title": "Fight Club",
+            "overview": "",
+            "popularity": 1.0,
+            "poster_path": "",
+            "production_companies": [],
+            "production_countries": [{"iso_3166_1": "US", "name": "United States of America"}],
+            "release_date": "1999-10-15",
+            "revenue": 100_853_753,
+            "runtime": 139,
+            "spoken_languages": [],
+            "status": "Released",
+            "tagline": "",
+            "title": "Бойцовский клуб",
+            "video": false,
+            "vote_average": 8.4,
+            "vote_count": 30_000
+        })
+        .to_string(),
+    )
+}
+
+#[tokio::test]
+async fn film_goes_through_the_watch_list() {
+    let mut h = Harness::new().await;
+
+    h.callback(MyCallback::AddFilmToWatchList { id: FILM_ID })
+        .await;
+    assert!(h.last_sent().contains("Добавлен в список для просмотра"));
+    let movies = h.storage.get_users_movies(USER_ID).await.unwrap();
+    assert_eq!(movies.len(), 1);
+    assert!(!movies[0].viewing.watched);
+
+    h.callback(MyCallback::AddFilmToWatchList { id: FILM_ID })
+        .await;
+    assert!(h.last_sent().contains("Уже есть в вашем списке"));
+
+    h.text("🤔 Отложенные фильмы").await;
+    let sent = h.sent();
+    assert!(sent.iter().any(|t| t.contains("Бойцовский клуб")));
+    assert!(h.last_sent().contains("всего в списке: 1"));
+
+    h.callback(MyCallback::MarkFilmWatched { id: FILM_ID })
+        .await;
+    h.text("8,5").await;
+    assert_eq!(h.last_sent(), "Спасибо за оценку!");
+    let watched = h
+        .storage
+        .get_users_watched_movies_list(USER_ID)
+        .await
+        .unwrap();
+    assert_eq!(watched.len(), 1);
+    assert_eq!(watched[0].viewing.my_rating, Some(8.5));
+
+    h.text("💼 Просмотренные фильмы").await;
+    assert!(
+        h.sent()
+            .iter()
+            .any(|t| t.contains("Ваша текущая оценка: 8.50"))
+    );
+
+    h.callback(MyCallback::MarkFilmUnWatched { id: FILM_ID })
+        .await;
+    assert_eq!(h.last_sent(), "Фильм отмечен непросмотренным");
+    let page = h
+        .storage
+        .get_users_movies_page(USER_ID, false, 0, 5)
+        .await
+        .unwrap();
+    assert_eq!(page.total, 1);
+
+    h.callback(MyCallback::DeleteFilm { id: FILM_ID }).await;
+    assert_eq!(h.last_sent(), "Фильм удален из списка");
+    assert!(
+        h.storage
+            .get_users_movies(USER_ID)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn empty_watched_list_says_so() {
+    let mut h = Harness::new().await;
+    h.text("💼 Просмотренные фильмы").await;
+    assert_eq!(h.last_sent(), "Ваш список просмотренных фильмов пуст");
+}
+
+#[tokio::test]
+async fn rating_must_be_a_number() {
+    let mut h = Harness::new().await;
+    h.callback(MyCallback::AddFilmToWatchList { id: FILM_ID })
+        .await;
+    h.callback(MyCallback::MarkFilmWatched { id: FILM_ID })
+        .await;
+    assert!(h.try_text("отлично").await.is_err());
+    let watched = h
+        .storage
+        .get_users_watched_movies_list(USER_ID)
+        .await
+        .unwrap();
+    assert_eq!(watched[0].viewing.my_rating, None);
+}
+
+#[tokio::test]
+async fn list_page_survives_a_title_tmdb_cannot_return() {
+    let mut h = Harness::new().await;
+    h.storage
+        .import_film(Movie::new(USER_ID, 404), "RU".to_string())
+        .await
+        .unwrap();
+    h.callback(MyCallback::AddFilmToWatchList { id: FILM_ID })
+        .await;
+
+    h.text("🤔 Отложенные фильмы").await;
+    let sent = h.sent();
+    assert!(
+        sent.iter()
+            .any(|t| t.contains("Не удалось загрузить данные из TMDB"))
+    );
+    assert!(sent.iter().any(|t| t.contains("Бойцовский клуб")));
+    assert!(h.last_sent().contains("всего в списке: 2"));
+}
@@ -1,16 +1,23 @@
 use anyhow::{Context, Result};
+use mongodb::bson::DateTime;
 use teloxide::{
+    RequestError,
     prelude::*,
-    types::{InlineKeyboardMarkup, KeyboardRemove, ParseMode},
+    types::{InlineKeyboardMarkup, InputFile, KeyboardRemove, ParseMode},
 };
 use tracing::instrument;
 
 use crate::app::{
+    review::{YearReview, review_year},
+    snapshots::{film_snapshot, serial_snapshot},
     storage::Storage,
-    telegram::{MyCallback, MyDialogue, State, TextCommand},
-    tmdb::Tmdb,
+    telegram::{MyCallback, MyDialogue, State, TextCommand, UserList},
+    tmdb::{ImageKind, Tmdb},
 };
 
+/// Number of titles shown per page of a user's list.
+const PAGE_SIZE: u64 = 5;
+
 #[instrument(name = "search film", skip_all)]
 pub async fn search_film_text_command_handler(
     bot: Bot,
@@ -31,31 +38,19 @@ pub async fn films_to_watch_text_command_handler(
     storage: Storage,
     tmdb_client: Tmdb,
 ) -> Result<()> {
-    tracing::info!("RECEIVED WATCH LIST!");
     if let Some(from) = msg.from {
-        let users_watch_list = storage.get_users_movie_watch_list(from.id.0).await?;
-        tracing::info!("Users watch list contains {} films", users_watch_list.len());
-        if !users_watch_list.is_empty() {
-            for movie in users_watch_list {
-                let film = tmdb_client.get_films_details(movie.film_id).await?;
-                let poster = tmdb_client.get_image(&film.poster_path).await?;
-                let mu = InlineKeyboardMarkup::default()
-                    .append_row(vec![
-                        MyCallback::MarkFilmWatched { id: film.id }.into(),
-                        MyCallback::DeleteFilm { id: film.id }.into(),
-                    ])
-                    .append_row(vec![
-                        MyCallback::GetFilmsDetails { id: film.id }.into(),
-                        MyCallback::GetFilmsCredits { id: film.id }.into(),
-                    ])
-                    .append_row(vec![MyCallback::Cancel.into()]);
-                bot.send_photo(msg.chat.id, poster)
-                    .caption(film.to_string())
-                    .parse_mode(teloxide::types::ParseMode::Html)
-                    .reply_markup(mu)
-                    .await?;
-            }
-        } else {
+        let total = send_user_list_page(
+            &bot,
+            msg.chat.id,
+            from.id.0,
+            UserList::FilmsToWatch,
+            1,
+            &storage,
+            &tmdb_client,
+        )
+        .await?;
+        tracing::info!("Users watch list contains {total} films");
+        if total == 0 {
             let popular_movies = tmdb_client.get_popular_movies(1).await?;
             let watched = storage.get_users_watched_movies_list(from.id.0).await?;
             for film in popular_movies.results {
@@ -65,19 +60,20 @@ pub async fn films_to_watch_text_command_handler(
                 let text = film.to_string();
                 match film.poster_path.as_ref() {
                     Some(image_url) => {
-                        let file = tmdb_client
-                            .get_image(&image_url)
-                            .await
-                            .context("Getting image")?;
                         let mu = InlineKeyboardMarkup::default().append_row(vec![
                             MyCallback::GetFilmsDetails { id: film.id }.into(),
                             MyCallback::AddFilmToWatchList { id: film.id }.into(),
                         ]);
-                        bot.send_photo(msg.chat.id, file)
-                            .caption(&text)
-                            .parse_mode(ParseMode::Html)
-                            .reply_markup(mu)
-                            .await?;
+                        send_card(
+                            &bot,
+                            msg.chat.id,
+                            &storage,
+                            &tmdb_client,
+                            Some((image_url, ImageKind::ListPoster)),
+                            text.clone(),
+                            mu,
+                        )
+                        .await?;
                     }
                     None => {
                         let mu = InlineKeyboardMarkup::default().append_row(vec![
@@ -108,39 +104,17 @@ pub async fn watched_movies_text_command_handler(
     tmdb_client: Tmdb,
 ) -> Result<()> {
     if let Some(from) = msg.from {
-        let watched = storage.get_users_watched_movies_list(from.id.0).await?;
-        if !watched.is_empty() {
-            for movie in watched {
-                let film = tmdb_client.get_films_details(movie.film_id).await?;
-                let poster = tmdb_client.get_image(&film.poster_path).await?;
-                if let Some(current_rate) = movie.my_rating {
-                    let mu = InlineKeyboardMarkup::default()
-                        .append_row(vec![
-                            MyCallback::RateFilm { id: film.id }.into(),
-                            MyCallback::MarkFilmUnWatched { id: film.id }.into(),
-                        ])
-                        .append_row(vec![MyCallback::DeleteFilm { id: film.id }.into()]);
-                    bot.send_photo(msg.chat.id, poster)
-                        .caption(format!(
-                            "{}\nВаша текущая оценка: {current_rate:.2}",
-                            film.to_string()
-                        ))
-                        .parse_mode(ParseMode::Html)
-                        .reply_markup(mu)
-                        .await?;
-                } else {
-                    let mu = InlineKeyboardMarkup::default().append_row(vec![
-                        MyCallback::RateFilm { id: film.id }.into(),
-                        MyCallback::DeleteFilm { id: film.id }.into(),
-                    ]);
-                    bot.send_photo(msg.chat.id, poster)
-                        .caption(film.to_string())
-                        .parse_mode(ParseMode::Html)
-                        .reply_markup(mu)
-                        .await?;
-                }
-            }
-        } else {
+        let total = send_user_list_page(
+            &bot,
+            msg.chat.id,
+            from.id.0,
+            UserList::WatchedFilms,
+            1,
+            &storage,
+            &tmdb_client,
+        )
+        .await?;
+        if total == 0 {
             bot.send_message(msg.chat.id, "Ваш список просмотренных фильмов пуст")
                 .reply_markup(TextCommand::keyboard())
                 .await?;
@@ -169,32 +143,18 @@ pub async fn serials_to_watch_text_command_handler(
     tmdb_client: Tmdb,
 ) -> Result<()> {
     if let Some(from) = msg.from {
-        let users_watch_list = storage.get_users_serials_watch_list(from.id.0).await?;
-        tracing::info!(
-            "Users watch list contains {} serials",
-            users_watch_list.len()
-        );
-        if !users_watch_list.is_empty() {
-            for serial in users_watch_list {
-                let tv_show = tmdb_client.get_tv_show_details(serial.serial_id).await?;
-                let poster = tmdb_client.get_image(&tv_show.poster_path).await?;
-                let mu = InlineKeyboardMarkup::default()
-                    .append_row(vec![
-                        MyCallback::MarkSerialWatched { id: tv_show.id }.into(),
-                        MyCallback::DeleteSerial { id: tv_show.id }.into(),
-                    ])
-                    .append_row(vec![
-                        MyCallback::GetSerialDetails { id: tv_show.id }.into(),
-                        MyCallback::GetSerialCredits { id: tv_show.id }.into(),
-                    ])
-                    .append_row(vec![MyCallback::Cancel.into()]);
-                bot.send_photo(msg.chat.id, poster)
-                    .caption(tv_show.to_string())
-                    .parse_mode(teloxide::types::ParseMode::Html)
-                    .reply_markup(mu)
-                    .await?;
-            }
-        } else {
+        let total = send_user_list_page(
+            &bot,
+            msg.chat.id,
+            from.id.0,
+            UserList::SerialsToWatch,
+            1,
+            &storage,
+            &tmdb_client,
+        )
+        .await?;
+        tracing::info!("Users watch list contains {total} serials");
+        if total == 0 {
             let popular_serials = tmdb_client.get_popular_tv_shows(1).await?;
             let watched = storage.get_users_watched_serials_list(from.id.0).await?;
             for serial in popular_serials.results {
@@ -204,19 +164,20 @@ pub async fn serials_to_watch_text_command_handler(
                 let text = serial.to_string();
                 match serial.poster_path.as_ref() {
                     Some(image_url) => {
-                        let file = tmdb_client
-                            .get_image(&image_url)
-                            .await
-                            .context("Getting image")?;
                         let mu = InlineKeyboardMarkup::default().append_row(vec![
                             MyCallback::GetSerialDetails { id: serial.id }.into(),
                             MyCallback::AddSerialToWatchList { id: serial.id }.into(),
                         ]);
-                        bot.send_photo(msg.chat.id, file)
-                            .caption(&text)
-                            .parse_mode(ParseMode::Html)
-                            .reply_markup(mu)
-                            .await?;
+                        send_card(
+                            &bot,
+                            msg.chat.id,
+                            &storage,
+                            &tmdb_client,
+                            Some((image_url, ImageKind::ListPoster)),
+                            text.clone(),
+                            mu,
+                        )
+                        .await?;
                     }
                     None => {
                         let mu = InlineKeyboardMarkup::default().append_row(vec![
@@ -247,43 +208,321 @@ pub async fn watched_serials_text_command_handler(
     tmdb_client: Tmdb,
 ) -> Result<()> {
     if let Some(from) = msg.from {
-        let watched = storage.get_users_watched_serials_list(from.id.0).await?;
-        if !watched.is_empty() {
-            for serial in watched {
-                let tv_show = tmdb_client.get_tv_show_details(serial.serial_id).await?;
-                let poster = tmdb_client.get_image(&tv_show.poster_path).await?;
-                if let Some(current_rate) = serial.my_rating {
-                    let mu = InlineKeyboardMarkup::default()
+        let total = send_user_list_page(
+            &bot,
+            msg.chat.id,
+            from.id.0,
+            UserList::WatchedSerials,
+            1,
+            &storage,
+            &tmdb_client,
+        )
+        .await?;
+        if total == 0 {
+            bot.send_message(msg.chat.id, "Ваш список просмотренных сериалов пуст")
+                .reply_markup(TextCommand::keyboard())
+                .await?;
+        }
+    }
+    Ok(())
+}
+#[instrument(name = "year review", skip_all)]
+pub async fn year_review_text_command_handler(
+    bot: Bot,
+    msg: Message,
+    storage: Storage,
+    tmdb_client: Tmdb,
+) -> Result<()> {
+    let Some(from) = msg.from else {
+        return Ok(());
+    };
+    let year = review_year(DateTime::now());
+    let review = YearReview::build(&storage, &tmdb_client, from.id.0, year).await?;
+    if review.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            format!("В {year} году вы ещё ничего не отметили просмотренным"),
+        )
+        .reply_markup(TextCommand::keyboard())
+        .await?;
+        return Ok(());
+    }
+    if let Some(collage) = review.collage(&tmdb_client).await? {
+        let file = InputFile::memory(collage).file_name(format!("itogi-{year}.png"));
+        bot.send_photo(msg.chat.id, file)
+            .caption(format!("🎉 Итоги {year} года"))
+            .await?;
+    }
+    bot.send_message(msg.chat.id, review.to_string())
+        .parse_mode(ParseMode::Html)
+        .reply_markup(TextCommand::keyboard())
+        .await?;
+    Ok(())
+}
+/// Sends a card as a photo when the title has a poster, as text otherwise.
+///
+/// Posters are uploaded once per size: later cards reuse the `file_id` Telegram
+/// returned, and the poster is downloaded again only if Telegram rejects a stale id.
+pub async fn send_card(
+    bot: &Bot,
+    chat_id: ChatId,
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+    poster: Option<(&str, ImageKind)>,
+    caption: String,
+    mu: InlineKeyboardMarkup,
+) -> Result<()> {
+    let Some((path, kind)) = poster else {
+        bot.send_message(chat_id, caption)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(mu)
+            .await?;
+        return Ok(());
+    };
+    let poster_path = format!("{size}{path}", size = tmdb_client.image_size(kind));
+    let file_id = match storage.poster_file_id(&poster_path).await {
+        Ok(file_id) => file_id,
+        Err(e) => {
+            tracing::warn!("Failed to look up the file id of {poster_path}: {e}");
+            None
+        }
+    };
+    if let Some(file_id) = file_id {
+        let sent = bot
+            .send_photo(chat_id, InputFile::file_id(file_id.into()))
+            .caption(caption.clone())
+            .parse_mode(ParseMode::Html)
+            .reply_markup(mu.clone())
+            .await;
+        match sent {
+            Ok(_) => return Ok(()),
+            Err(RequestError::Api(e)) => {
+                tracing::warn!("Telegram rejected the file id of {poster_path}: {e}");
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let poster = tmdb_client
+        .get_image(path, kind)
+        .await
+        .context("Getting image")?;
+    let sent = bot
+        .send_photo(chat_id, poster)
+        .caption(caption)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(mu)
+        .await?;
+    // Telegram returns every size it made, the last one is the original upload.
+    if let Some(photo) = sent.photo().and_then(|sizes| sizes.last())
+        && let Err(e) = storage
+            .set_poster_file_id(&poster_path, &photo.file.id.0)
+            .await
+    {
+        tracing::warn!("Failed to save the file id of {poster_path}: {e}");
+    }
+    Ok(())
+}
+
+/// Sends one page of the user's list and returns the size of the whole list.
+pub async fn send_user_list_page(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: u64,
+    list: UserList,
+    page: u32,
+    storage: &Storage,
+    tmdb_client: &Tmdb,
+) -> Result<u64> {
+    let skip = u64::from(page.saturating_sub(1)) * PAGE_SIZE;
+    let mut cards = Vec::new();
+    let total = match list {
+        UserList::FilmsToWatch | UserList::WatchedFilms => {
+            let watched = list == UserList::WatchedFilms;
+            let result = storage
+                .get_users_movies_page(user_id, watched, skip, PAGE_SIZE)
+                .await?;
+            for movie in result.items {
+                let id = movie.film_id;
+                let snapshot = match film_snapshot(storage, tmdb_client, &movie).await {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(e) => {
+                        tracing::warn!("Failed to get the snapshot of film {id}: {e:#}");
+                        None
+                    }
+                };
+                let mut caption = snapshot
+                    .as_ref()
+                    .map_or_else(|| unavailable_caption("Фильм", id), ToString::to_string);
+                let mu = if !watched {
+                    InlineKeyboardMarkup::default()
                         .append_row(vec![
-                            MyCallback::RateSerial { id: tv_show.id }.into(),
-                            MyCallback::MarkSerialUnWatched { id: tv_show.id }.into(),
+                            MyCallback::MarkFilmWatched { id }.into(),
+                            MyCallback::DeleteFilm { id }.into(),
                         ])
-                        .append_row(vec![MyCallback::DeleteSerial { id: tv_show.id }.into()]);
-                    bot.send_photo(msg.chat.id, poster)
-                        .caption(format!(
-                            "{}\nВаша текущая оценка: {current_rate:.2}",
-                            tv_show.to_string()
-                        ))
-                        .parse_mode(ParseMode::Html)
-                        .reply_markup(mu)
-                        .await?;
+                        .append_row(vec![
+                            MyCallback::GetFilmsDetails { id }.into(),
+                            MyCallback::GetFilmsCredits { id }.into(),
+                        ])
+                        .append_row(vec![MyCallback::Cancel.into()])
+                } else if let Some(current_rate) = movie.viewing.my_rating {
+                    caption.push_str(&watched_suffix(movie.viewing.watched_on()));
+                    caption.push_str(&format!("\nВаша текущая оценка: {current_rate:.2}"));
+                    InlineKeyboardMarkup::default()
+                        .append_row(vec![
+                            MyCallback::RateFilm { id }.into(),
+                            MyCallback::MarkFilmUnWatched { id }.into(),
+                        ])
+                        .append_row(vec![MyCallback::DeleteFilm { id }.into()])
                 } else {
-                    let mu = InlineKeyboardMarkup::default().append_row(vec![
-                        MyCallback::RateSerial { id: tv_show.id }.into(),
-                        MyCallback::DeleteSerial { id: tv_show.id }.into(),
-                    ]);
-                    bot.send_photo(msg.chat.id, poster)
-                        .caption(tv_show.to_string())
-                        .parse_mode(ParseMode::Html)
-                        .reply_markup(mu)
-                        .await?;
-                }
+                    caption.push_str(&watched_suffix(movie.viewing.watched_on()));
+                    InlineKeyboardMarkup::default().append_row(vec![
+                        MyCallback::RateFilm { id }.into(),
+                        MyCallback::DeleteFilm { id }.into(),
+                    ])
+                };
+                cards.push((snapshot.and_then(|s| s.poster_path), caption, mu));
             }
-        } else {
-            bot.send_message(msg.chat.id, "Ваш список просмотренных фильмов пуст")
-                .reply_markup(TextCommand::keyboard())
+            result.total
+        }
+        UserList::SerialsToWatch | UserList::WatchedSerials => {
+            let watched = list == UserList::WatchedSerials;
+            let result = storage
+                .get_users_serials_page(user_id, watched, skip, PAGE_SIZE)
                 .await?;
+            for serial in result.items {
+                let id = serial.serial_id;
+                let snapshot = match serial_snapshot(storage, tmdb_client, &serial).await {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(e) => {
+                        tracing::warn!("Failed to get the snapshot of serial {id}: {e:#}");
+                        None
+                    }
+                };
+                let mut caption = snapshot
+                    .as_ref()
+                    .map_or_else(|| unavailable_caption("Сериал", id), ToString::to_string);
+                let reminders = MyCallback::SerialReminders {
+                    id,
+                    enabled: !serial.reminders,
+                };
+                if let Some(snapshot) = &snapshot
+                    && let Some(progress) = serial.progress_line(snapshot)
+                {
+                    caption.push_str(&format!("\n{progress}"));
+                }
+                let mu = if !watched {
+                    InlineKeyboardMarkup::default()
+                        .append_row(vec![
+                            MyCallback::MarkSerialWatched { id }.into(),
+                            MyCallback::DeleteSerial { id }.into(),
+                        ])
+                        .append_row(vec![
+                            MyCallback::GetSerialDetails { id }.into(),
+                            MyCallback::GetSerialCredits { id }.into(),
+                        ])
+                        .append_row(vec![
+                            MyCallback::SerialProgress { id }.into(),
+                            reminders.into(),
+                        ])
+                        .append_row(vec![MyCallback::Cancel.into()])
+                } else if let Some(current_rate) = serial.overall_rating() {
+                    caption.push_str(&watched_suffix(serial.viewing.watched_on()));
+                    if serial.viewing.my_rating.is_some() {
+                        caption.push_str(&format!("\nВаша текущая оценка: {current_rate:.2}"));
+                    } else {
+                        caption.push_str(&format!("\nОценка по сезонам: {current_rate:.2}"));
+                    }
+                    if let Some(season_ratings) = serial.season_ratings_line() {
+                        caption.push_str(&format!("\n{season_ratings}"));
+                    }
+                    InlineKeyboardMarkup::default()
+                        .append_row(vec![
+                            MyCallback::RateSerial { id }.into(),
+                            MyCallback::RateSerialSeason { id }.into(),
+                        ])
+                        .append_row(vec![
+                            MyCallback::MarkSerialUnWatched { id }.into(),
+                            MyCallback::DeleteSerial { id }.into(),
+                        ])
+                        .append_row(vec![reminders.into()])
+                } else {
+                    caption.push_str(&watched_suffix(serial.viewing.watched_on()));
+                    InlineKeyboardMarkup::default()
+                        .append_row(vec![
+                            MyCallback::RateSerial { id }.into(),
+                            MyCallback::RateSerialSeason { id }.into(),
+                        ])
+                        .append_row(vec![
+                            MyCallback::DeleteSerial { id }.into(),
+                            reminders.into(),
+                        ])
+                };
+                cards.push((snapshot.and_then(|s| s.poster_path), caption, mu));
+            }
+            result.total
         }
+    };
+    if total == 0 {
+        return Ok(0);
     }
-    Ok(())
+    let pages = total.div_ceil(PAGE_SIZE);
+    let mut row = Vec::new();
+    if page > 1 {
+        row.push(
+            MyCallback::UserListPreviousPage {
+                list,
+                page: page - 1,
+            }
+            .into(),
+        );
+    }
+    if u64::from(page) < pages {
+        row.push(
+            MyCallback::UserListNextPage {
+                list,
+                page: page + 1,
+            }
+            .into(),
+        );
+    }
+    if cards.is_empty() {
+        bot.send_message(chat_id, "На этой странице ничего нет")
+            .reply_markup(InlineKeyboardMarkup::default().append_row(row))
+            .await?;
+        return Ok(total);
+    }
+    let l = cards.len();
+    for (i, (poster_path, caption, mut mu)) in cards.into_iter().enumerate() {
+        if i == l - 1 && !row.is_empty() {
+            mu = mu.append_row(row.clone());
+        }
+        send_card(
+            bot,
+            chat_id,
+            storage,
+            tmdb_client,
+            poster_path.as_deref().map(|p| (p, ImageKind::ListPoster)),
+            caption,
+            mu,
+        )
+        .await?;
+    }
+    bot.send_message(
+        chat_id,
+        format!("Страница {page} из {pages}, всего в списке: {total}"),
+    )
+    .reply_markup(TextCommand::keyboard())
+    .await?;
+    Ok(total)
+}
+
+/// Caption of a card whose title TMDB couldn't return, the buttons still work.
+fn unavailable_caption(kind: &str, id: i64) -> String {
+    format!("<b>{kind} #{id}</b>\n⚠️ Не удалось загрузить данные из TMDB")
+}
+
+fn watched_suffix(watched_on: Option<String>) -> String {
+    watched_on
+        .map(|watched_on| format!("\n✅ {watched_on}"))
+        .unwrap_or_default()
 }
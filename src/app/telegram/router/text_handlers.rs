@@ -1,22 +1,28 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use teloxide::{
     prelude::*,
     types::{InlineKeyboardMarkup, ParseMode},
 };
 use tracing::instrument;
 
+use super::send_card;
+
 use crate::app::{
     storage::Storage,
     telegram::{MyCallback, MyDialogue, State, TextCommand},
-    tmdb::Tmdb,
+    tmdb::{ImageKind, Tmdb},
 };
 
-#[instrument(name = "search film by title", skip(bot, msg, dialogue, tmdb_client))]
+#[instrument(
+    name = "search film by title",
+    skip(bot, msg, dialogue, storage, tmdb_client)
+)]
 pub async fn search_film_title_received(
     bot: Bot,
     msg: Message,
     dialogue: MyDialogue,
     message_text: String,
+    storage: Storage,
     tmdb_client: Tmdb,
 ) -> Result<()> {
     dialogue.exit().await?;
@@ -25,7 +31,7 @@ pub async fn search_film_title_received(
     let total = result.total_pages;
     if films.is_empty() {
         bot.send_message(msg.chat.id, "Ничего не найдено")
-            .reply_markup(TextCommand::keyboard())
+            .reply_markup(TextCommand::keyboard_for(&msg.chat))
             .await?;
     } else {
         let l = films.len();
@@ -33,10 +39,6 @@ pub async fn search_film_title_received(
             let text = film.to_string();
             match film.poster_path.as_ref() {
                 Some(image_url) => {
-                    let file = tmdb_client
-                        .get_image(image_url)
-                        .await
-                        .context("Getting image")?;
                     let mut mu = InlineKeyboardMarkup::default().append_row(vec![
                         MyCallback::GetFilmsDetails { id: film.id }.into(),
                         MyCallback::AddFilmToWatchList { id: film.id }.into(),
@@ -50,11 +52,16 @@ pub async fn search_film_title_received(
                             .into(),
                         ]);
                     }
-                    bot.send_photo(msg.chat.id, file)
-                        .caption(&text)
-                        .parse_mode(ParseMode::Html)
-                        .reply_markup(mu)
-                        .await?;
+                    send_card(
+                        &bot,
+                        msg.chat.id,
+                        &storage,
+                        &tmdb_client,
+                        Some((image_url, ImageKind::ListPoster)),
+                        text.clone(),
+                        mu,
+                    )
+                    .await?;
                 }
                 None => {
                     let mut mu = InlineKeyboardMarkup::default().append_row(vec![
@@ -92,28 +99,31 @@ pub async fn film_rate_received(
     message_text: String,
     storage: Storage,
 ) -> Result<()> {
-    if let Some(from) = msg.from {
-        if let Some(data) = dialogue.get().await? {
-            if let State::FilmRateReceived { film_id } = data {
-                let user_id = from.id.0;
-                let rate = message_text.replace(',', ".").trim().parse()?;
-                storage.rate_movie(user_id, film_id, rate).await?;
-                bot.send_message(msg.chat.id, "Спасибо за оценку!")
-                    .reply_markup(TextCommand::keyboard())
-                    .await?;
-                dialogue.exit().await?;
-            }
-        }
+    if let Some(from) = msg.from
+        && let Some(data) = dialogue.get().await?
+        && let State::FilmRateReceived { film_id } = data
+    {
+        let user_id = from.id.0;
+        let rate = message_text.replace(',', ".").trim().parse()?;
+        storage.rate_movie(user_id, film_id, rate).await?;
+        bot.send_message(msg.chat.id, "Спасибо за оценку!")
+            .reply_markup(TextCommand::keyboard())
+            .await?;
+        dialogue.exit().await?;
     }
 
     Ok(())
 }
-#[instrument(name = "search serial by title", skip(bot, msg, dialogue, tmdb_client))]
+#[instrument(
+    name = "search serial by title",
+    skip(bot, msg, dialogue, storage, tmdb_client)
+)]
 pub async fn search_serial_title_received(
     bot: Bot,
     msg: Message,
     dialogue: MyDialogue,
     message_text: String,
+    storage: Storage,
     tmdb_client: Tmdb,
 ) -> Result<()> {
     dialogue.exit().await?;
@@ -122,7 +132,7 @@ pub async fn search_serial_title_received(
     let total = result.total_pages;
     if serials.is_empty() {
         bot.send_message(msg.chat.id, "Ничего не найдено")
-            .reply_markup(TextCommand::keyboard())
+            .reply_markup(TextCommand::keyboard_for(&msg.chat))
             .await?;
     } else {
         let l = serials.len();
@@ -130,10 +140,6 @@ pub async fn search_serial_title_received(
             let text = serial.to_string();
             match serial.poster_path.as_ref() {
                 Some(image_url) => {
-                    let file = tmdb_client
-                        .get_image(&image_url)
-                        .await
-                        .context("Getting image")?;
                     let mut mu = InlineKeyboardMarkup::default().append_row(vec![
                         MyCallback::GetSerialDetails { id: serial.id }.into(),
                         MyCallback::AddSerialToWatchList { id: serial.id }.into(),
@@ -147,11 +153,16 @@ pub async fn search_serial_title_received(
                             .into(),
                         ]);
                     }
-                    bot.send_photo(msg.chat.id, file)
-                        .caption(&text)
-                        .parse_mode(ParseMode::Html)
-                        .reply_markup(mu)
-                        .await?;
+                    send_card(
+                        &bot,
+                        msg.chat.id,
+                        &storage,
+                        &tmdb_client,
+                        Some((image_url, ImageKind::ListPoster)),
+                        text.clone(),
+                        mu,
+                    )
+                    .await?;
                 }
                 None => {
                     let mut mu = InlineKeyboardMarkup::default().append_row(vec![
@@ -189,18 +200,49 @@ pub async fn serial_rate_received(
     message_text: String,
     storage: Storage,
 ) -> Result<()> {
-    if let Some(from) = msg.from {
-        if let Some(data) = dialogue.get().await? {
-            if let State::SerialRateReceived { serial_id } = data {
-                let user_id = from.id.0;
-                let rate = message_text.replace(',', ".").trim().parse()?;
-                storage.rate_serial(user_id, serial_id, rate).await?;
-                bot.send_message(msg.chat.id, "Спасибо за оценку!")
-                    .reply_markup(TextCommand::keyboard())
-                    .await?;
-                dialogue.exit().await?;
-            }
+    if let Some(from) = msg.from
+        && let Some(data) = dialogue.get().await?
+        && let State::SerialRateReceived { serial_id } = data
+    {
+        let user_id = from.id.0;
+        let rate = message_text.replace(',', ".").trim().parse()?;
+        storage.rate_serial(user_id, serial_id, rate).await?;
+        bot.send_message(msg.chat.id, "Спасибо за оценку!")
+            .reply_markup(TextCommand::keyboard())
+            .await?;
+        dialogue.exit().await?;
+    }
+
+    Ok(())
+}
+#[instrument(name = "rate serial season", skip(bot, msg, dialogue, storage))]
+pub async fn serial_season_rate_received(
+    bot: Bot,
+    msg: Message,
+    dialogue: MyDialogue,
+    message_text: String,
+    storage: Storage,
+) -> Result<()> {
+    if let Some(from) = msg.from
+        && let Some(data) = dialogue.get().await?
+        && let State::SerialSeasonRateReceived { serial_id, season } = data
+    {
+        let user_id = from.id.0;
+        let rate = message_text.replace(',', ".").trim().parse()?;
+        storage
+            .rate_serial_season(user_id, serial_id, season, rate)
+            .await?;
+        let mut text = String::from("Спасибо за оценку!");
+        if let Some(serial) = storage.get_serial(user_id, serial_id).await?
+            && serial.viewing.my_rating.is_none()
+            && let Some(average) = serial.season_average()
+        {
+            text.push_str(&format!("\nОбщая оценка по сезонам: {average:.2}"));
         }
+        bot.send_message(msg.chat.id, text)
+            .reply_markup(TextCommand::keyboard())
+            .await?;
+        dialogue.exit().await?;
     }
 
     Ok(())
@@ -50,22 +50,289 @@ macro_rules! minutes_to_hours {
         }
     }};
 }
-use std::fmt::Display;
-
-use anyhow::{Result, anyhow};
-use serde::{Deserialize, Serialize};
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::BuildHasher,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use reqwest::{Response, StatusCode, header::RETRY_AFTER};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use teloxide::types::InputFile;
+use tokio::sync::Semaphore;
 use tracing::instrument;
 
+use crate::app::cache::ResponseCache;
+
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
+/// Requests to TMDB allowed at the same time, the rest wait for a slot.
+const MAX_IN_FLIGHT: usize = 8;
+/// Responses kept in the in-process cache.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1000;
+const DETAILS_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+const CREDITS_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const SEARCH_TTL: Duration = Duration::from_secs(60 * 60);
+const RELEASE_DATES_TTL: Duration = Duration::from_secs(3 * 60 * 60);
+const CONFIGURATION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const FIND_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How failed GET requests to TMDB are retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt.
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for every next one.
+    pub base_delay: Duration,
+    /// Longest wait between attempts; a longer `Retry-After` fails the request instead.
+    pub max_delay: Duration,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+impl RetryPolicy {
+    /// Exponential backoff with jitter, so parallel requests don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let random = RandomState::new().hash_one(attempt);
+        let half = exponential / 2;
+        half + half.mul_f64((random % 1000) as f64 / 1000.0)
+    }
+}
+
+/// Counters of the requests sent to TMDB since the client was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TmdbMetrics {
+    pub requests: u64,
+    /// Attempts repeated after a throttle, a server error or a network failure.
+    pub retries: u64,
+    /// Responses with `429 Too Many Requests`.
+    pub throttles: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+impl Display for TmdbMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} requests, {} retries, {} throttles, cache: {} hits, {} misses",
+            self.requests, self.retries, self.throttles, self.cache_hits, self.cache_misses
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicU64,
+    retries: AtomicU64,
+    throttles: AtomicU64,
+}
+
+/// Why a request to TMDB failed.
+#[derive(Debug)]
+pub enum TmdbError {
+    /// The title, season or episode doesn't exist.
+    NotFound,
+    /// TMDB rejected the token.
+    Unauthorized,
+    /// Still throttled after all retries.
+    RateLimited,
+    /// A network failure or an unexpected response status.
+    Upstream(String),
+    /// The response didn't match the model, `path` points at the offending field.
+    Decode { path: String, message: String },
+}
+impl Display for TmdbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TmdbError::NotFound => write!(f, "TMDB: not found"),
+            TmdbError::Unauthorized => write!(f, "TMDB: unauthorized"),
+            TmdbError::RateLimited => write!(f, "TMDB: rate limited"),
+            TmdbError::Upstream(e) => write!(f, "TMDB: {e}"),
+            TmdbError::Decode { path, message } => {
+                write!(f, "TMDB: failed to decode {path}: {message}")
+            }
+        }
+    }
+}
+impl std::error::Error for TmdbError {}
+impl From<reqwest::Error> for TmdbError {
+    fn from(e: reqwest::Error) -> Self {
+        TmdbError::Upstream(e.to_string())
+    }
+}
+impl TmdbError {
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::NOT_FOUND => TmdbError::NotFound,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => TmdbError::Unauthorized,
+            StatusCode::TOO_MANY_REQUESTS => TmdbError::RateLimited,
+            status => TmdbError::Upstream(format!("Error: {status}")),
+        }
+    }
+    /// What to tell the user whose request failed.
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            TmdbError::NotFound => "😕 В TMDB нет такого фильма или сериала",
+            TmdbError::Unauthorized => {
+                "🔑 Бот не смог авторизоваться в TMDB, сообщите об этом администратору"
+            }
+            TmdbError::RateLimited => "⏳ TMDB просит подождать, попробуйте ещё раз через минуту",
+            TmdbError::Upstream(_) => "🌩️ TMDB сейчас недоступен, попробуйте позже",
+            TmdbError::Decode { .. } => "🧩 TMDB ответил в неожиданном формате, попробуйте позже",
+        }
+    }
+}
+
+pub type TmdbResult<T> = std::result::Result<T, TmdbError>;
+
+/// Decodes a response body, logging the offending field when it doesn't fit the model.
+fn decode<T: DeserializeOwned>(body: &str) -> TmdbResult<T> {
+    let deserializer = &mut serde_json::Deserializer::from_str(body);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        let pointer: String = e
+            .path()
+            .iter()
+            .map(|segment| match segment {
+                serde_path_to_error::Segment::Seq { index } => format!("/{index}"),
+                serde_path_to_error::Segment::Map { key } => format!("/{key}"),
+                serde_path_to_error::Segment::Enum { variant } => format!("/{variant}"),
+                serde_path_to_error::Segment::Unknown => String::from("/?"),
+            })
+            .collect();
+        let field = serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|payload| payload.pointer(&pointer).cloned());
+        match field {
+            Some(field) => tracing::error!(
+                "Failed to decode TMDB field {path} = {field}: {}",
+                e.inner()
+            ),
+            None => tracing::error!(
+                "Failed to decode TMDB field {path}, it is missing: {}",
+                e.inner()
+            ),
+        }
+        TmdbError::Decode {
+            path,
+            message: e.inner().to_string(),
+        }
+    })
+}
+
+/// Kinds of images the bot sends, each shown at a different size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    /// Poster of a card in a list or in search results.
+    ListPoster,
+    /// Poster of a title's detail card.
+    DetailPoster,
+    /// Episode still.
+    Still,
+    /// Poster tile of a rendered collage.
+    CollageTile,
+    /// Wide still of a title's detail card.
+    Backdrop,
+}
+impl ImageKind {
+    /// Width in pixels the image should have at least.
+    fn min_width(self) -> u32 {
+        match self {
+            ImageKind::ListPoster => 342,
+            ImageKind::DetailPoster => 500,
+            ImageKind::Still => 300,
+            ImageKind::CollageTile => 200,
+            ImageKind::Backdrop => 780,
+        }
+    }
+}
+
+/// Where images are served from and in which sizes, as in TMDB's `/configuration`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageConfig {
+    #[serde(rename = "secure_base_url")]
+    pub base_url: String,
+    pub poster_sizes: Vec<String>,
+    pub still_sizes: Vec<String>,
+    #[serde(default = "default_backdrop_sizes")]
+    pub backdrop_sizes: Vec<String>,
+}
+fn sizes(sizes: &[&str]) -> Vec<String> {
+    sizes.iter().map(|s| s.to_string()).collect()
+}
+fn default_backdrop_sizes() -> Vec<String> {
+    sizes(&["w300", "w780", "w1280", "original"])
+}
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            base_url: String::from("https://image.tmdb.org/t/p/"),
+            poster_sizes: sizes(&["w92", "w154", "w185", "w342", "w500", "w780", "original"]),
+            still_sizes: sizes(&["w92", "w185", "w300", "original"]),
+            backdrop_sizes: default_backdrop_sizes(),
+        }
+    }
+}
+impl ImageConfig {
+    /// The smallest size wide enough for the kind, the largest one if none is.
+    pub fn size(&self, kind: ImageKind) -> &str {
+        let sizes = match kind {
+            ImageKind::Still => &self.still_sizes,
+            ImageKind::Backdrop => &self.backdrop_sizes,
+            _ => &self.poster_sizes,
+        };
+        let width = |size: &str| size.strip_prefix('w').and_then(|w| w.parse::<u32>().ok());
+        sizes
+            .iter()
+            .find(|size| width(size).is_some_and(|w| w >= kind.min_width()))
+            .or(sizes.last())
+            .map_or("original", String::as_str)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfigurationResponse {
+    images: ImageConfig,
+}
+
+/// The title a path belongs to: `/movie/550/credits` belongs to `/movie/550`.
+fn resource(path: &str) -> String {
+    path.split('/').take(3).collect::<Vec<_>>().join("/")
+}
+
+/// Delay requested by the server, only the delta-seconds form is supported.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
 #[derive(Debug, Clone)]
 pub struct Tmdb {
     token: String,
     client: reqwest::Client,
     base_url: String,
-    image_base_url: String,
+    images: ImageConfig,
     language: String,
+    retry_policy: RetryPolicy,
+    in_flight: Arc<Semaphore>,
+    counters: Arc<Counters>,
+    cache: Arc<ResponseCache>,
 }
 impl Tmdb {
     #[instrument(name = "new tmdb client", skip(token))]
@@ -75,169 +342,331 @@ impl Tmdb {
             .user_agent(APP_USER_AGENT)
             .build()?;
         let base_url = String::from("https://api.themoviedb.org/3");
-        let image_base_url = String::from("https://image.tmdb.org/t/p/w300");
         let language = String::from("ru");
         Ok(Self {
             token,
             client,
             base_url,
-            image_base_url,
+            images: ImageConfig::default(),
             language,
+            retry_policy: RetryPolicy::default(),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+            counters: Arc::default(),
+            cache: Arc::new(ResponseCache::in_memory(DEFAULT_CACHE_CAPACITY)),
         })
     }
-    #[instrument(name = "get image", skip(self))]
-    pub async fn get_image(&self, url: &str) -> Result<InputFile> {
-        let uri = format!("{b}{url}", b = self.image_base_url);
-        tracing::info!("Getting image from {uri}");
-        let resp = self
-            .client
-            .get(&uri)
-            .bearer_auth(&self.token)
-            .send()
+    /// Points the client at another API server, e.g. a local mock.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+    /// Serves images from another server, the size is appended to `image_base_url`.
+    pub fn with_image_base_url(mut self, image_base_url: impl Into<String>) -> Self {
+        self.images.base_url = image_base_url.into();
+        self
+    }
+    pub fn with_language(mut self, language: impl Into<String>) -> Self {
+        self.language = language.into();
+        self
+    }
+    /// Takes the image server and the available sizes from TMDB's `/configuration`.
+    #[instrument(name = "bootstrap tmdb configuration", skip(self))]
+    pub async fn bootstrap_configuration(mut self) -> TmdbResult<Self> {
+        let configuration: ConfigurationResponse = self
+            .get_json("/configuration", &[], CONFIGURATION_TTL)
             .await?;
-        if !resp.status().is_success() {
-            return Err(anyhow!("Error: {st}", st = resp.status()));
+        tracing::info!("Using TMDB images from {}", configuration.images.base_url);
+        self.images = configuration.images;
+        Ok(self)
+    }
+    /// Size the image of the kind is requested in, e.g. `w342`.
+    pub fn image_size(&self, kind: ImageKind) -> &str {
+        self.images.size(kind)
+    }
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.in_flight = Arc::new(Semaphore::new(max_in_flight));
+        self
+    }
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Arc::new(cache);
+        self
+    }
+    /// Drops the cached details, credits and release dates of the film.
+    pub async fn invalidate_film(&self, id: i64) {
+        self.cache.invalidate(&format!("/movie/{id}")).await;
+    }
+    /// Drops the cached details, credits, seasons and episodes of the serial.
+    pub async fn invalidate_tv_show(&self, id: i64) {
+        self.cache.invalidate(&format!("/tv/{id}")).await;
+    }
+    pub fn metrics(&self) -> TmdbMetrics {
+        TmdbMetrics {
+            requests: self.counters.requests.load(Ordering::Relaxed),
+            retries: self.counters.retries.load(Ordering::Relaxed),
+            throttles: self.counters.throttles.load(Ordering::Relaxed),
+            cache_hits: self.cache.hits(),
+            cache_misses: self.cache.misses(),
         }
-        let bytes = resp.bytes().await?;
+    }
+    /// The shared request path: every call to TMDB is a GET, so throttled,
+    /// failed and timed out attempts are safe to repeat. Returns the body,
+    /// which is read while the request still holds its in-flight slot.
+    async fn get(&self, uri: &str, query: &[(&str, &str)]) -> TmdbResult<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let permit = self
+                .in_flight
+                .acquire()
+                .await
+                .map_err(|e| TmdbError::Upstream(e.to_string()))?;
+            self.counters.requests.fetch_add(1, Ordering::Relaxed);
+            let result = self
+                .client
+                .get(uri)
+                .bearer_auth(&self.token)
+                .query(query)
+                .send()
+                .await;
+            let (error, delay) = match result {
+                Ok(response) if response.status().is_success() => {
+                    return Ok(response.bytes().await?.to_vec());
+                }
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    self.counters.throttles.fetch_add(1, Ordering::Relaxed);
+                    let delay = retry_after(&response)
+                        .unwrap_or_else(|| self.retry_policy.backoff(attempt));
+                    (TmdbError::RateLimited, delay)
+                }
+                Ok(response) if response.status().is_server_error() => (
+                    TmdbError::from_status(response.status()),
+                    self.retry_policy.backoff(attempt),
+                ),
+                Ok(response) => return Err(TmdbError::from_status(response.status())),
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    (e.into(), self.retry_policy.backoff(attempt))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            drop(permit);
+            if attempt >= self.retry_policy.max_retries || delay > self.retry_policy.max_delay {
+                return Err(error);
+            }
+            attempt += 1;
+            self.counters.retries.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("Request to {uri} failed: {error}, retry {attempt} in {delay:?}");
+            tokio::time::sleep(delay).await;
+        }
+    }
+    /// Cached GET of an API endpoint, responses are kept for `ttl` per language.
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        ttl: Duration,
+    ) -> TmdbResult<T> {
+        let query_string: Vec<String> = query.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        let key = format!(
+            "{l}:{path}?{q}",
+            l = self.language,
+            q = query_string.join("&")
+        );
+        if let Some(body) = self.cache.get(&key).await {
+            match decode(&body) {
+                Ok(value) => return Ok(value),
+                Err(e) => tracing::warn!("Dropping undecodable cached response {key}: {e}"),
+            }
+        }
+        let uri = format!("{b}{path}", b = self.base_url);
+        let body = String::from_utf8_lossy(&self.get(&uri, query).await?).into_owned();
+        let value = decode(&body)?;
+        self.cache.put(key, resource(path), body, ttl).await;
+        Ok(value)
+    }
+    #[instrument(name = "get image", skip(self))]
+    pub async fn get_image(&self, path: &str, kind: ImageKind) -> TmdbResult<InputFile> {
+        let bytes = self.get_image_bytes(path, kind).await?;
         let f = InputFile::memory(bytes);
         Ok(f)
     }
+    /// Raw image data, for images rendered by the bot itself.
+    #[instrument(name = "get image bytes", skip(self))]
+    pub async fn get_image_bytes(&self, path: &str, kind: ImageKind) -> TmdbResult<Vec<u8>> {
+        let uri = format!(
+            "{b}{size}{path}",
+            b = self.images.base_url,
+            size = self.image_size(kind)
+        );
+        tracing::info!("Getting image from {uri}");
+        self.get(&uri, &[]).await
+    }
     #[instrument(name = "search film", skip(self))]
-    pub async fn search_film(&self, title: String, page: u8) -> Result<SearchResponse> {
-        let uri = format!("{b}/search/movie", b = self.base_url);
-        let response = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[
-                ("language", self.language.clone()),
-                ("query", title),
-                ("include_adult", String::from("true")),
-                ("page", format!("{page}")),
-            ])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(response)
+    pub async fn search_film(&self, title: String, page: u8) -> TmdbResult<SearchResponse> {
+        let page = page.to_string();
+        self.get_json(
+            "/search/movie",
+            &[
+                ("language", &self.language),
+                ("query", &title),
+                ("include_adult", "true"),
+                ("page", &page),
+            ],
+            SEARCH_TTL,
+        )
+        .await
     }
     #[instrument(name = "get films details", skip(self))]
-    pub async fn get_films_details(&self, id: i64) -> Result<FilmDetails> {
-        let uri = format!("{b}/movie/{id}", b = self.base_url);
-        tracing::info!("Getting film details from {u}", u = uri.to_string());
-        let result = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[("language", self.language.clone())])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(result)
+    pub async fn get_films_details(&self, id: i64) -> TmdbResult<FilmDetails> {
+        tracing::info!("Getting film {id} details");
+        self.get_json(
+            &format!("/movie/{id}"),
+            &[("language", &self.language)],
+            DETAILS_TTL,
+        )
+        .await
     }
     #[instrument(name = "get films credits", skip(self))]
-    pub async fn get_films_credits(&self, id: i64) -> Result<FilmCredits> {
-        let uri = format!("{b}/movie/{id}/credits", b = self.base_url);
-        tracing::info!("Getting film credits from {u}", u = uri.to_string());
-        let result = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[("language", self.language.clone())])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(result)
+    pub async fn get_films_credits(&self, id: i64) -> TmdbResult<FilmCredits> {
+        tracing::info!("Getting film {id} credits");
+        self.get_json(
+            &format!("/movie/{id}/credits"),
+            &[("language", &self.language)],
+            CREDITS_TTL,
+        )
+        .await
     }
     #[instrument(name = "get popular movies", skip(self))]
-    pub async fn get_popular_movies(&self, page: u8) -> Result<SearchResponse> {
-        let uri = format!("{b}/movie/popular", b = self.base_url);
-        let response = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[
-                ("language", self.language.clone()),
-                ("include_adult", String::from("true")),
-                ("page", format!("{page}")),
-            ])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(response)
+    pub async fn get_popular_movies(&self, page: u8) -> TmdbResult<SearchResponse> {
+        let page = page.to_string();
+        self.get_json(
+            "/movie/popular",
+            &[
+                ("language", &self.language),
+                ("include_adult", "true"),
+                ("page", &page),
+            ],
+            SEARCH_TTL,
+        )
+        .await
     }
     #[instrument(name = "search tv show", skip(self))]
-    pub async fn search_tvshow(&self, title: String, page: u8) -> Result<SearchTVResponse> {
-        let uri = format!("{b}/search/tv", b = self.base_url);
-        let response = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[
-                ("language", self.language.clone()),
-                ("query", title),
-                ("include_adult", String::from("true")),
-                ("page", format!("{page}")),
-            ])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(response)
+    pub async fn search_tvshow(&self, title: String, page: u8) -> TmdbResult<SearchTVResponse> {
+        let page = page.to_string();
+        self.get_json(
+            "/search/tv",
+            &[
+                ("language", &self.language),
+                ("query", &title),
+                ("include_adult", "true"),
+                ("page", &page),
+            ],
+            SEARCH_TTL,
+        )
+        .await
     }
     #[instrument(name = "get tv show details", skip(self))]
-    pub async fn get_tv_show_details(&self, id: i64) -> Result<TVShowDetails> {
-        let uri = format!("{b}/tv/{id}", b = self.base_url);
-        tracing::info!("Getting tv show details from {u}", u = uri.to_string());
-        let result = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[("language", self.language.clone())])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(result)
+    pub async fn get_tv_show_details(&self, id: i64) -> TmdbResult<TVShowDetails> {
+        tracing::info!("Getting tv show {id} details");
+        self.get_json(
+            &format!("/tv/{id}"),
+            &[
+                ("language", &self.language),
+                ("append_to_response", "external_ids"),
+            ],
+            DETAILS_TTL,
+        )
+        .await
     }
     #[instrument(name = "get tv show credits", skip(self))]
-    pub async fn get_tv_show_credits(&self, id: i64) -> Result<FilmCredits> {
-        let uri = format!("{b}/tv/{id}/credits", b = self.base_url);
-        tracing::info!("Getting tv show credits from {u}", u = uri.to_string());
-        let result = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[("language", self.language.clone())])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(result)
+    pub async fn get_tv_show_credits(&self, id: i64) -> TmdbResult<FilmCredits> {
+        tracing::info!("Getting tv show {id} credits");
+        self.get_json(
+            &format!("/tv/{id}/credits"),
+            &[("language", &self.language)],
+            CREDITS_TTL,
+        )
+        .await
     }
     #[instrument(name = "get popular tv shows", skip(self))]
-    pub async fn get_popular_tv_shows(&self, page: u8) -> Result<SearchTVResponse> {
-        let uri = format!("{b}/tv/popular", b = self.base_url);
-        let response = self
-            .client
-            .get(uri)
-            .bearer_auth(&self.token)
-            .query(&[
-                ("language", self.language.clone()),
-                ("include_adult", String::from("true")),
-                ("page", format!("{page}")),
-            ])
-            .send()
-            .await?
-            .json()
-            .await?;
-        Ok(response)
+    pub async fn get_popular_tv_shows(&self, page: u8) -> TmdbResult<SearchTVResponse> {
+        let page = page.to_string();
+        self.get_json(
+            "/tv/popular",
+            &[
+                ("language", &self.language),
+                ("include_adult", "true"),
+                ("page", &page),
+            ],
+            SEARCH_TTL,
+        )
+        .await
+    }
+    #[instrument(name = "get season details", skip(self))]
+    pub async fn get_season_details(
+        &self,
+        tv_id: i64,
+        season_number: i64,
+    ) -> TmdbResult<SeasonDetails> {
+        tracing::info!("Getting tv show {tv_id} season {season_number} details");
+        self.get_json(
+            &format!("/tv/{tv_id}/season/{season_number}"),
+            &[("language", &self.language)],
+            DETAILS_TTL,
+        )
+        .await
+    }
+    #[instrument(name = "get episode details", skip(self))]
+    pub async fn get_episode_details(
+        &self,
+        tv_id: i64,
+        season_number: i64,
+        episode_number: i64,
+    ) -> TmdbResult<Episode> {
+        tracing::info!("Getting tv show {tv_id} episode S{season_number}E{episode_number}");
+        self.get_json(
+            &format!("/tv/{tv_id}/season/{season_number}/episode/{episode_number}"),
+            &[("language", &self.language)],
+            DETAILS_TTL,
+        )
+        .await
+    }
+    /// Release dates of a film in every country.
+    #[instrument(name = "get film release dates", skip(self))]
+    pub async fn get_release_dates(&self, id: i64) -> TmdbResult<ReleaseDatesResponse> {
+        tracing::info!("Getting film {id} release dates");
+        self.get_json(
+            &format!("/movie/{id}/release_dates"),
+            &[],
+            RELEASE_DATES_TTL,
+        )
+        .await
+    }
+    /// Looks a title up by an id from another database, e.g. `imdb_id`.
+    #[instrument(name = "find by external id", skip(self))]
+    pub async fn find(&self, external_id: &str, external_source: &str) -> TmdbResult<FindResponse> {
+        self.get_json(
+            &format!("/find/{external_id}"),
+            &[
+                ("language", &self.language),
+                ("external_source", external_source),
+            ],
+            FIND_TTL,
+        )
+        .await
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FindResponse {
+    #[serde(default)]
+    pub movie_results: Vec<FilmOverview>,
+    #[serde(default)]
+    pub tv_results: Vec<TVShowOverview>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SearchResponse {
     pub page: i64,
@@ -310,7 +739,7 @@ impl Display for FilmDetails {
         if let Some(year) = self.release_date.clone().split("-").nth(0) {
             result = format!("{result} ({year})\n");
         } else {
-            result.push_str("\n");
+            result.push('\n');
         }
         result.push_str(&self.release_date);
 
@@ -321,7 +750,7 @@ impl Display for FilmDetails {
             .map(|g| g.name.clone())
             .collect::<Vec<_>>();
         for (i, genre) in genres.iter().enumerate() {
-            result.push_str(&genre);
+            result.push_str(genre);
             if i != genres.len() - 1 {
                 result.push_str(" | ");
             }
@@ -528,7 +957,7 @@ impl fmt::Display for Crew {
 }
 
 // Вспомогательная функция для экранирования HTML-символов
-fn escape_html(text: &str) -> String {
+pub fn escape_html(text: &str) -> String {
     text.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
@@ -587,10 +1016,10 @@ impl Display for TVShowOverview {
         write!(f, "\n⭐ <b>Рейтинг:</b> {} ({} голосов)", rating, votes)?;
         write!(f, "\n🌐 <b>Язык:</b> {}", self.original_language)?;
 
-        if let Some(original_country) = self.original_country.as_ref() {
-            if !original_country.is_empty() {
-                write!(f, "\n🇺🇳 <b>Страна:</b> {}", original_country.join(", "))?;
-            }
+        if let Some(original_country) = self.original_country.as_ref()
+            && !original_country.is_empty()
+        {
+            write!(f, "\n🇺🇳 <b>Страна:</b> {}", original_country.join(", "))?;
         }
 
         if !overview.is_empty() {
@@ -644,6 +1073,16 @@ pub struct TVShowDetails {
     pub type_field: String,
     pub vote_average: f64,
     pub vote_count: i64,
+    /// Present when requested with `append_to_response=external_ids`.
+    #[serde(default)]
+    pub external_ids: Option<ExternalIds>,
+}
+
+/// Ids of a title in other databases.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ExternalIds {
+    #[serde(default)]
+    pub imdb_id: Option<String>,
 }
 impl Display for TVShowDetails {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -868,3 +1307,240 @@ impl Display for Season {
         Ok(())
     }
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonDetails {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub overview: String,
+    pub air_date: Option<String>,
+    pub poster_path: Option<String>,
+    pub season_number: i64,
+    #[serde(default)]
+    pub episodes: Vec<Episode>,
+}
+
+impl Display for SeasonDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "📀 <b>{}</b>", escape_html(&self.name))?;
+        if let Some(air_date) = self.air_date.as_deref().filter(|d| !d.is_empty()) {
+            write!(f, "\n📅 <b>Дата выхода:</b> {air_date}")?;
+        }
+        write!(f, "\n🎞️ <b>Эпизодов:</b> {}", self.episodes.len())?;
+        if !self.overview.is_empty() {
+            write!(f, "\n\n{}", escape_html(&self.overview))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Episode {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub overview: String,
+    pub air_date: Option<String>,
+    pub episode_number: i64,
+    pub season_number: i64,
+    pub runtime: Option<i64>,
+    pub still_path: Option<String>,
+    #[serde(default)]
+    pub vote_average: f64,
+}
+impl Display for Episode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "🎞️ <b>S{:02}E{:02} · {}</b>",
+            self.season_number,
+            self.episode_number,
+            escape_html(&self.name)
+        )?;
+        if let Some(air_date) = self.air_date.as_deref().filter(|d| !d.is_empty()) {
+            write!(f, "\n📅 <b>Дата выхода:</b> {air_date}")?;
+        }
+        if let Some(runtime) = self.runtime.filter(|r| *r > 0) {
+            write!(f, "\n⏱️ <b>Продолжительность:</b> {runtime} мин")?;
+        }
+        if self.vote_average > 0.0 {
+            write!(f, "\n⭐ <b>Рейтинг:</b> {:.1}", self.vote_average)?;
+        }
+        if !self.overview.is_empty() {
+            write!(f, "\n\n{}", escape_html(&self.overview))?;
+        }
+        Ok(())
+    }
+}
+impl Episode {
+    /// Episodes without a date or dated in the future have not aired yet.
+    pub fn is_aired(&self, today: &str) -> bool {
+        self.air_date
+            .as_deref()
+            .is_some_and(|date| !date.is_empty() && date <= today)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReleaseDatesResponse {
+    pub id: i64,
+    #[serde(default)]
+    pub results: Vec<CountryReleaseDates>,
+}
+impl ReleaseDatesResponse {
+    /// Earliest `YYYY-MM-DD` date of the given release types in the region.
+    pub fn earliest(&self, region: &str, types: &[i64]) -> Option<String> {
+        self.results
+            .iter()
+            .filter(|c| c.iso_3166_1.eq_ignore_ascii_case(region))
+            .flat_map(|c| &c.release_dates)
+            .filter(|d| types.contains(&d.release_type))
+            .filter_map(|d| d.release_date.get(..10))
+            .min()
+            .map(str::to_string)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountryReleaseDates {
+    pub iso_3166_1: String,
+    #[serde(default)]
+    pub release_dates: Vec<ReleaseDate>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReleaseDate {
+    #[serde(default)]
+    pub certification: String,
+    #[serde(default)]
+    pub note: String,
+    pub release_date: String,
+    /// 1 premiere, 2 limited theatrical, 3 theatrical, 4 digital, 5 physical, 6 TV.
+    #[serde(rename = "type")]
+    pub release_type: i64,
+}
+
+/// TMDB release type codes.
+pub const THEATRICAL_LIMITED_RELEASE: i64 = 2;
+pub const THEATRICAL_RELEASE: i64 = 3;
+pub const DIGITAL_RELEASE: i64 = 4;
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::app::mock_server::{MockResponse, MockServer};
+
+    /// Answers with `failures` in turn, then with an empty `/find` result.
+    async fn failing_server(failures: Vec<MockResponse>) -> MockServer {
+        let calls = AtomicUsize::new(0);
+        MockServer::start(move |_| {
+            let call = calls.fetch_add(1, Ordering::Relaxed);
+            failures
+                .get(call)
+                .cloned()
+                .unwrap_or_else(|| MockResponse::json("{}"))
+        })
+        .await
+    }
+
+    fn client(server: &MockServer, max_retries: u32) -> Tmdb {
+        Tmdb::new("token".to_string())
+            .unwrap()
+            .with_base_url(server.url())
+            .with_retry_policy(RetryPolicy {
+                max_retries,
+                base_delay: Duration::from_millis(20),
+                max_delay: Duration::from_secs(2),
+            })
+    }
+
+    #[test]
+    fn image_sizes_fit_the_kind() {
+        let images = ImageConfig::default();
+        assert_eq!(images.size(ImageKind::ListPoster), "w342");
+        assert_eq!(images.size(ImageKind::Still), "w300");
+        assert_eq!(images.size(ImageKind::Backdrop), "w780");
+        let configuration: ConfigurationResponse = serde_json::from_str(
+            r#"{"images": {"secure_base_url": "https://img/", "poster_sizes": ["w500"],
+                "still_sizes": ["w300"], "backdrop_sizes": ["w300", "w1280"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(configuration.images.size(ImageKind::Backdrop), "w1280");
+    }
+
+    #[tokio::test]
+    async fn throttled_request_waits_for_retry_after() {
+        let server =
+            failing_server(vec![MockResponse::status(429).header("Retry-After", "1")]).await;
+        let tmdb = client(&server, 3);
+
+        let started = Instant::now();
+        tmdb.find("tt0137523", "imdb_id").await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.requests().len(), 2);
+        let metrics = tmdb.metrics();
+        assert_eq!(
+            (metrics.requests, metrics.retries, metrics.throttles),
+            (2, 1, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_after_beyond_max_delay_fails_at_once() {
+        let server =
+            failing_server(vec![MockResponse::status(429).header("Retry-After", "60")]).await;
+        let tmdb = client(&server, 3);
+
+        let result = tmdb.find("tt0137523", "imdb_id").await;
+        assert!(matches!(result, Err(TmdbError::RateLimited)));
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(tmdb.metrics().retries, 0);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_with_backoff() {
+        let server =
+            failing_server(vec![MockResponse::status(502), MockResponse::status(503)]).await;
+        let tmdb = client(&server, 3);
+
+        let started = Instant::now();
+        tmdb.find("tt0137523", "imdb_id").await.unwrap();
+        // At least half of 20ms, then half of 40ms.
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(server.requests().len(), 3);
+        let metrics = tmdb.metrics();
+        assert_eq!(
+            (metrics.requests, metrics.retries, metrics.throttles),
+            (3, 2, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let server = MockServer::start(|_| MockResponse::status(500)).await;
+        let tmdb = client(&server, 2);
+
+        let result = tmdb.find("tt0137523", "imdb_id").await;
+        assert!(matches!(result, Err(TmdbError::Upstream(_))));
+        assert_eq!(server.requests().len(), 3);
+        let metrics = tmdb.metrics();
+        assert_eq!((metrics.requests, metrics.retries), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = MockServer::start(|_| MockResponse::status(404)).await;
+        let tmdb = client(&server, 3);
+
+        let result = tmdb.find("tt0137523", "imdb_id").await;
+        assert!(matches!(result, Err(TmdbError::NotFound)));
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(tmdb.metrics().retries, 0);
+    }
+}